@@ -1,7 +1,12 @@
 fn main() {
+    // The Go SQIP library is only linked when it's used instead of the native generator
+    if std::env::var_os("CARGO_FEATURE_SQIP_FFI").is_none() {
+        return;
+    }
+
     let path = "./build";
     let lib = "sqip";
 
     println!("cargo:rustc-link-search=native={}", path);
     println!("cargo:rustc-link-lib=static={}", lib);
-}
\ No newline at end of file
+}
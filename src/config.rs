@@ -0,0 +1,754 @@
+use crate::data::exif::ExifField;
+use crate::error::AppError;
+use crate::layout::Layout;
+use crate::privacy::PrivacyPolicy;
+use crate::upload::conflict::ConflictPolicy;
+use crate::upload::constants::{DEFAULT_BUCKET_NAME, DEFAULT_REGION, DEFAULT_WEB_PREFIX};
+use crate::upload::template::KeyTemplate;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::read_to_string;
+use std::iter::once;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The name of the configuration file looked for in the Hugo site root
+pub const SITE_CONFIG_FILE: &str = "rith.toml";
+
+/// Files, other than [`SITE_CONFIG_FILE`], which mark the root of a Hugo site
+const HUGO_CONFIG_FILES: &[&str] = &[
+    "hugo.toml",
+    "hugo.yaml",
+    "hugo.json",
+    "config.toml",
+    "config.yaml",
+    "config.json",
+];
+
+/// The prefix for environment variables which override configuration values. e.g. `RITH_BUCKET`
+pub const ENV_PREFIX: &str = "RITH_";
+
+/// Every configuration key, in the order they are shown
+const KEYS: &[&str] = &[
+    "backend",
+    "bucket",
+    "region",
+    "local_directory",
+    "web_prefix",
+    "key_prefix",
+    "key_template",
+    "upload_parallelism",
+    "upload_retries",
+    "on_conflict",
+    "hashed_keys",
+    "layout",
+    "layouts",
+    "exif_fields",
+    "privacy",
+];
+
+/// Where a configuration value was obtained from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Environment(String),
+    CommandLine,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "{}", path.to_string_lossy()),
+            ConfigSource::Environment(variable) => write!(f, "environment variable {variable}"),
+            ConfigSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// Where uploaded images are stored
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// An S3 (compatible) bucket
+    S3,
+    /// A directory on the local filesystem
+    Local,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "s3" => Ok(Backend::S3),
+            "local" => Ok(Backend::Local),
+            _ => Err(format!(
+                "{s} is not a storage backend. Expected s3 or local"
+            )),
+        }
+    }
+}
+
+/// Command line flags which override any configuration file or environment variable
+#[derive(Debug, Default, Clone, StructOpt)]
+pub struct ConfigOverrides {
+    /// The site configuration file to use instead of `rith.toml` in the Hugo site root
+    #[structopt(long = "config", parse(from_os_str))]
+    pub config_file: Option<PathBuf>,
+
+    /// Where to upload images to: s3 or local
+    #[structopt(long)]
+    pub backend: Option<Backend>,
+
+    /// The bucket to upload images to
+    #[structopt(long)]
+    pub bucket: Option<String>,
+
+    /// The region the bucket is in
+    #[structopt(long)]
+    pub region: Option<String>,
+
+    /// The directory to copy images to when using the local backend
+    #[structopt(long = "local-directory", parse(from_os_str))]
+    pub local_directory: Option<PathBuf>,
+
+    /// The URL prefix uploaded files are publicly served from
+    #[structopt(long = "web-prefix")]
+    pub web_prefix: Option<String>,
+
+    /// The prefix all uploaded keys are placed under
+    #[structopt(long = "key-prefix")]
+    pub key_prefix: Option<String>,
+
+    /// The layout of uploaded keys. e.g. `{prefix}/{year}/{month_name}/{directory}/{file}`.
+    /// Tokens: prefix, year, month, month_name, directory, name, stem, file, variant, width, format, hash and exif_date
+    #[structopt(long = "key-template")]
+    pub key_template: Option<KeyTemplate>,
+
+    /// The maximum number of files to upload at once
+    #[structopt(long = "upload-parallelism")]
+    pub upload_parallelism: Option<usize>,
+
+    /// How many times to retry an upload that failed with a transient error
+    #[structopt(long = "upload-retries")]
+    pub upload_retries: Option<u32>,
+
+    /// What to do when a different file already exists where an image is being uploaded to: fail, rename or overwrite
+    #[structopt(long = "on-conflict")]
+    pub on_conflict: Option<ConflictPolicy>,
+
+    /// What metadata is removed from uploaded files: keep-all, strip-gps or strip-all-but-copyright
+    #[structopt(long)]
+    pub privacy: Option<PrivacyPolicy>,
+
+    /// Embed a hash of each file's contents in its key and upload it as immutable
+    #[structopt(long = "hashed-keys", conflicts_with = "no-hashed-keys")]
+    pub hashed_keys: bool,
+
+    /// Don't embed a hash of each file's contents in its key, even if `hashed_keys = true` is configured
+    #[structopt(long = "no-hashed-keys")]
+    pub no_hashed_keys: bool,
+
+    /// The layout from `layouts` used for images no layout lists the file name of
+    #[structopt(long)]
+    pub layout: Option<String>,
+}
+
+/// A partial set of configuration values from a single source
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigLayer {
+    backend: Option<Backend>,
+    bucket: Option<String>,
+    region: Option<String>,
+    local_directory: Option<PathBuf>,
+    web_prefix: Option<String>,
+    key_prefix: Option<String>,
+    key_template: Option<KeyTemplate>,
+    upload_parallelism: Option<usize>,
+    upload_retries: Option<u32>,
+    on_conflict: Option<ConflictPolicy>,
+    hashed_keys: Option<bool>,
+    layout: Option<String>,
+    layouts: Option<BTreeMap<String, Layout>>,
+    exif_fields: Option<Vec<ExifField>>,
+    privacy: Option<PrivacyPolicy>,
+}
+
+impl ConfigLayer {
+    fn from_file(path: &Path) -> Result<Self, AppError> {
+        let contents = read_to_string(path)?;
+        toml::from_str(&contents).map_err(|source| AppError::ConfigParse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Reads `RITH_<KEY>` for every key from `environment`, returning a layer per variable set.
+    /// Values are used verbatim as strings, except for the keys which are numbers, booleans, lists or tables.
+    fn from_environment(
+        environment: impl Fn(&str) -> Option<String>,
+    ) -> Result<Vec<(Self, ConfigSource)>, AppError> {
+        let mut layers = Vec::new();
+        for key in KEYS {
+            let variable = [ENV_PREFIX, &key.to_uppercase()].join("");
+            let Some(value) = environment(&variable) else {
+                continue;
+            };
+            let mut table = toml::Table::new();
+            table.insert((*key).to_owned(), environment_value(key, value));
+            let layer =
+                toml::Value::Table(table)
+                    .try_into()
+                    .map_err(|source| AppError::ConfigParse {
+                        path: PathBuf::from(&variable),
+                        source,
+                    })?;
+            layers.push((layer, ConfigSource::Environment(variable)));
+        }
+        Ok(layers)
+    }
+}
+
+/// The value of the environment variable for `key`.
+/// Values which aren't valid for the key are left as strings so they're reported when the layer is read.
+fn environment_value(key: &str, value: String) -> toml::Value {
+    match key {
+        "upload_parallelism" | "upload_retries" => value
+            .trim()
+            .parse()
+            .map_or(toml::Value::String(value), toml::Value::Integer),
+        "hashed_keys" => value
+            .trim()
+            .parse()
+            .map_or(toml::Value::String(value), toml::Value::Boolean),
+        // e.g. `camera_model,lens`
+        "exif_fields" => toml::Value::Array(
+            value
+                .split(',')
+                .map(|field| toml::Value::String(field.trim().to_owned()))
+                .collect(),
+        ),
+        // An inline table. e.g. `{ content = { breakpoints = [{ width = "720px" }] } }`
+        "layouts" => format!("value = {value}")
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut t| t.remove("value"))
+            .unwrap_or(toml::Value::String(value)),
+        _ => toml::Value::String(value),
+    }
+}
+
+impl From<&ConfigOverrides> for ConfigLayer {
+    fn from(overrides: &ConfigOverrides) -> Self {
+        Self {
+            backend: overrides.backend,
+            bucket: overrides.bucket.clone(),
+            region: overrides.region.clone(),
+            local_directory: overrides.local_directory.clone(),
+            web_prefix: overrides.web_prefix.clone(),
+            key_prefix: overrides.key_prefix.clone(),
+            key_template: overrides.key_template.clone(),
+            upload_parallelism: overrides.upload_parallelism,
+            upload_retries: overrides.upload_retries,
+            on_conflict: overrides.on_conflict,
+            hashed_keys: match (overrides.hashed_keys, overrides.no_hashed_keys) {
+                (true, _) => Some(true),
+                (false, true) => Some(false),
+                (false, false) => None,
+            },
+            layout: overrides.layout.clone(),
+            layouts: None,
+            exif_fields: None,
+            privacy: overrides.privacy,
+        }
+    }
+}
+
+/// The merged configuration used for a run
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    /// Where images are uploaded to
+    pub backend: Backend,
+    /// The bucket images are uploaded to
+    pub bucket: String,
+    /// The region of the bucket
+    pub region: String,
+    /// The directory images are copied to when using [`Backend::Local`]
+    pub local_directory: PathBuf,
+    /// The URL prefix uploaded keys are served from. e.g. `https://files.arranfrance.com/`
+    pub web_prefix: String,
+    /// The prefix every uploaded key starts with. e.g. `images`
+    pub key_prefix: String,
+    /// The layout of uploaded keys, rendered for every uploaded file
+    pub key_template: KeyTemplate,
+    /// The maximum number of files uploaded at once
+    pub upload_parallelism: usize,
+    /// How many times an upload that failed with a transient error is retried
+    pub upload_retries: u32,
+    /// What happens when a different object already exists at a key being uploaded to
+    pub on_conflict: ConflictPolicy,
+    /// Whether keys embed a short hash of the file's contents, allowing objects to be cached forever
+    pub hashed_keys: bool,
+    /// The name of the layout used for images no layout lists the file name of
+    pub layout: Option<String>,
+    /// Where images sit on a page, by name. Layouts from every source are combined.
+    pub layouts: BTreeMap<String, Layout>,
+    /// The EXIF fields written to the data file
+    pub exif_fields: Vec<ExifField>,
+    /// What metadata is removed from every uploaded file, including the copy of the original
+    pub privacy: PrivacyPolicy,
+    #[serde(skip)]
+    origins: BTreeMap<&'static str, ConfigSource>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backend: Backend::S3,
+            bucket: DEFAULT_BUCKET_NAME.to_owned(),
+            region: DEFAULT_REGION.to_owned(),
+            local_directory: PathBuf::from("static"),
+            web_prefix: DEFAULT_WEB_PREFIX.to_owned(),
+            key_prefix: "images".to_owned(),
+            key_template: KeyTemplate::default(),
+            upload_parallelism: 8,
+            upload_retries: 3,
+            on_conflict: ConflictPolicy::Fail,
+            hashed_keys: false,
+            layout: None,
+            layouts: BTreeMap::new(),
+            exif_fields: ExifField::DEFAULT.to_vec(),
+            privacy: PrivacyPolicy::StripGps,
+            origins: KEYS.iter().map(|k| (*k, ConfigSource::Default)).collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration, layering (lowest to highest precedence) the defaults, the user configuration file,
+    /// the site configuration file, `RITH_*` environment variables and finally command line flags.
+    /// The site configuration file is looked for in the root of the Hugo site the current directory is in.
+    pub fn load(overrides: &ConfigOverrides) -> Result<Self, AppError> {
+        Self::load_layers(
+            overrides,
+            user_config_path(),
+            &std::env::current_dir()?,
+            |variable| std::env::var(variable).ok(),
+        )
+    }
+
+    fn load_layers(
+        overrides: &ConfigOverrides,
+        user_path: Option<PathBuf>,
+        current_directory: &Path,
+        environment: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, AppError> {
+        let mut config = Self::default();
+
+        if let Some(path) = user_path.filter(|p| p.exists()) {
+            debug!("Loading user configuration from {}", path.to_string_lossy());
+            config.merge(ConfigLayer::from_file(&path)?, &ConfigSource::File(path));
+        }
+
+        let site_path = overrides
+            .config_file
+            .clone()
+            .unwrap_or_else(|| site_config_path(current_directory));
+        if site_path.exists() {
+            debug!(
+                "Loading site configuration from {}",
+                site_path.to_string_lossy()
+            );
+            config.merge(
+                ConfigLayer::from_file(&site_path)?,
+                &ConfigSource::File(site_path),
+            );
+        } else if overrides.config_file.is_some() {
+            return Err(AppError::ConfigMissing(site_path));
+        }
+
+        for (layer, source) in ConfigLayer::from_environment(environment)? {
+            config.merge(layer, &source);
+        }
+
+        config.merge(ConfigLayer::from(overrides), &ConfigSource::CommandLine);
+        if let Some(name) = &config.layout {
+            config.layout(name)?;
+        }
+        Ok(config)
+    }
+
+    fn merge(&mut self, layer: ConfigLayer, source: &ConfigSource) {
+        let origins = &mut self.origins;
+        apply(&mut self.backend, layer.backend, "backend", source, origins);
+        apply(&mut self.bucket, layer.bucket, "bucket", source, origins);
+        apply(&mut self.region, layer.region, "region", source, origins);
+        apply(
+            &mut self.local_directory,
+            layer.local_directory,
+            "local_directory",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.web_prefix,
+            layer.web_prefix,
+            "web_prefix",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.key_prefix,
+            layer.key_prefix,
+            "key_prefix",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.key_template,
+            layer.key_template,
+            "key_template",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.upload_parallelism,
+            layer.upload_parallelism,
+            "upload_parallelism",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.upload_retries,
+            layer.upload_retries,
+            "upload_retries",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.on_conflict,
+            layer.on_conflict,
+            "on_conflict",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.hashed_keys,
+            layer.hashed_keys,
+            "hashed_keys",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.layout,
+            layer.layout.map(Some),
+            "layout",
+            source,
+            origins,
+        );
+        apply(
+            &mut self.exif_fields,
+            layer.exif_fields,
+            "exif_fields",
+            source,
+            origins,
+        );
+        apply(&mut self.privacy, layer.privacy, "privacy", source, origins);
+        if let Some(layouts) = layer.layouts {
+            self.layouts.extend(layouts);
+            origins.insert("layouts", source.clone());
+        }
+    }
+
+    /// The layout images in the file called `file_name` are rendered with.
+    /// A layout listing the file name is preferred over the configured `layout`. When several do, the one with the
+    /// most specific matching pattern (the most characters other than `*`) is used, and a tie is an error.
+    pub fn layout_for(&self, file_name: &str) -> Result<Option<&Layout>, AppError> {
+        let mut listing = self
+            .layouts
+            .iter()
+            .filter_map(|(name, layout)| Some((layout.specificity(file_name)?, name, layout)))
+            .collect::<Vec<_>>();
+        listing.sort_by_key(|(specificity, _, _)| std::cmp::Reverse(*specificity));
+        match listing.as_slice() {
+            [(most, first, _), (next, second, _), ..] if most == next => {
+                Err(AppError::AmbiguousLayout {
+                    file: file_name.to_owned(),
+                    first: (*first).clone(),
+                    second: (*second).clone(),
+                })
+            }
+            [(_, _, layout), ..] => Ok(Some(layout)),
+            [] => self
+                .layout
+                .as_ref()
+                .map(|name| self.layout(name))
+                .transpose(),
+        }
+    }
+
+    /// The layout called `name`
+    pub fn layout(&self, name: &str) -> Result<&Layout, AppError> {
+        self.layouts
+            .get(name)
+            .ok_or_else(|| AppError::UnknownLayout(name.to_owned()))
+    }
+
+    /// Where the value for `key` came from
+    pub fn source_of(&self, key: &str) -> Option<&ConfigSource> {
+        self.origins.get(key)
+    }
+
+    /// A TOML formatted view of the merged configuration annotated with where each value came from
+    pub fn show(&self) -> Result<String, AppError> {
+        let table = toml::Table::try_from(self).map_err(anyhow::Error::from)?;
+        Ok(KEYS
+            .iter()
+            .filter_map(|key| table.get(*key).map(|value| (key, value)))
+            .map(|(key, value)| {
+                let source = self.source_of(key).unwrap_or(&ConfigSource::Default);
+                format!("{key} = {value} # {source}")
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+fn apply<T>(
+    target: &mut T,
+    value: Option<T>,
+    key: &'static str,
+    source: &ConfigSource,
+    origins: &mut BTreeMap<&'static str, ConfigSource>,
+) {
+    if let Some(value) = value {
+        *target = value;
+        origins.insert(key, source.clone());
+    }
+}
+
+/// `rith.toml` in the root of the Hugo site `directory` is in: the closest directory, from `directory` upwards,
+/// with `rith.toml` or a Hugo configuration file in it. Outside of a site it's `rith.toml` in `directory`.
+fn site_config_path(directory: &Path) -> PathBuf {
+    directory
+        .ancestors()
+        .find(|ancestor| {
+            once(SITE_CONFIG_FILE)
+                .chain(HUGO_CONFIG_FILES.iter().copied())
+                .any(|file| ancestor.join(file).is_file())
+        })
+        .unwrap_or(directory)
+        .join(SITE_CONFIG_FILE)
+}
+
+/// `$XDG_CONFIG_HOME/rith/config.toml`, falling back to `~/.config/rith/config.toml`
+fn user_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("rith").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs::{create_dir_all, write};
+
+    fn environment(variables: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let variables = variables
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect::<HashMap<_, _>>();
+        move |name| variables.get(name).cloned()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let site = tempfile::tempdir().unwrap();
+        let user_path = site.path().join("user.toml");
+        write(
+            &user_path,
+            "bucket = \"user\"\nregion = \"user\"\nkey_prefix = \"user\"\nupload_retries = 1\n",
+        )
+        .unwrap();
+        let site_path = site.path().join(SITE_CONFIG_FILE);
+        write(
+            &site_path,
+            "bucket = \"site\"\nregion = \"site\"\nupload_retries = 2\n",
+        )
+        .unwrap();
+        let overrides = ConfigOverrides {
+            bucket: Some("command line".to_owned()),
+            ..ConfigOverrides::default()
+        };
+
+        let config = Config::load_layers(
+            &overrides,
+            Some(user_path.clone()),
+            site.path(),
+            environment(&[("RITH_BUCKET", "environment"), ("RITH_UPLOAD_RETRIES", "5")]),
+        )
+        .unwrap();
+
+        assert_eq!(config.bucket, "command line");
+        assert_eq!(config.source_of("bucket"), Some(&ConfigSource::CommandLine));
+        assert_eq!(config.upload_retries, 5);
+        assert_eq!(
+            config.source_of("upload_retries"),
+            Some(&ConfigSource::Environment("RITH_UPLOAD_RETRIES".to_owned()))
+        );
+        assert_eq!(config.region, "site");
+        assert_eq!(
+            config.source_of("region"),
+            Some(&ConfigSource::File(site_path))
+        );
+        assert_eq!(config.key_prefix, "user");
+        assert_eq!(
+            config.source_of("key_prefix"),
+            Some(&ConfigSource::File(user_path))
+        );
+        assert_eq!(config.upload_parallelism, 8);
+        assert_eq!(
+            config.source_of("upload_parallelism"),
+            Some(&ConfigSource::Default)
+        );
+    }
+
+    #[test]
+    fn test_hashed_keys_can_be_turned_off() {
+        let site = tempfile::tempdir().unwrap();
+        write(site.path().join(SITE_CONFIG_FILE), "hashed_keys = true\n").unwrap();
+        let load = |args: &[&str]| {
+            let overrides =
+                ConfigOverrides::from_iter_safe(once("rith").chain(args.iter().copied()))?;
+            Ok::<_, anyhow::Error>(
+                Config::load_layers(&overrides, None, site.path(), environment(&[]))?.hashed_keys,
+            )
+        };
+        assert!(load(&[]).unwrap());
+        assert!(load(&["--hashed-keys"]).unwrap());
+        assert!(!load(&["--no-hashed-keys"]).unwrap());
+        assert!(load(&["--hashed-keys", "--no-hashed-keys"]).is_err());
+    }
+
+    #[test]
+    fn test_environment_strings_are_verbatim() {
+        let site = tempfile::tempdir().unwrap();
+        let config = Config::load_layers(
+            &ConfigOverrides::default(),
+            None,
+            site.path(),
+            environment(&[
+                ("RITH_BUCKET", "1234"),
+                ("RITH_KEY_PREFIX", "true"),
+                (
+                    "RITH_WEB_PREFIX",
+                    "https://example.com/\"quoted\" # not a comment",
+                ),
+                ("RITH_HASHED_KEYS", "true"),
+                ("RITH_UPLOAD_PARALLELISM", " 4 "),
+                ("RITH_EXIF_FIELDS", "camera_model, lens"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.bucket, "1234");
+        assert_eq!(config.key_prefix, "true");
+        assert_eq!(
+            config.web_prefix,
+            "https://example.com/\"quoted\" # not a comment"
+        );
+        assert!(config.hashed_keys);
+        assert_eq!(config.upload_parallelism, 4);
+        assert_eq!(
+            config.exif_fields,
+            [ExifField::CameraModel, ExifField::Lens]
+        );
+
+        let invalid = Config::load_layers(
+            &ConfigOverrides::default(),
+            None,
+            site.path(),
+            environment(&[("RITH_UPLOAD_RETRIES", "many")]),
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_layout_for() {
+        let site = tempfile::tempdir().unwrap();
+        let load = |contents: &str| {
+            write(site.path().join(SITE_CONFIG_FILE), contents).unwrap();
+            Config::load_layers(
+                &ConfigOverrides::default(),
+                None,
+                site.path(),
+                environment(&[]),
+            )
+        };
+        let config = load(
+            r#"
+layout = "content"
+[layouts.content]
+breakpoints = [{ width = "720px" }]
+[layouts.aside]
+breakpoints = [{ width = "100vw" }]
+files = ["*.png"]
+[layouts.sidebar]
+breakpoints = [{ width = "300px" }]
+files = ["*-sidebar.*"]
+[layouts.banner]
+breakpoints = [{ width = "100vw" }]
+files = ["hero-*"]
+[layouts.panorama]
+breakpoints = [{ width = "100vw" }]
+files = ["*.jpeg"]
+"#,
+        )
+        .unwrap();
+        let layout_for = |file_name| config.layout_for(file_name).unwrap().unwrap();
+
+        assert_eq!(layout_for("cat.jpg"), &config.layouts["content"]);
+        assert_eq!(layout_for("cat.png"), &config.layouts["aside"]);
+        // `*-sidebar.*` is more specific than `*.png`, although aside sorts first
+        assert_eq!(layout_for("cat-sidebar.png"), &config.layouts["sidebar"]);
+        assert!(matches!(
+            config.layout_for("hero-1.jpeg"),
+            Err(AppError::AmbiguousLayout { .. })
+        ));
+
+        // The configured layout must exist
+        assert!(matches!(
+            load("layout = \"missing\"\n"),
+            Err(AppError::UnknownLayout(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_site_config_is_found_in_the_site_root() {
+        let site = tempfile::tempdir().unwrap();
+        write(site.path().join("hugo.toml"), "").unwrap();
+        write(site.path().join(SITE_CONFIG_FILE), "bucket = \"site\"\n").unwrap();
+        let posts = site.path().join("content").join("posts");
+        create_dir_all(&posts).unwrap();
+
+        let config =
+            Config::load_layers(&ConfigOverrides::default(), None, &posts, environment(&[]))
+                .unwrap();
+        assert_eq!(config.bucket, "site");
+
+        let outside = tempfile::tempdir().unwrap();
+        assert_eq!(
+            site_config_path(outside.path()),
+            outside.path().join(SITE_CONFIG_FILE)
+        );
+    }
+}
@@ -1,3 +1,6 @@
+/// Where the data file is written when `--output` isn't given
+pub const DEFAULT_DATA_FILE: &str = "./data/images.json";
+
 pub const MONTH_NAMES: [&str; 12] = [
     "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
 ];
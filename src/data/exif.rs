@@ -1,34 +1,628 @@
+use chrono::{NaiveDate, NaiveDateTime};
 use peck_exif::exif::Exif as PeckExif;
 use serde::{Deserialize, Serialize};
 use std::convert::From;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+use super::metadata::jpeg_segments;
+
+/// The TIFF tag holding a photo's orientation
+pub(crate) const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Every tag read with exiftool. Tags are known by their name, e.g. `Model`, or their description
+/// without spaces, e.g. `CameraModelName`, depending on how exiftool prints them, so both are listed.
+pub const EXIF_TAGS: &[&str] = &[
+    "Make",
+    "Model",
+    "CameraModelName",
+    "LensID",
+    "LensModel",
+    "LensType",
+    "FocalLength",
+    "FNumber",
+    "Aperture",
+    "ExposureTime",
+    "ShutterSpeed",
+    "ISO",
+    "ExposureCompensation",
+    "Megapixels",
+    "DateTimeOriginal",
+    "Date/TimeOriginal",
+    "Orientation",
+    "GPSLatitude",
+    "GPSLatitudeRef",
+    "GPSLongitude",
+    "GPSLongitudeRef",
+    "GPSAltitude",
+];
+
+/// The camera settings and capture details of a photo
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Exif {
-    pub shutter_speed: Option<String>,
-    pub aperture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub camera_make: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
     pub camera_model: Option<String>,
-    pub focal_length: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
     pub lens: Option<String>,
-    pub megapixels: Option<String>,
-    pub iso: Option<String>,
-    pub exposure_compensation: Option<String>,
+
+    /// In millimetres
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub focal_length: Option<f64>,
+
+    /// The f-number. e.g. `2.8`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub aperture: Option<f64>,
+
+    /// In seconds. e.g. `1/250`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub exposure_time: Option<Rational>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub iso: Option<u32>,
+
+    /// In stops. e.g. `-0.67`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub exposure_compensation: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub megapixels: Option<f64>,
+
+    /// When the photo was taken, in the camera's local time. e.g. `2024-03-09T12:00:00`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub date_time_original: Option<NaiveDateTime>,
+
+    /// How the stored pixels must be rotated and flipped to display the photo upright
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub orientation: Option<Orientation>,
+
+    /// Where the photo was taken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub gps: Option<Gps>,
+}
+
+impl Exif {
+    /// The day the photo was taken, if known
+    pub fn captured_on(&self) -> Option<NaiveDate> {
+        self.date_time_original.map(|date_time| date_time.date())
+    }
+
+    /// Parses the values exiftool prints for [`EXIF_TAGS`], given the value of each tag by name
+    pub fn from_tags<'a>(tag: impl Fn(&str) -> Option<&'a str>) -> Self {
+        let first = |names: &[&str]| names.iter().find_map(|name| tag(name));
+        let latitude = tag("GPSLatitude").and_then(|v| coordinate(v, tag("GPSLatitudeRef")));
+        let longitude = tag("GPSLongitude").and_then(|v| coordinate(v, tag("GPSLongitudeRef")));
+        Exif {
+            camera_make: tag("Make").map(str::to_owned),
+            camera_model: first(&["Model", "CameraModelName"]).map(str::to_owned),
+            lens: first(&["LensID", "LensModel", "LensType"]).map(str::to_owned),
+            focal_length: tag("FocalLength").and_then(leading_number),
+            aperture: first(&["FNumber", "Aperture"]).and_then(leading_number),
+            exposure_time: first(&["ExposureTime", "ShutterSpeed"]).and_then(|v| v.parse().ok()),
+            iso: tag("ISO").and_then(|v| v.trim().parse().ok()),
+            exposure_compensation: tag("ExposureCompensation")
+                .and_then(fraction)
+                .map(|stops| (stops * 100.0).round() / 100.0),
+            megapixels: tag("Megapixels").and_then(leading_number),
+            date_time_original: first(&["DateTimeOriginal", "Date/TimeOriginal"]).and_then(|v| {
+                NaiveDateTime::parse_from_str(v.get(..19)?, "%Y:%m:%d %H:%M:%S").ok()
+            }),
+            orientation: tag("Orientation").and_then(|v| v.parse().ok()),
+            gps: latitude.zip(longitude).map(|(latitude, longitude)| Gps {
+                latitude,
+                longitude,
+                altitude: tag("GPSAltitude").and_then(|v| {
+                    let altitude = leading_number(v)?;
+                    Some(if v.contains("Below") {
+                        -altitude
+                    } else {
+                        altitude
+                    })
+                }),
+            }),
+        }
+    }
+
+    /// This EXIF with only the values in `fields`
+    pub fn only(self, fields: &[ExifField]) -> Self {
+        let keep = |field| fields.contains(&field);
+        Exif {
+            camera_make: self.camera_make.filter(|_| keep(ExifField::CameraMake)),
+            camera_model: self.camera_model.filter(|_| keep(ExifField::CameraModel)),
+            lens: self.lens.filter(|_| keep(ExifField::Lens)),
+            focal_length: self.focal_length.filter(|_| keep(ExifField::FocalLength)),
+            aperture: self.aperture.filter(|_| keep(ExifField::Aperture)),
+            exposure_time: self.exposure_time.filter(|_| keep(ExifField::ExposureTime)),
+            iso: self.iso.filter(|_| keep(ExifField::Iso)),
+            exposure_compensation: self
+                .exposure_compensation
+                .filter(|_| keep(ExifField::ExposureCompensation)),
+            megapixels: self.megapixels.filter(|_| keep(ExifField::Megapixels)),
+            date_time_original: self
+                .date_time_original
+                .filter(|_| keep(ExifField::DateTimeOriginal)),
+            orientation: self.orientation.filter(|_| keep(ExifField::Orientation)),
+            gps: self.gps.filter(|_| keep(ExifField::Gps)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 impl From<PeckExif> for Exif {
     fn from(item: PeckExif) -> Self {
-        Exif {
-            shutter_speed: item.attributes.get("ShutterSpeed").cloned(),
-            aperture: item.attributes.get("Aperture").cloned(),
-            camera_model: item.attributes.get("CameraModelName").cloned(),
-            focal_length: item
-                .attributes
-                .get("FocalLength")
-                .cloned()
-                .map(|x| x.replace(')', "")),
-            lens: item.attributes.get("LensType").cloned(),
-            megapixels: item.attributes.get("Megapixels").cloned(),
-            iso: item.attributes.get("ISO").cloned(),
-            exposure_compensation: item.attributes.get("ExposureCompensation").cloned(),
+        Exif::from_tags(|name| item.attributes.get(name).map(String::as_str))
+    }
+}
+
+/// A field of [`Exif`] which can be written to the data file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExifField {
+    CameraMake,
+    CameraModel,
+    Lens,
+    FocalLength,
+    Aperture,
+    ExposureTime,
+    Iso,
+    ExposureCompensation,
+    Megapixels,
+    DateTimeOriginal,
+    Orientation,
+    Gps,
+}
+
+impl ExifField {
+    /// The fields written to the data file unless configured otherwise. Where a photo was taken is left out.
+    pub const DEFAULT: &'static [ExifField] = &[
+        ExifField::CameraMake,
+        ExifField::CameraModel,
+        ExifField::Lens,
+        ExifField::FocalLength,
+        ExifField::Aperture,
+        ExifField::ExposureTime,
+        ExifField::Iso,
+        ExifField::ExposureCompensation,
+        ExifField::Megapixels,
+        ExifField::DateTimeOriginal,
+        ExifField::Orientation,
+    ];
+}
+
+/// A positive fraction. e.g. `1/250`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rational {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Rational {
+    pub fn value(&self) -> f64 {
+        self.numerator as f64 / self.denominator as f64
+    }
+}
+
+impl FromStr for Rational {
+    type Err = String;
+
+    /// Parses a fraction, e.g. `1/250`, or a decimal, e.g. `0.5` or `30`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("{s} is not a fraction. e.g. 1/250");
+        let s = s.trim().trim_end_matches(['s', '"']).trim();
+        if let Some((numerator, denominator)) = s.split_once('/') {
+            let numerator = numerator.trim().parse().map_err(|_| error())?;
+            let denominator = denominator.trim().parse().map_err(|_| error())?;
+            if denominator == 0 {
+                return Err(error());
+            }
+            return Ok(Self {
+                numerator,
+                denominator,
+            });
+        }
+        let value = s
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite() && *value >= 0.0)
+            .ok_or_else(error)?;
+        let denominator = if value.fract() == 0.0 { 1 } else { 1000 };
+        let numerator = (value * denominator as f64).round() as u32;
+        let divisor = gcd(numerator, denominator);
+        Ok(Self {
+            numerator: numerator / divisor,
+            denominator: denominator / divisor,
+        })
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a.max(1)
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl TryFrom<String> for Rational {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Rational> for String {
+    fn from(value: Rational) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.denominator == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+/// The EXIF orientation of a photo, 1-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum Orientation {
+    Normal = 1,
+    MirrorHorizontal = 2,
+    Rotate180 = 3,
+    MirrorVertical = 4,
+    MirrorHorizontalRotate270 = 5,
+    Rotate90 = 6,
+    MirrorHorizontalRotate90 = 7,
+    Rotate270 = 8,
+}
+
+impl Orientation {
+    const ALL: [Orientation; 8] = [
+        Orientation::Normal,
+        Orientation::MirrorHorizontal,
+        Orientation::Rotate180,
+        Orientation::MirrorVertical,
+        Orientation::MirrorHorizontalRotate270,
+        Orientation::Rotate90,
+        Orientation::MirrorHorizontalRotate90,
+        Orientation::Rotate270,
+    ];
+
+    /// The orientation in the EXIF of a JPEG or TIFF, read without exiftool
+    pub fn read(contents: &[u8]) -> Option<Orientation> {
+        let tiff = if contents.starts_with(b"II*\0") || contents.starts_with(b"MM\0*") {
+            contents
+        } else {
+            jpeg_segments(contents)
+                .into_iter()
+                .filter(|(marker, _)| *marker == 0xE1)
+                .find_map(|(_, segment)| segment.strip_prefix(b"Exif\0\0"))?
+        };
+        let reader = Tiff::new(tiff)?;
+        let entry = reader.find(tiff, reader.first_ifd(tiff)?, ORIENTATION_TAG)?;
+        let value = reader.u16(tiff, entry + 8)?;
+        u8::try_from(value).ok()?.try_into().ok()
+    }
+
+    /// Whether the photo is displayed on its side, so its width and height are swapped
+    pub fn swaps_dimensions(&self) -> bool {
+        matches!(
+            self,
+            Orientation::MirrorHorizontalRotate270
+                | Orientation::Rotate90
+                | Orientation::MirrorHorizontalRotate90
+                | Orientation::Rotate270
+        )
+    }
+
+    /// The width and height of a photo stored as `width` by `height` pixels, displayed upright
+    pub fn upright(&self, width: usize, height: usize) -> (usize, usize) {
+        if self.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// How exiftool describes the orientation
+    fn description(&self) -> &'static str {
+        match self {
+            Orientation::Normal => "Horizontal (normal)",
+            Orientation::MirrorHorizontal => "Mirror horizontal",
+            Orientation::Rotate180 => "Rotate 180",
+            Orientation::MirrorVertical => "Mirror vertical",
+            Orientation::MirrorHorizontalRotate270 => "Mirror horizontal and rotate 270 CW",
+            Orientation::Rotate90 => "Rotate 90 CW",
+            Orientation::MirrorHorizontalRotate90 => "Mirror horizontal and rotate 90 CW",
+            Orientation::Rotate270 => "Rotate 270 CW",
+        }
+    }
+}
+
+impl TryFrom<u8> for Orientation {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Orientation::ALL
+            .get((value as usize).wrapping_sub(1))
+            .copied()
+            .ok_or_else(|| format!("{value} is not an EXIF orientation. Expected 1-8"))
+    }
+}
+
+impl From<Orientation> for u8 {
+    fn from(value: Orientation) -> Self {
+        value as u8
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = String;
+
+    /// Parses an orientation as a number or as exiftool describes it. e.g. `6` or `Rotate 90 CW`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(value) = s.parse::<u8>() {
+            return value.try_into();
+        }
+        Orientation::ALL
+            .into_iter()
+            .find(|orientation| orientation.description().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("{s} is not an EXIF orientation"))
+    }
+}
+
+/// A position in decimal degrees, north and east positive, and metres above sea level
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub altitude: Option<f64>,
+}
+
+/// The size in bytes of each TIFF field type, by type number
+const TIFF_TYPE_SIZES: [usize; 13] = [0, 1, 1, 2, 4, 8, 1, 1, 2, 4, 8, 4, 8];
+
+/// Reads the TIFF structure EXIF is stored as. Offsets are from the start of the structure.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Tiff {
+    little_endian: bool,
+}
+
+impl Tiff {
+    /// A reader for `tiff`, if it starts with a TIFF header
+    pub fn new(tiff: &[u8]) -> Option<Self> {
+        if tiff.starts_with(b"II*\0") {
+            Some(Self {
+                little_endian: true,
+            })
+        } else if tiff.starts_with(b"MM\0*") {
+            Some(Self {
+                little_endian: false,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn u16(&self, tiff: &[u8], offset: usize) -> Option<u16> {
+        let bytes = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    pub fn u32(&self, tiff: &[u8], offset: usize) -> Option<u32> {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// Where the first image file directory (IFD0) is
+    pub fn first_ifd(&self, tiff: &[u8]) -> Option<usize> {
+        self.u32(tiff, 4).map(|offset| offset as usize)
+    }
+
+    /// Where each 12 byte entry of the directory at `ifd` is
+    pub fn entries(&self, tiff: &[u8], ifd: usize) -> Vec<usize> {
+        let count = self.u16(tiff, ifd).unwrap_or_default() as usize;
+        (0..count)
+            .map(|index| ifd + 2 + index * 12)
+            .take_while(|entry| entry + 12 <= tiff.len())
+            .collect()
+    }
+
+    /// Where the entry for `tag` in the directory at `ifd` is
+    pub fn find(&self, tiff: &[u8], ifd: usize, tag: u16) -> Option<usize> {
+        self.entries(tiff, ifd)
+            .into_iter()
+            .find(|entry| self.u16(tiff, *entry) == Some(tag))
+    }
+
+    /// Where the directory the entry for `tag` in the directory at `ifd` points to is. e.g. the GPS directory.
+    pub fn sub_ifd(&self, tiff: &[u8], ifd: usize, tag: u16) -> Option<usize> {
+        let entry = self.find(tiff, ifd, tag)?;
+        self.u32(tiff, entry + 8).map(|offset| offset as usize)
+    }
+
+    /// The bytes holding the value of the entry at `entry`, which are inline if they fit in 4 bytes
+    pub fn value(&self, tiff: &[u8], entry: usize) -> Option<Range<usize>> {
+        let size = TIFF_TYPE_SIZES.get(self.u16(tiff, entry + 2)? as usize)?
+            * self.u32(tiff, entry + 4)? as usize;
+        let start = if size <= 4 {
+            entry + 8
+        } else {
+            self.u32(tiff, entry + 8)? as usize
+        };
+        (start + size <= tiff.len()).then_some(start..start + size)
+    }
+}
+
+/// The number at the start of a value exiftool prints. e.g. `50.0` in `50.0 mm (35 mm equivalent: 75.0 mm)`
+fn leading_number(value: &str) -> Option<f64> {
+    let value = value.trim().trim_start_matches("f/");
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(value.len());
+    value[..end].parse().ok()
+}
+
+/// A number which may be written as a fraction. e.g. `+1/3`
+fn fraction(value: &str) -> Option<f64> {
+    let value = value.trim();
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let denominator = denominator.trim().parse::<f64>().ok()?;
+            (denominator != 0.0).then_some(numerator.trim().parse::<f64>().ok()? / denominator)
         }
+        None => value.parse().ok(),
+    }
+}
+
+/// A latitude or longitude in decimal degrees from a decimal or `51 deg 30' 26.00" N`.
+/// The hemisphere is read from the end of the value or from `reference`, e.g. `South`.
+fn coordinate(value: &str, reference: Option<&str>) -> Option<f64> {
+    let numbers = value
+        .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let degrees = match numbers[..] {
+        [degrees] => degrees,
+        [degrees, minutes] => degrees + minutes / 60.0,
+        [degrees, minutes, seconds] => degrees + minutes / 60.0 + seconds / 3600.0,
+        _ => return None,
+    };
+    let hemisphere = value
+        .trim()
+        .chars()
+        .last()
+        .filter(char::is_ascii_alphabetic)
+        .or_else(|| reference?.trim().chars().next());
+    Some(match hemisphere.map(|c| c.to_ascii_uppercase()) {
+        Some('S' | 'W') => -degrees.abs(),
+        _ => degrees,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_from_tags() {
+        let tags = HashMap::from([
+            ("Model", "X-T4"),
+            ("FocalLength", "50.0 mm (35 mm equivalent: 75.0 mm)"),
+            ("FNumber", "2.8"),
+            ("ExposureTime", "1/250"),
+            ("ISO", "400"),
+            ("ExposureCompensation", "-2/3"),
+            ("DateTimeOriginal", "2024:03:09 12:30:05+01:00"),
+            ("Orientation", "Rotate 90 CW"),
+            ("GPSLatitude", "51 deg 30' 36.00\" N"),
+            ("GPSLongitude", "0 deg 7' 12.00\""),
+            ("GPSLongitudeRef", "West"),
+            ("GPSAltitude", "12.5 m Below Sea Level"),
+        ]);
+        let exif = Exif::from_tags(|name| tags.get(name).copied());
+        assert_eq!(exif.camera_model.as_deref(), Some("X-T4"));
+        assert_eq!(exif.focal_length, Some(50.0));
+        assert_eq!(exif.aperture, Some(2.8));
+        assert_eq!(exif.exposure_time, Some("1/250".parse().unwrap()));
+        assert_eq!(exif.iso, Some(400));
+        assert_eq!(exif.exposure_compensation, Some(-0.67));
+        assert_eq!(exif.captured_on(), NaiveDate::from_ymd_opt(2024, 3, 9));
+        assert_eq!(exif.orientation, Some(Orientation::Rotate90));
+        let gps = exif.gps.unwrap();
+        assert!((gps.latitude - 51.51).abs() < 1e-9);
+        assert!((gps.longitude + 0.12).abs() < 1e-9);
+        assert_eq!(gps.altitude, Some(-12.5));
+
+        let exif = exif.only(ExifField::DEFAULT);
+        assert_eq!(exif.gps, None);
+        assert_eq!(exif.iso, Some(400));
+    }
+
+    #[test]
+    fn test_read_orientation() {
+        for value in 1..=8u16 {
+            for little_endian in [true, false] {
+                let u16_bytes = |n: u16| {
+                    if little_endian {
+                        n.to_le_bytes()
+                    } else {
+                        n.to_be_bytes()
+                    }
+                };
+                let mut tiff = if little_endian {
+                    b"II*\0\x08\0\0\0".to_vec()
+                } else {
+                    b"MM\0*\0\0\0\x08".to_vec()
+                };
+                tiff.extend(u16_bytes(1));
+                tiff.extend(u16_bytes(ORIENTATION_TAG));
+                // A SHORT with a count of 1
+                tiff.extend(u16_bytes(3));
+                tiff.extend(if little_endian {
+                    [1, 0, 0, 0]
+                } else {
+                    [0, 0, 0, 1]
+                });
+                tiff.extend(u16_bytes(value));
+                tiff.extend([0, 0, 0, 0, 0, 0]);
+
+                let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+                jpeg.extend(((tiff.len() + 8) as u16).to_be_bytes());
+                jpeg.extend(b"Exif\0\0");
+                jpeg.extend(&tiff);
+                jpeg.extend([0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+
+                let expected = Orientation::try_from(value as u8).ok();
+                assert_eq!(Orientation::read(&jpeg), expected);
+                assert_eq!(Orientation::read(&tiff), expected);
+            }
+        }
+        assert_eq!(Orientation::read(&[0xFF, 0xD8, 0xFF, 0xD9]), None);
+    }
+
+    #[test]
+    fn test_rational() {
+        assert_eq!("0.5".parse::<Rational>().unwrap().to_string(), "1/2");
+        assert_eq!("30".parse::<Rational>().unwrap().to_string(), "30");
+        assert!("1/0".parse::<Rational>().is_err());
     }
 }
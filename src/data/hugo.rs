@@ -1,14 +1,94 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use super::exif::Exif;
 use super::fallback_image::FallbackImage;
+use super::image_file::ImageFile;
+use super::metadata::Metadata;
 use super::source::Source;
+use crate::image::crop::{CropRectangle, FocalPoint};
+use crate::image::placeholder::PlaceholderKind;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct HugoData {
     pub name: String,
     pub fallback: FallbackImage,
     pub sources: Vec<Source>,
+    /// How to render `fallback.placeholder` and each source's placeholder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub placeholder_kind: Option<PlaceholderKind>,
+
+    /// The intrinsic width of the image in pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub width: Option<usize>,
+
+    /// The intrinsic height of the image in pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub height: Option<usize>,
+
+    /// `width` divided by `height`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub aspect_ratio: Option<f64>,
+
+    /// Every uploaded size and format, smallest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub variants: Vec<ImageFile>,
+
+    /// A crop of the most interesting region for social media link previews, if `--social-card` was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub social_card: Option<ImageFile>,
+
+    /// A square crop of the most interesting region, if `--thumbnail` was given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub thumbnail: Option<ImageFile>,
+
+    /// A description of the image for people who can't see it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub alt: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub caption: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub title: Option<String>,
+
+    /// Who made the image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub credit: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub license: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Where the subject of the image is as `x,y` fractions of its width and height, if given by hand
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub focus: Option<FocalPoint>,
+
+    /// The part of the image crops were taken from as `x,y,width,height` fractions, if given by hand
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub crop: Option<CropRectangle>,
+
+    /// A CSS `object-position` keeping the subject in view. e.g. `50% 30%`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub object_position: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub hqimage: Option<String>,
@@ -17,7 +97,102 @@ pub struct HugoData {
     #[serde(default)]
     pub original_image: Option<String>,
 
+    /// EXIF written as strings by earlier versions is migrated to typed values when the data file is read
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_exif")]
     pub exif: Option<Exif>,
+
+    /// The image's IPTC and XMP metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+}
+
+/// The exiftool tag each field of the EXIF written by earlier versions, which were all strings, was read from
+const LEGACY_EXIF_TAGS: &[(&str, &str)] = &[
+    ("shutter_speed", "ShutterSpeed"),
+    ("aperture", "Aperture"),
+    ("camera_model", "CameraModelName"),
+    ("focal_length", "FocalLength"),
+    ("lens", "LensType"),
+    ("megapixels", "Megapixels"),
+    ("iso", "ISO"),
+    ("exposure_compensation", "ExposureCompensation"),
+    ("date_time_original", "DateTimeOriginal"),
+];
+
+/// Reads typed EXIF, or migrates EXIF written as strings by earlier versions by parsing each value as exiftool printed it.
+/// EXIF which is neither is dropped rather than failing to read the data file.
+fn deserialize_exif<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Exif>, D::Error> {
+    let Some(value) = Option::<serde_json::Value>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    // Only earlier versions wrote `shutter_speed`, and always wrote it even when it was null
+    let legacy = value.get("shutter_speed").is_some();
+    if !legacy {
+        if let Ok(exif) = serde_json::from_value(value.clone()) {
+            return Ok(Some(exif));
+        }
+    }
+    let exif = Exif::from_tags(|name| {
+        let (field, _) = LEGACY_EXIF_TAGS.iter().find(|(_, tag)| *tag == name)?;
+        value.get(field)?.as_str()
+    });
+    Ok((!exif.is_empty()).then_some(exif))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::exif::Rational;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_legacy_exif_round_trips() {
+        let legacy = r#"[{
+            "name": "holiday-beach.jpg",
+            "fallback": {"src": "beach-640w.jpeg", "sizes": "100vw", "srcset": "beach-640w.jpeg 640w", "placeholder": ""},
+            "sources": [],
+            "exif": {
+                "shutter_speed": "1/250",
+                "aperture": "2.8",
+                "camera_model": "X100V",
+                "focal_length": "23.0 mm (35 mm equivalent: 35.0 mm",
+                "lens": null,
+                "megapixels": "26.1",
+                "iso": "400",
+                "exposure_compensation": "-2/3",
+                "date_time_original": "2024:03:09 12:00:00"
+            }
+        }]"#;
+        let data: Vec<HugoData> = serde_json::from_str(legacy).unwrap();
+        let exif = data[0].exif.clone().unwrap();
+        assert_eq!(
+            exif.exposure_time,
+            Some("1/250".parse::<Rational>().unwrap())
+        );
+        assert_eq!(exif.aperture, Some(2.8));
+        assert_eq!(exif.camera_model.as_deref(), Some("X100V"));
+        assert_eq!(exif.focal_length, Some(23.0));
+        assert_eq!(exif.lens, None);
+        assert_eq!(exif.megapixels, Some(26.1));
+        assert_eq!(exif.iso, Some(400));
+        assert_eq!(exif.exposure_compensation, Some(-0.67));
+        assert_eq!(
+            exif.date_time_original,
+            NaiveDate::from_ymd_opt(2024, 3, 9).and_then(|date| date.and_hms_opt(12, 0, 0))
+        );
+
+        // Written back typed, the migrated EXIF reads the same
+        let rewritten: Vec<HugoData> =
+            serde_json::from_str(&serde_json::to_string(&data).unwrap()).unwrap();
+        assert_eq!(rewritten, data);
+    }
+
+    #[test]
+    fn test_unreadable_exif_is_dropped() {
+        let entry = r#"{"name": "a", "fallback": {"src": "", "sizes": "", "srcset": "", "placeholder": ""}, "sources": [], "exif": 3}"#;
+        let data: HugoData = serde_json::from_str(entry).unwrap();
+        assert_eq!(data.exif, None);
+    }
 }
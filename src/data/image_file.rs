@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+
+/// A single uploaded size and format of an image
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImageFile {
+    pub src: String,
+    pub width: usize,
+    pub height: usize,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+    /// The size of the file in bytes
+    pub bytes: u64,
+}
+
+impl ImageFile {
+    pub fn new(src: String, width: usize, height: usize, mime_type: String, bytes: u64) -> Self {
+        Self {
+            src,
+            width,
+            height,
+            mime_type,
+            bytes,
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::sidecar::Sidecar;
+
+/// The start of the payload of a JPEG APP13 segment holding Photoshop image resources
+const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+
+/// The Photoshop image resource holding IPTC-IIM records
+const IPTC_RESOURCE: u16 = 0x0404;
+
+/// IPTC-IIM application record datasets
+const IPTC_OBJECT_NAME: u8 = 5;
+const IPTC_KEYWORDS: u8 = 25;
+const IPTC_BYLINE: u8 = 80;
+const IPTC_COPYRIGHT: u8 = 116;
+const IPTC_CAPTION: u8 = 120;
+
+/// Descriptive metadata written into an image by its photographer, e.g. in Lightroom, as IPTC or XMP
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub title: Option<String>,
+
+    /// What the image shows. IPTC's caption/abstract.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// A description of the image for people who can't see it, if given separately to `description`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub alt_text: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub keywords: Vec<String>,
+
+    /// Who made the image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub creator: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub copyright: Option<String>,
+}
+
+impl Metadata {
+    /// Reads the metadata of the image at `path`, whose contents are `contents`.
+    ///
+    /// Values in an `.xmp` sidecar next to the image take precedence over XMP embedded in it,
+    /// which takes precedence over IPTC-IIM records embedded in it.
+    pub fn read(path: &Path, contents: &[u8]) -> Result<Self> {
+        let sidecar = match xmp_sidecar_path(path) {
+            Some(xmp_path) => Self::from_xmp(
+                &read_to_string(&xmp_path)
+                    .with_context(|| format!("Failed to read {}", xmp_path.to_string_lossy()))?,
+            ),
+            None => Self::default(),
+        };
+        let embedded_xmp = embedded_xmp(contents)
+            .map(Self::from_xmp)
+            .unwrap_or_default();
+        let iptc = iptc_records(contents)
+            .map(|records| Self::from_iptc(&records))
+            .unwrap_or_default();
+        Ok(sidecar.or(embedded_xmp).or(iptc))
+    }
+
+    /// Parses the properties of an XMP packet
+    pub fn from_xmp(xmp: &str) -> Self {
+        let first = |name| xmp_property(xmp, name).into_iter().next();
+        Self {
+            title: first("dc:title"),
+            description: first("dc:description"),
+            alt_text: first("Iptc4xmpCore:AltTextAccessibility"),
+            keywords: xmp_property(xmp, "dc:subject"),
+            creator: first("dc:creator"),
+            copyright: first("dc:rights"),
+        }
+    }
+
+    /// Reads IPTC-IIM application records, as `(dataset, value)` pairs
+    fn from_iptc(records: &[(u8, String)]) -> Self {
+        let first = |dataset| {
+            records
+                .iter()
+                .find(|(number, _)| *number == dataset)
+                .map(|(_, value)| value.clone())
+        };
+        Self {
+            title: first(IPTC_OBJECT_NAME),
+            description: first(IPTC_CAPTION),
+            alt_text: None,
+            keywords: records
+                .iter()
+                .filter(|(number, _)| *number == IPTC_KEYWORDS)
+                .map(|(_, value)| value.clone())
+                .collect(),
+            creator: first(IPTC_BYLINE),
+            copyright: first(IPTC_COPYRIGHT),
+        }
+    }
+
+    /// This metadata, falling back to `other` for any value that isn't set
+    pub fn or(self, other: Metadata) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            description: self.description.or(other.description),
+            alt_text: self.alt_text.or(other.alt_text),
+            keywords: if self.keywords.is_empty() {
+                other.keywords
+            } else {
+                self.keywords
+            },
+            creator: self.creator.or(other.creator),
+            copyright: self.copyright.or(other.copyright),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The descriptions this metadata prefills when the image's sidecar doesn't give them.
+    /// The description is used as alt text unless alt text is given separately.
+    pub fn sidecar(&self) -> Sidecar {
+        Sidecar {
+            alt: self.alt_text.clone().or_else(|| self.description.clone()),
+            caption: self.description.clone(),
+            title: self.title.clone(),
+            credit: self.creator.clone(),
+            tags: self.keywords.clone(),
+            ..Sidecar::default()
+        }
+    }
+}
+
+/// Where the `.xmp` sidecar of the image at `path` is, if it has one.
+/// Both `photo.xmp` (as Lightroom writes) and `photo.jpg.xmp` (as darktable writes) are found.
+fn xmp_sidecar_path(path: &Path) -> Option<PathBuf> {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".xmp");
+    [path.with_extension("xmp"), PathBuf::from(appended)]
+        .into_iter()
+        .find(|candidate| candidate.is_file())
+}
+
+/// The XMP packet embedded in an image. Found by its root element so it's read from any format storing it
+/// uncompressed, including JPEG, PNG, WebP and TIFF.
+fn embedded_xmp(contents: &[u8]) -> Option<&str> {
+    const START: &[u8] = b"<x:xmpmeta";
+    const END: &[u8] = b"</x:xmpmeta>";
+    let start = find(contents, START)?;
+    let end = start + find(&contents[start..], END)? + END.len();
+    std::str::from_utf8(&contents[start..end]).ok()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Every value of the XMP property `name`, whether written as an element (with or without an
+/// `rdf:Alt`, `rdf:Bag` or `rdf:Seq` of items) or as an attribute of its `rdf:Description`
+fn xmp_property(xmp: &str, name: &str) -> Vec<String> {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let element = xmp.match_indices(&open).find_map(|(start, _)| {
+        let rest = &xmp[start + open.len()..];
+        // Skip properties whose name only starts with `name`
+        if !rest.starts_with(['>', ' ', '\t', '\r', '\n']) {
+            return None;
+        }
+        let content_start = rest.find('>')? + 1;
+        if rest[..content_start].ends_with("/>") {
+            return None;
+        }
+        let content_end = rest.find(&close)?;
+        Some(&rest[content_start..content_end])
+    });
+    if let Some(content) = element {
+        let items = xml_elements(content, "rdf:li");
+        let values = if items.is_empty() && !content.contains('<') {
+            vec![content]
+        } else {
+            items
+        };
+        return values
+            .into_iter()
+            .map(|value| unescape_xml(value.trim()))
+            .filter(|value| !value.is_empty())
+            .collect();
+    }
+
+    let attribute = format!("{name}=");
+    xmp.match_indices(&attribute)
+        .find_map(|(start, _)| {
+            if !xmp[..start].ends_with(char::is_whitespace) {
+                return None;
+            }
+            let rest = &xmp[start + attribute.len()..];
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let end = rest[1..].find(quote)?;
+            Some(unescape_xml(&rest[1..=end]))
+        })
+        .filter(|value| !value.is_empty())
+        .into_iter()
+        .collect()
+}
+
+/// The text content of every `name` element in `xml`
+fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(content_start) = rest.find('>') else {
+            break;
+        };
+        if rest[..content_start].ends_with('/') {
+            rest = &rest[content_start + 1..];
+            continue;
+        }
+        let Some(content_end) = rest.find(&close) else {
+            break;
+        };
+        elements.push(&rest[content_start + 1..content_end]);
+        rest = &rest[content_end + close.len()..];
+    }
+    elements
+}
+
+fn unescape_xml(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = entity.and_then(|(entity, _)| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .unwrap_or_else(|| entity.strip_prefix('#').unwrap_or("").parse())
+                .ok()
+                .and_then(char::from_u32),
+        });
+        match (character, entity) {
+            (Some(character), Some((_, end))) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// The IPTC-IIM application records of a JPEG, from the Photoshop resources in its APP13 segment
+fn iptc_records(contents: &[u8]) -> Option<Vec<(u8, String)>> {
+    jpeg_segments(contents)
+        .into_iter()
+        .filter(|(marker, _)| *marker == 0xED)
+        .filter_map(|(_, segment)| segment.strip_prefix(PHOTOSHOP_HEADER))
+        .find_map(|resources| photoshop_resource(resources, IPTC_RESOURCE))
+        .map(parse_iim)
+}
+
+/// The marker and payload of a JPEG segment
+pub(crate) type JpegSegment<'a> = (u8, &'a [u8]);
+
+/// The marker and payload of every segment of a JPEG before its image data. Empty for other formats.
+pub(crate) fn jpeg_segments(contents: &[u8]) -> Vec<JpegSegment<'_>> {
+    split_jpeg(contents).0
+}
+
+/// The marker and payload of every segment of a JPEG before its image data, and everything from its start of
+/// scan marker onwards. The image data is `None` if it's missing or the JPEG is malformed.
+pub(crate) fn split_jpeg(contents: &[u8]) -> (Vec<JpegSegment<'_>>, Option<&[u8]>) {
+    let mut segments = Vec::new();
+    if !contents.starts_with(&[0xFF, 0xD8]) {
+        return (segments, None);
+    }
+    let mut offset = 2;
+    while offset + 4 <= contents.len() && contents[offset] == 0xFF {
+        let marker = contents[offset + 1];
+        // Metadata segments all come before the start of scan
+        if marker == 0xDA {
+            return (segments, Some(&contents[offset..]));
+        }
+        let length = u16::from_be_bytes([contents[offset + 2], contents[offset + 3]]) as usize;
+        let Some(segment) = contents.get(offset + 4..offset + 2 + length) else {
+            break;
+        };
+        segments.push((marker, segment));
+        offset += 2 + length;
+    }
+    (segments, None)
+}
+
+/// The data of the Photoshop image resource with `id`
+fn photoshop_resource(mut resources: &[u8], id: u16) -> Option<&[u8]> {
+    while resources.len() >= 12 && resources.starts_with(b"8BIM") {
+        let resource_id = u16::from_be_bytes([resources[4], resources[5]]);
+        // The name is a Pascal string padded to an even length
+        let name_length = resources[6] as usize;
+        let size_offset = 6 + (name_length + 1).next_multiple_of(2);
+        let size = u32::from_be_bytes(
+            resources
+                .get(size_offset..size_offset + 4)?
+                .try_into()
+                .ok()?,
+        ) as usize;
+        let data_offset = size_offset + 4;
+        let data = resources.get(data_offset..data_offset + size)?;
+        if resource_id == id {
+            return Some(data);
+        }
+        resources = resources.get(data_offset + size.next_multiple_of(2)..)?;
+    }
+    None
+}
+
+/// The application record (2) datasets of IPTC-IIM data, decoded as UTF-8 or, failing that, Latin-1
+fn parse_iim(mut data: &[u8]) -> Vec<(u8, String)> {
+    let mut records = Vec::new();
+    while data.len() >= 5 && data[0] == 0x1C {
+        let (record, dataset) = (data[1], data[2]);
+        let size = u16::from_be_bytes([data[3], data[4]]) as usize;
+        // Extended datasets, longer than 32KiB, are never descriptions
+        if size & 0x8000 != 0 {
+            break;
+        }
+        let Some(value) = data.get(5..5 + size) else {
+            break;
+        };
+        if record == 2 {
+            let value = match std::str::from_utf8(value) {
+                Ok(value) => value.to_owned(),
+                Err(_) => value.iter().map(|byte| *byte as char).collect(),
+            };
+            records.push((dataset, value.trim().to_owned()));
+        }
+        data = &data[5 + size..];
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XMP: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/"
+    photoshop:Credit="Arran France &amp; Co">
+   <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Heron</rdf:li></rdf:Alt></dc:title>
+   <dc:description><rdf:Alt><rdf:li xml:lang="x-default">A heron &lt;standing&gt; in a river</rdf:li></rdf:Alt></dc:description>
+   <dc:creator><rdf:Seq><rdf:li>Arran France</rdf:li></rdf:Seq></dc:creator>
+   <dc:subject><rdf:Bag><rdf:li>birds</rdf:li><rdf:li>rivers</rdf:li></rdf:Bag></dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn test_from_xmp() {
+        let metadata = Metadata::from_xmp(XMP);
+        assert_eq!(metadata.title.as_deref(), Some("Heron"));
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("A heron <standing> in a river")
+        );
+        assert_eq!(metadata.creator.as_deref(), Some("Arran France"));
+        assert_eq!(metadata.keywords, vec!["birds", "rivers"]);
+        assert_eq!(metadata.copyright, None);
+        assert_eq!(
+            xmp_property(XMP, "photoshop:Credit"),
+            vec!["Arran France & Co"]
+        );
+    }
+
+    #[test]
+    fn test_embedded_iptc_and_xmp() {
+        let dataset = |number: u8, value: &str| {
+            let mut bytes = vec![0x1C, 2, number];
+            bytes.extend((value.len() as u16).to_be_bytes());
+            bytes.extend(value.as_bytes());
+            bytes
+        };
+        let iptc = [
+            dataset(IPTC_KEYWORDS, "birds"),
+            dataset(IPTC_KEYWORDS, "rivers"),
+            dataset(IPTC_COPYRIGHT, "© Arran France"),
+            dataset(IPTC_CAPTION, "From IPTC"),
+        ]
+        .concat();
+        let mut resources = b"8BIM".to_vec();
+        resources.extend(IPTC_RESOURCE.to_be_bytes());
+        resources.extend([0, 0]);
+        resources.extend((iptc.len() as u32).to_be_bytes());
+        resources.extend(&iptc);
+        let mut app13 = PHOTOSHOP_HEADER.to_vec();
+        app13.extend(resources);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xED];
+        jpeg.extend(((app13.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(app13);
+        let xmp = b"<x:xmpmeta><dc:description><rdf:Alt><rdf:li>From XMP</rdf:li></rdf:Alt></dc:description></x:xmpmeta>";
+        let mut app1 = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+        app1.extend(xmp);
+        jpeg.extend([0xFF, 0xE1]);
+        jpeg.extend(((app1.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(app1);
+        jpeg.extend([0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+
+        let metadata = Metadata::read(Path::new("/nonexistent/heron.jpg"), &jpeg).unwrap();
+        assert_eq!(metadata.description.as_deref(), Some("From XMP"));
+        assert_eq!(metadata.keywords, vec!["birds", "rivers"]);
+        assert_eq!(metadata.copyright.as_deref(), Some("© Arran France"));
+        assert_eq!(metadata.sidecar().alt.as_deref(), Some("From XMP"));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod exif;
 pub mod fallback_image;
 pub mod hugo;
+pub mod image_file;
+pub mod metadata;
 pub mod source;
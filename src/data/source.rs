@@ -2,19 +2,33 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Source {
-    pub media: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub media: Option<String>,
     pub sizes: String,
     pub srcset: String,
     pub placeholder: String,
+    /// The MIME type of every image in the `srcset`
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub mime_type: Option<String>,
 }
 
 impl Source {
-    pub fn new(media: String, sizes: String, srcset: String, placeholder: String) -> Self {
+    pub fn new(
+        media: Option<String>,
+        sizes: String,
+        srcset: String,
+        placeholder: String,
+        mime_type: Option<String>,
+    ) -> Self {
         Self {
             media,
             sizes,
             srcset,
             placeholder,
+            mime_type,
         }
     }
 }
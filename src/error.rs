@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use std::{io, str::Utf8Error};
+use std::{io, path::PathBuf, str::Utf8Error};
 
 use s3::creds::error::CredentialsError;
 
@@ -18,8 +18,27 @@ pub enum AppError {
     RegionParse(#[from] Utf8Error),
     #[error("Failed to get AWS credentials")]
     Credentials(#[from] CredentialsError),
+    #[error("Failed to parse configuration file {}", .path.display())]
+    ConfigParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Configuration file {} does not exist", .0.display())]
+    ConfigMissing(PathBuf),
+    #[error("Layout {0} is not defined in the layouts configuration")]
+    UnknownLayout(String),
+    #[error("{file} is listed just as specifically by layouts {first} and {second}. Make one of their files patterns more specific")]
+    AmbiguousLayout {
+        file: String,
+        first: String,
+        second: String,
+    },
     #[error("Key already exists in data template")]
     KeyAlreadyExists,
+    #[error("{0} images in the data file have problems")]
+    LintFailed(usize),
+    #[error("{0} uploaded files record where they were taken")]
+    AuditFailed(usize),
     #[error("Image is too small")]
     ImageTooSmall,
     #[error(transparent)]
@@ -0,0 +1,176 @@
+use rayon::ThreadPool;
+
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+/// Limits how much memory concurrently decoded images may use.
+///
+/// Images reserve an estimate of the memory they need before they are decoded and release it when they're
+/// finished, so a batch of huge inputs is processed a few at a time rather than all at once.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    capacity: u64,
+    available: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Memory reserved from a [`MemoryBudget`]. Returned to the budget when dropped.
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            available: Mutex::new(capacity),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until `bytes` are available and reserves them.
+    /// Requests larger than the whole budget wait until nothing else is reserved and then take all of it.
+    pub fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let bytes = bytes.min(self.capacity);
+        let mut available = self
+            .released
+            .wait_while(self.available.lock().unwrap(), |available| {
+                *available < bytes
+            })
+            .unwrap();
+        *available -= bytes;
+        Reservation {
+            budget: self,
+            bytes,
+        }
+    }
+
+    /// Runs `task` for every item on `pool`, returning the results in the same order.
+    ///
+    /// The `estimate` of each item is reserved on the calling thread before the item is handed to the pool,
+    /// and released when its task finishes. Workers never wait on the budget, so a worker holding a reservation
+    /// which steals another item's task whilst waiting on nested parallel work can't deadlock.
+    pub fn map<T: Sync, R: Send>(
+        &self,
+        pool: &ThreadPool,
+        items: &[T],
+        estimate: impl Fn(&T) -> u64,
+        task: impl Fn(&T) -> R + Sync,
+    ) -> Vec<R> {
+        let results = items.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+        let task = &task;
+        pool.in_place_scope(|scope| {
+            for (item, result) in items.iter().zip(&results) {
+                let reservation = self.reserve(estimate(item));
+                scope.spawn(move |_| {
+                    *result.lock().unwrap() = Some(task(item));
+                    drop(reservation);
+                });
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| {
+                result
+                    .into_inner()
+                    .unwrap()
+                    .expect("Every task finished with the scope")
+            })
+            .collect()
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Estimates the memory needed to decode the image at `path` and hold `copies` working copies of it alongside.
+/// Only the image header is read. If the dimensions can't be determined the file size is used as a rough guide.
+pub fn estimate_decoded_size(path: &Path, copies: usize) -> u64 {
+    // Assume 4 channels of 16 bits per pixel, the largest format the decoder produces
+    const BYTES_PER_PIXEL: u64 = 8;
+    // Compressed images are typically at least this much smaller than their decoded pixels
+    const COMPRESSION_RATIO: u64 = 10;
+
+    let decoded = match imagesize::size(path) {
+        Ok(size) => size.width as u64 * size.height as u64 * BYTES_PER_PIXEL,
+        Err(_) => path.metadata().map(|m| m.len()).unwrap_or_default() * COMPRESSION_RATIO,
+    };
+    decoded * (1 + copies as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_reservations_are_released() {
+        let budget = MemoryBudget::new(100);
+        {
+            let _first = budget.reserve(60);
+            assert_eq!(*budget.available.lock().unwrap(), 40);
+        }
+        assert_eq!(*budget.available.lock().unwrap(), 100);
+    }
+
+    #[test]
+    fn test_oversized_reservation_waits_for_everything() {
+        let budget = Arc::new(MemoryBudget::new(100));
+        let first = budget.reserve(10);
+        let waiter = {
+            let budget = Arc::clone(&budget);
+            thread::spawn(move || {
+                let reservation = budget.reserve(1000);
+                reservation.bytes
+            })
+        };
+        drop(first);
+        assert_eq!(waiter.join().unwrap(), 100);
+    }
+
+    #[test]
+    fn test_map_with_nested_parallelism_on_a_small_pool() {
+        let budget = MemoryBudget::new(100);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let in_use = AtomicU64::new(0);
+        let peak = AtomicU64::new(0);
+        let images = (0..16).collect::<Vec<u64>>();
+        // Each image needs most of the budget and fans out over its widths, like processing an image does
+        let results = budget.map(
+            &pool,
+            &images,
+            |_| 60,
+            |image| {
+                let now = in_use.fetch_add(60, Ordering::SeqCst) + 60;
+                peak.fetch_max(now, Ordering::SeqCst);
+                let widths = (0..8)
+                    .into_par_iter()
+                    .map(|width| {
+                        thread::sleep(std::time::Duration::from_millis(1));
+                        width
+                    })
+                    .sum::<u64>();
+                in_use.fetch_sub(60, Ordering::SeqCst);
+                image * widths
+            },
+        );
+        assert_eq!(
+            results,
+            images.iter().map(|image| image * 28).collect::<Vec<_>>()
+        );
+        assert!(peak.load(Ordering::SeqCst) <= 100);
+        assert_eq!(*budget.available.lock().unwrap(), 100);
+    }
+}
@@ -0,0 +1,285 @@
+use super::generated_image::GeneratedImage;
+use crate::data::exif::Exif;
+
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::fs::{copy, create_dir_all, read_to_string, rename, write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Makes the name of every temporary file written by this process unique
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A persistent cache of encoded images, and the EXIF and placeholders of their sources.
+///
+/// Entries are keyed by a hash of the source file's contents, so moving or renaming a file doesn't invalidate it,
+/// and each variant is keyed by its dimensions, format, encoder settings, colour profile handling and background.
+/// Placeholders are keyed by their kind and settings.
+/// ```text
+/// <root>/<source hash>/source.json
+/// <root>/<source hash>/exif.json
+/// <root>/<source hash>/placeholder-<settings hash>.txt
+/// <root>/<source hash>/<width>x<height>-<settings hash>.<ext>
+/// <root>/<source hash>/<width>x<height>-<crop region>-<settings hash>.<ext>
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cache {
+    root: PathBuf,
+}
+
+/// What is cached about a source file, independent of any variant
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SourceManifest {
+    /// The width of the decoded source, before it's turned upright
+    pub width: usize,
+    /// The height of the decoded source, before it's turned upright
+    pub height: usize,
+    /// Whether any pixel is transparent
+    pub transparent: bool,
+}
+
+/// The cache entry for a single source file
+#[derive(Debug)]
+pub struct CachedSource<'a> {
+    cache: &'a Cache,
+    hash: String,
+}
+
+impl Cache {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// `$XDG_CACHE_HOME/rith`, falling back to `~/.cache/rith`
+    pub fn default_directory() -> Option<PathBuf> {
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache_home.join("rith"))
+    }
+
+    /// The entry for the source file with contents `bytes`
+    pub fn source(&self, bytes: &[u8]) -> CachedSource<'_> {
+        CachedSource {
+            cache: self,
+            hash: hash(bytes),
+        }
+    }
+}
+
+impl CachedSource<'_> {
+    fn directory(&self) -> PathBuf {
+        self.cache.root.join(&self.hash)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.directory().join("source.json")
+    }
+
+    fn exif_path(&self) -> PathBuf {
+        self.directory().join("exif.json")
+    }
+
+    fn placeholder_path(&self, settings: &str) -> PathBuf {
+        self.directory().join(format!(
+            "placeholder-{}.txt",
+            &hash(settings.as_bytes())[..12]
+        ))
+    }
+
+    fn variant_path(&self, image: &GeneratedImage) -> PathBuf {
+        let color = if image.embeds_profile {
+            "embedded profile"
+        } else {
+            "srgb"
+        };
+        let background = image
+            .background
+            .map(|background| format!(" onto {background}"))
+            .unwrap_or_default();
+        let settings =
+            hash(format!("{} {color}{background}", image.format.encoder_settings()).as_bytes());
+        let region = image
+            .region
+            .map(|region| format!("{region}-"))
+            .unwrap_or_default();
+        self.directory().join(format!(
+            "{}x{}-{region}{}.{}",
+            image.width,
+            image.height,
+            &settings[..12],
+            image.format.extension()
+        ))
+    }
+
+    /// What was found decoding the source, if it has been decoded before
+    pub fn manifest(&self) -> Option<SourceManifest> {
+        let manifest = read_to_string(self.manifest_path()).ok()?;
+        serde_json::from_str(&manifest).ok()
+    }
+
+    pub fn set_manifest(&self, manifest: &SourceManifest) -> Result<()> {
+        create_dir_all(self.directory())?;
+        let manifest = serde_json::to_string(manifest)?;
+        write_atomically(&self.manifest_path(), |path| Ok(write(path, &manifest)?))
+    }
+
+    /// The EXIF read from the source, if it has been read before
+    pub fn exif(&self) -> Option<Exif> {
+        let exif = read_to_string(self.exif_path()).ok()?;
+        serde_json::from_str(&exif).ok()
+    }
+
+    pub fn set_exif(&self, exif: &Exif) -> Result<()> {
+        create_dir_all(self.directory())?;
+        let exif = serde_json::to_string(exif)?;
+        write_atomically(&self.exif_path(), |path| Ok(write(path, &exif)?))
+    }
+
+    /// The placeholder made with `settings`, if it has been made before. See [`super::placeholder::settings`]
+    pub fn placeholder(&self, settings: &str) -> Option<String> {
+        read_to_string(self.placeholder_path(settings)).ok()
+    }
+
+    pub fn set_placeholder(&self, settings: &str, placeholder: &str) -> Result<()> {
+        create_dir_all(self.directory())?;
+        write_atomically(&self.placeholder_path(settings), |path| {
+            Ok(write(path, placeholder)?)
+        })
+    }
+
+    /// Copies a cached variant to `image.path`. Returns `false` if the variant isn't cached.
+    pub fn restore(&self, image: &GeneratedImage) -> Result<bool> {
+        let cached = self.variant_path(image);
+        if !cached.exists() {
+            return Ok(false);
+        }
+        debug!(
+            "Reusing {} for {}",
+            cached.to_string_lossy(),
+            image.path.to_string_lossy()
+        );
+        copy(&cached, &image.path).with_context(|| {
+            format!("Failed to copy {} from the cache", cached.to_string_lossy())
+        })?;
+        Ok(true)
+    }
+
+    /// Adds the variant at `image.path` to the cache
+    pub fn store(&self, image: &GeneratedImage) -> Result<()> {
+        create_dir_all(self.directory())?;
+        write_atomically(&self.variant_path(image), |path| {
+            copy(&image.path, path)?;
+            Ok(())
+        })
+        .with_context(|| format!("Failed to cache {}", image.path.to_string_lossy()))
+    }
+}
+
+/// Writes to a temporary file alongside `path` and then moves it into place,
+/// so concurrent runs, and threads, never see a partially written entry
+fn write_atomically(path: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let temporary = path.with_extension(format!(
+        "{}-{}.tmp",
+        std::process::id(),
+        TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = write(&temporary).and_then(|()| Ok(rename(&temporary, path)?));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    written
+}
+
+/// A hex encoded SHA-256 hash
+fn hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::format::OutputFormat;
+    use crate::image::variant::Variant;
+    use std::fs::{read, read_dir, remove_file};
+    use std::thread;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_variants_are_reused_until_the_source_or_settings_change() {
+        let directory = tempdir().unwrap();
+        let cache = Cache::new(directory.path().join("cache"));
+        let image = GeneratedImage::new(
+            320,
+            240,
+            directory.path().join("photo-320w.jpeg"),
+            OutputFormat::Jpeg,
+            Variant::Resized(320),
+        );
+        let source = cache.source(b"photo");
+        assert!(!source.restore(&image).unwrap());
+
+        write(&image.path, b"encoded").unwrap();
+        source.store(&image).unwrap();
+        remove_file(&image.path).unwrap();
+        assert!(cache.source(b"photo").restore(&image).unwrap());
+        assert_eq!(read(&image.path).unwrap(), b"encoded");
+
+        assert!(!cache.source(b"edited photo").restore(&image).unwrap());
+        let webp = GeneratedImage::new(
+            320,
+            240,
+            directory.path().join("photo-320w.webp"),
+            OutputFormat::Webp,
+            Variant::Resized(320),
+        );
+        assert!(!source.restore(&webp).unwrap());
+        assert!(!source
+            .restore(&image.clone().with_embedded_profile(true))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_exif_and_placeholders_are_reused() {
+        let directory = tempdir().unwrap();
+        let cache = Cache::new(directory.path().to_path_buf());
+        let source = cache.source(b"photo");
+        assert_eq!(source.exif(), None);
+        assert_eq!(source.placeholder("blurhash"), None);
+
+        let exif = Exif {
+            iso: Some(400),
+            lens: Some("XF23mmF2 R WR".to_owned()),
+            ..Exif::default()
+        };
+        source.set_exif(&exif).unwrap();
+        source.set_placeholder("blurhash", "LEHV6nWB2yk8").unwrap();
+
+        let source = cache.source(b"photo");
+        assert_eq!(source.exif(), Some(exif));
+        assert_eq!(
+            source.placeholder("blurhash").as_deref(),
+            Some("LEHV6nWB2yk8")
+        );
+        assert_eq!(source.placeholder("thumbhash"), None);
+        assert_eq!(cache.source(b"edited photo").exif(), None);
+    }
+
+    #[test]
+    fn test_concurrent_writes_to_an_entry() {
+        let directory = tempdir().unwrap();
+        let cache = Cache::new(directory.path().to_path_buf());
+        let source = cache.source(b"photo");
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| source.set_placeholder("color", "#a0b1c2").unwrap());
+            }
+        });
+        assert_eq!(source.placeholder("color").as_deref(), Some("#a0b1c2"));
+        // No temporary files are left behind
+        assert_eq!(read_dir(source.directory()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,425 @@
+use anyhow::{Context, Result};
+use lcms2::{Intent, PixelFormat, Profile, Transform};
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_image::image::Image;
+
+use std::str::FromStr;
+
+use super::format::{push_jpeg_segment, OutputFormat};
+use crate::data::metadata::jpeg_segments;
+
+/// The media query matching displays which show more colours than sRGB
+pub const WIDE_GAMUT_MEDIA: &str = "(color-gamut: p3)";
+
+/// The start of the payload of a JPEG APP2 segment holding part of an ICC profile
+const JPEG_ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+const APP0: u8 = 0xE0;
+const APP2: u8 = 0xE2;
+/// The most profile bytes a JPEG segment holds alongside the header and its sequence number and count
+const JPEG_ICC_CHUNK_SIZE: usize = 65_519;
+
+/// The VP8X flags saying a WebP has an ICC profile or alpha channel
+const WEBP_ICC_FLAG: u8 = 0x20;
+const WEBP_ALPHA_FLAG: u8 = 0x10;
+
+/// sRGB's red, green and blue primaries as XYZ, adapted to D50 as ICC profiles store them
+const SRGB_PRIMARIES: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+
+/// How much larger than sRGB's a profile's gamut must be for it to be worth serving separately.
+/// Display P3's is about a third larger.
+const WIDE_GAMUT_RATIO: f64 = 1.1;
+
+/// What happens to the colour profile embedded in an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorManagement {
+    /// Convert the colours to sRGB, which browsers assume images without a profile use
+    Convert,
+    /// Keep the colours and embed the profile in formats which can hold one. Other formats are converted to sRGB.
+    Preserve,
+}
+
+impl FromStr for ColorManagement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "convert" => Ok(ColorManagement::Convert),
+            "preserve" => Ok(ColorManagement::Preserve),
+            _ => Err(format!(
+                "{s} is not a colour management mode. Expected convert or preserve"
+            )),
+        }
+    }
+}
+
+/// An ICC profile describing the colours of an RGB image. e.g. Display P3 for photos taken on an iPhone
+#[derive(Debug, Clone, PartialEq)]
+pub struct IccProfile(Vec<u8>);
+
+impl IccProfile {
+    /// The profile embedded in a JPEG, PNG or WebP, if it describes RGB colours.
+    /// Profiles of grey and CMYK images are left to the decoder.
+    pub fn read(contents: &[u8]) -> Option<Self> {
+        let profile = read_jpeg_profile(contents)
+            .or_else(|| read_png_profile(contents))
+            .or_else(|| read_webp_profile(contents))?;
+        (profile.get(16..20) == Some(b"RGB ")).then_some(Self(profile))
+    }
+
+    /// Whether the profile shows noticeably more colours than sRGB, e.g. Display P3 or Adobe RGB
+    pub fn is_wide_gamut(&self) -> bool {
+        match [b"rXYZ", b"gXYZ", b"bXYZ"].map(|signature| self.xyz(signature)) {
+            [Some(red), Some(green), Some(blue)] => {
+                gamut_area([red, green, blue]) > gamut_area(SRGB_PRIMARIES) * WIDE_GAMUT_RATIO
+            }
+            // Profiles described by lookup tables rather than primaries
+            _ => false,
+        }
+    }
+
+    /// The value of the XYZ tag with `signature`
+    fn xyz(&self, signature: &[u8; 4]) -> Option<[f64; 3]> {
+        let u32_at = |offset: usize| {
+            self.0
+                .get(offset..offset + 4)
+                .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        // The tag table follows the 128 byte header
+        let tags = u32_at(128)? as usize;
+        let entry = (0..tags)
+            .map(|index| 132 + index * 12)
+            .find(|entry| self.0.get(*entry..*entry + 4) == Some(signature))?;
+        let offset = u32_at(entry + 4)? as usize;
+        if self.0.get(offset..offset + 4) != Some(b"XYZ ") {
+            return None;
+        }
+        // Three s15Fixed16 numbers after the type and 4 reserved bytes
+        let number = |index: usize| {
+            u32_at(offset + 8 + index * 4).map(|value| value as i32 as f64 / 65_536.0)
+        };
+        Some([number(0)?, number(1)?, number(2)?])
+    }
+
+    /// Converts the colours of `image` from this profile to sRGB. Alpha is unchanged.
+    pub fn to_srgb(&self, image: &Image) -> Result<Image> {
+        let (width, height) = image.dimensions();
+        let colorspace = image.colorspace();
+        let sixteen_bit = image.depth() == BitDepth::Sixteen;
+        let format = match (colorspace, sixteen_bit) {
+            (ColorSpace::RGB, false) => PixelFormat::RGB_8,
+            (ColorSpace::RGBA, false) => PixelFormat::RGBA_8,
+            (ColorSpace::RGB, true) => PixelFormat::RGB_16,
+            (ColorSpace::RGBA, true) => PixelFormat::RGBA_16,
+            // Grey images have no primaries to convert
+            _ => return Ok(image.clone()),
+        };
+        let source = Profile::new_icc(&self.0).context("Failed to read the colour profile")?;
+        // Without copying extra channels the alpha of pixels transformed in place is left alone
+        let transform = Transform::<u8, u8>::new(
+            &source,
+            format,
+            &Profile::new_srgb(),
+            format,
+            Intent::Perceptual,
+        )
+        .context("Failed to convert the colour profile to sRGB")?;
+
+        if sixteen_bit {
+            let mut bytes = image
+                .flatten_frames::<u16>()
+                .swap_remove(0)
+                .into_iter()
+                .flat_map(u16::to_ne_bytes)
+                .collect::<Vec<u8>>();
+            transform.transform_in_place(&mut bytes);
+            let pixels = bytes
+                .chunks_exact(2)
+                .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                .collect::<Vec<u16>>();
+            Ok(Image::from_u16(&pixels, width, height, colorspace))
+        } else {
+            let mut pixels = image.flatten_to_u8().swap_remove(0);
+            transform.transform_in_place(&mut pixels);
+            Ok(Image::from_u8(&pixels, width, height, colorspace))
+        }
+    }
+
+    /// An encoded image with this profile embedded in it, or `None` if it isn't a JPEG or WebP
+    pub fn embed(&self, format: OutputFormat, encoded: &[u8]) -> Option<Vec<u8>> {
+        match format {
+            OutputFormat::Jpeg => self.embed_in_jpeg(encoded),
+            OutputFormat::Webp => self.embed_in_webp(encoded),
+            OutputFormat::Avif | OutputFormat::Jxl | OutputFormat::Png => None,
+        }
+    }
+
+    fn embed_in_jpeg(&self, encoded: &[u8]) -> Option<Vec<u8>> {
+        // The profile goes after the JFIF header, which must come first
+        let start = match encoded.strip_prefix(&[0xFF, 0xD8])? {
+            [0xFF, APP0, high, low, ..] => 4 + u16::from_be_bytes([*high, *low]) as usize,
+            _ => 2,
+        };
+        let mut jpeg = encoded.get(..start)?.to_vec();
+        // Profiles too large for a single segment are split, numbering each part from 1
+        let count = self.0.len().div_ceil(JPEG_ICC_CHUNK_SIZE);
+        for (index, chunk) in self.0.chunks(JPEG_ICC_CHUNK_SIZE).enumerate() {
+            let payload = [JPEG_ICC_HEADER, &[index as u8 + 1, count as u8], chunk].concat();
+            push_jpeg_segment(&mut jpeg, APP2, &payload);
+        }
+        jpeg.extend_from_slice(&encoded[start..]);
+        Some(jpeg)
+    }
+
+    fn embed_in_webp(&self, encoded: &[u8]) -> Option<Vec<u8>> {
+        let chunks = webp_chunks(encoded)?;
+        // A profile needs the extended format's header, which records the canvas size
+        let mut header = match chunks.iter().find(|(kind, _)| kind == b"VP8X") {
+            Some((_, header)) => header.to_vec(),
+            None => {
+                let (width, height, alpha) = chunks
+                    .iter()
+                    .find_map(|(kind, data)| webp_bitstream_size(kind, data))?;
+                let mut header = vec![if alpha { WEBP_ALPHA_FLAG } else { 0 }, 0, 0, 0];
+                header.extend(&(width - 1).to_le_bytes()[..3]);
+                header.extend(&(height - 1).to_le_bytes()[..3]);
+                header
+            }
+        };
+        *header.first_mut()? |= WEBP_ICC_FLAG;
+
+        let mut body = b"WEBP".to_vec();
+        let chunks = [(*b"VP8X", header.as_slice()), (*b"ICCP", self.0.as_slice())]
+            .into_iter()
+            .chain(
+                chunks
+                    .into_iter()
+                    .filter(|(kind, _)| kind != b"VP8X" && kind != b"ICCP"),
+            );
+        for (kind, data) in chunks {
+            body.extend(kind);
+            body.extend((data.len() as u32).to_le_bytes());
+            body.extend(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut webp = b"RIFF".to_vec();
+        webp.extend((body.len() as u32).to_le_bytes());
+        webp.extend(body);
+        Some(webp)
+    }
+}
+
+/// The area of the triangle the xy chromaticities of a red, green and blue primary make
+fn gamut_area(primaries: [[f64; 3]; 3]) -> f64 {
+    let [(x1, y1), (x2, y2), (x3, y3)] =
+        primaries.map(|[x, y, z]| (x / (x + y + z), y / (x + y + z)));
+    ((x1 * (y2 - y3) + x2 * (y3 - y1) + x3 * (y1 - y2)) / 2.0).abs()
+}
+
+/// Reassembles a profile split across APP2 segments
+fn read_jpeg_profile(contents: &[u8]) -> Option<Vec<u8>> {
+    let mut parts = jpeg_segments(contents)
+        .into_iter()
+        .filter(|(marker, _)| *marker == APP2)
+        .filter_map(|(_, payload)| payload.strip_prefix(JPEG_ICC_HEADER))
+        .filter_map(|part| Some((*part.first()?, part.get(2..)?)))
+        .collect::<Vec<_>>();
+    parts.sort_by_key(|(sequence, _)| *sequence);
+    let profile = parts
+        .into_iter()
+        .flat_map(|(_, part)| part)
+        .copied()
+        .collect::<Vec<_>>();
+    (!profile.is_empty()).then_some(profile)
+}
+
+/// Inflates the profile in a PNG's iCCP chunk
+fn read_png_profile(contents: &[u8]) -> Option<Vec<u8>> {
+    let mut rest = contents.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes(rest[..4].try_into().ok()?) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + length)?;
+        match kind {
+            // A name, a compression method and the zlib compressed profile
+            b"iCCP" => {
+                let name_end = data.iter().position(|byte| *byte == 0)?;
+                let compressed = data.get(name_end + 2..)?;
+                return zune_inflate::DeflateDecoder::new(compressed)
+                    .decode_zlib()
+                    .ok();
+            }
+            // The profile comes before the image data
+            b"IDAT" => return None,
+            _ => rest = rest.get(12 + length..)?,
+        }
+    }
+    None
+}
+
+fn read_webp_profile(contents: &[u8]) -> Option<Vec<u8>> {
+    webp_chunks(contents)?
+        .into_iter()
+        .find(|(kind, _)| kind == b"ICCP")
+        .map(|(_, profile)| profile.to_vec())
+}
+
+/// The type and data of every chunk of a WebP
+fn webp_chunks(contents: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    if !(contents.starts_with(b"RIFF") && contents.get(8..12) == Some(b"WEBP")) {
+        return None;
+    }
+    let mut chunks = Vec::new();
+    let mut rest = &contents[12..];
+    while rest.len() >= 8 {
+        let kind = rest[..4].try_into().ok()?;
+        let length = u32::from_le_bytes(rest[4..8].try_into().ok()?) as usize;
+        chunks.push((kind, rest.get(8..8 + length)?));
+        rest = rest.get(8 + length + length % 2..).unwrap_or_default();
+    }
+    Some(chunks)
+}
+
+/// The width, height and whether there's alpha of a lossy or lossless WebP bitstream
+fn webp_bitstream_size(kind: &[u8; 4], data: &[u8]) -> Option<(u32, u32, bool)> {
+    match kind {
+        // A 3 byte frame tag and start code, then 14 bit dimensions
+        b"VP8 " if data.get(3..6) == Some(&[0x9D, 0x01, 0x2A]) => {
+            let dimension = |offset: usize| {
+                Some(
+                    u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?) as u32
+                        & 0x3FFF,
+                )
+            };
+            Some((dimension(6)?, dimension(8)?, false))
+        }
+        // A signature byte, then 14 bit dimensions less one and an alpha bit
+        b"VP8L" if data.first() == Some(&0x2F) => {
+            let bits = u32::from_le_bytes(data.get(1..5)?.try_into().ok()?);
+            Some((
+                (bits & 0x3FFF) + 1,
+                ((bits >> 14) & 0x3FFF) + 1,
+                (bits >> 28) & 1 == 1,
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal RGB profile with only primaries
+    fn profile(primaries: [[f64; 3]; 3]) -> IccProfile {
+        let mut profile = vec![0; 128];
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile.extend(3u32.to_be_bytes());
+        for (index, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+            profile.extend(*signature);
+            profile.extend((168 + index as u32 * 20).to_be_bytes());
+            profile.extend(20u32.to_be_bytes());
+        }
+        for primary in primaries {
+            profile.extend(b"XYZ \0\0\0\0");
+            for value in primary {
+                profile.extend(((value * 65_536.0).round() as i32).to_be_bytes());
+            }
+        }
+        IccProfile(profile)
+    }
+
+    #[test]
+    fn test_wide_gamut() {
+        let display_p3 = [
+            [0.5151, 0.2412, -0.0011],
+            [0.2920, 0.6922, 0.0419],
+            [0.1571, 0.0666, 0.7841],
+        ];
+        assert!(profile(display_p3).is_wide_gamut());
+        assert!(!profile(SRGB_PRIMARIES).is_wide_gamut());
+    }
+
+    /// Display P3 as lcms2 writes it
+    fn display_p3() -> IccProfile {
+        let curve = lcms2::ToneCurve::new_parametric(
+            4,
+            &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045],
+        )
+        .unwrap();
+        let xy = |x, y| lcms2::CIExyY { x, y, Y: 1.0 };
+        let primaries = lcms2::CIExyYTRIPLE {
+            Red: xy(0.680, 0.320),
+            Green: xy(0.265, 0.690),
+            Blue: xy(0.150, 0.060),
+        };
+        let profile =
+            Profile::new_rgb(&xy(0.3127, 0.3290), &primaries, &[&curve, &curve, &curve]).unwrap();
+        IccProfile(profile.icc().unwrap())
+    }
+
+    #[test]
+    fn test_to_srgb() {
+        let profile = display_p3();
+        assert!(profile.is_wide_gamut());
+
+        // Grey is unchanged, colours become more saturated and alpha is left alone
+        let pixels = [128, 128, 128, 255, 200, 100, 100, 128, 100, 200, 100, 7];
+        let image = Image::from_u8(&pixels, 3, 1, ColorSpace::RGBA);
+        let srgb = profile.to_srgb(&image).unwrap();
+        assert_eq!(
+            srgb.flatten_to_u8()[0],
+            [128, 128, 128, 255, 215, 93, 97, 128, 45, 203, 86, 7]
+        );
+
+        let image = Image::from_u16(&[51_400, 25_700, 25_700], 1, 1, ColorSpace::RGB);
+        let srgb = profile.to_srgb(&image).unwrap();
+        assert_eq!(srgb.depth(), BitDepth::Sixteen);
+        assert_eq!(srgb.flatten_frames::<u16>()[0], [55_227, 23_797, 24_834]);
+    }
+
+    #[test]
+    fn test_embed_and_read() {
+        let profile = profile(SRGB_PRIMARIES);
+
+        let jpeg = [
+            &[0xFF, 0xD8, 0xFF, APP0, 0, 4, 0, 0][..],
+            &[0xFF, 0xDA, 0, 2, 0xFF, 0xD9],
+        ]
+        .concat();
+        let embedded = profile.embed(OutputFormat::Jpeg, &jpeg).unwrap();
+        assert_eq!(IccProfile::read(&embedded), Some(profile.clone()));
+        assert_eq!(&embedded[..8], &jpeg[..8]);
+
+        // A lossless 3x2 image with alpha
+        let bits: u32 = 2 | (1 << 14) | (1 << 28);
+        let mut vp8l = vec![0x2F];
+        vp8l.extend(bits.to_le_bytes());
+        let mut webp = b"RIFF".to_vec();
+        webp.extend((12 + vp8l.len() as u32 + 1).to_le_bytes());
+        webp.extend(b"WEBPVP8L");
+        webp.extend((vp8l.len() as u32).to_le_bytes());
+        webp.extend(&vp8l);
+        webp.push(0);
+        let embedded = profile.embed(OutputFormat::Webp, &webp).unwrap();
+        assert_eq!(IccProfile::read(&embedded), Some(profile));
+        let chunks = webp_chunks(&embedded).unwrap();
+        assert_eq!(
+            chunks[0],
+            (
+                *b"VP8X",
+                &[WEBP_ICC_FLAG | WEBP_ALPHA_FLAG, 0, 0, 0, 2, 0, 0, 1, 0, 0][..]
+            )
+        );
+        assert_eq!(
+            u32::from_le_bytes(embedded[4..8].try_into().unwrap()) as usize,
+            embedded.len() - 8
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use load_image::export::imgref::{ImgVec, ImgVecKind};
+use load_image::export::rgb::ComponentSlice;
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_image::image::Image;
+
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use super::format::OutputFormat;
+
+/// An 8x8 Bayer matrix. Each pixel in a tile is rounded against a different threshold.
+const BAYER: [[u32; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// What happens to images with transparent pixels when they're encoded to a format without an alpha channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    /// Flatten the image onto the background colour
+    Flatten,
+    /// Use PNG rather than JPEG as the fallback so the transparency is kept
+    SwitchFormat,
+}
+
+impl FromStr for Transparency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "flatten" => Ok(Transparency::Flatten),
+            "switch-format" => Ok(Transparency::SwitchFormat),
+            _ => Err(format!(
+                "{s} is not a transparency mode. Expected flatten or switch-format"
+            )),
+        }
+    }
+}
+
+/// The sRGB colour transparent images are flattened onto
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Background(pub [u8; 3]);
+
+impl Default for Background {
+    fn default() -> Self {
+        Self([255, 255, 255])
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [red, green, blue] = self.0;
+        write!(f, "#{red:02x}{green:02x}{blue:02x}")
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        let channel = |index: usize| {
+            hex.get(index * 2..index * 2 + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(1), channel(2)) {
+            (6, Some(red), Some(green), Some(blue)) => Ok(Self([red, green, blue])),
+            _ => Err(format!(
+                "{s} is not a background colour. Expected a hex colour such as #ffffff"
+            )),
+        }
+    }
+}
+
+/// A decoded image and whether any of its pixels are at least partly transparent.
+/// An alpha channel which is opaque everywhere doesn't count.
+pub struct DecodedImage {
+    pub image: Image,
+    pub transparent: bool,
+}
+
+/// Converts an image decoded by load_image, which stores pixels in rows with a stride, to a zune image.
+/// We use load_image because it uses mozjpeg under the hood (which means we can use jpegli) and mozjpeg is a painful library to use.
+pub fn from_loaded_image(image: load_image::Image) -> DecodedImage {
+    from_imgvec(image.into_imgvec())
+}
+
+/// Copies the pixels into a zune image, finding whether any are transparent as they're copied
+fn from_imgvec(pixels: ImgVecKind) -> DecodedImage {
+    fn convert<P: ComponentSlice<T>, T: Copy + PartialOrd>(
+        pixels: ImgVec<P>,
+        colorspace: ColorSpace,
+        opaque: T,
+        image: fn(&[T], usize, usize, ColorSpace) -> Image,
+    ) -> DecodedImage {
+        let (pixels, width, height) = pixels.into_contiguous_buf();
+        let alpha = has_alpha(colorspace);
+        let mut transparent = false;
+        let pixels = pixels
+            .iter()
+            .flat_map(|pixel| {
+                let components = pixel.as_slice();
+                transparent |= alpha && components.last().is_some_and(|value| *value < opaque);
+                components.iter().copied()
+            })
+            .collect::<Vec<T>>();
+        DecodedImage {
+            image: image(&pixels, width, height, colorspace),
+            transparent,
+        }
+    }
+
+    match pixels {
+        ImgVecKind::RGB8(pixels) => convert(pixels, ColorSpace::RGB, u8::MAX, Image::from_u8),
+        ImgVecKind::RGBA8(pixels) => convert(pixels, ColorSpace::RGBA, u8::MAX, Image::from_u8),
+        ImgVecKind::RGB16(pixels) => convert(pixels, ColorSpace::RGB, u16::MAX, Image::from_u16),
+        ImgVecKind::RGBA16(pixels) => convert(pixels, ColorSpace::RGBA, u16::MAX, Image::from_u16),
+        ImgVecKind::GRAY8(pixels) => convert(pixels, ColorSpace::Luma, u8::MAX, Image::from_u8),
+        ImgVecKind::GRAY16(pixels) => convert(pixels, ColorSpace::Luma, u16::MAX, Image::from_u16),
+        ImgVecKind::GRAYA8(pixels) => convert(pixels, ColorSpace::LumaA, u8::MAX, Image::from_u8),
+        ImgVecKind::GRAYA16(pixels) => {
+            convert(pixels, ColorSpace::LumaA, u16::MAX, Image::from_u16)
+        }
+    }
+}
+
+/// The image with pixels `format` can encode. Alpha is flattened onto `background` for formats without an alpha channel
+/// and 16 bit images are dithered to 8 bits for formats which only take 8.
+pub fn for_format(image: &Image, format: OutputFormat, background: Background) -> Cow<'_, Image> {
+    let colorspace = image.colorspace();
+    let sixteen_bit = image.depth() == BitDepth::Sixteen;
+    let flatten = has_alpha(colorspace) && !format.supports_alpha();
+    let reduce = sixteen_bit && !format.supports_sixteen_bit();
+    if !flatten && !reduce {
+        return Cow::Borrowed(image);
+    }
+
+    let (width, height) = image.dimensions();
+    // Work at 16 bits so flattening doesn't round before dithering
+    let pixels = if sixteen_bit {
+        image.flatten_frames::<u16>().swap_remove(0)
+    } else {
+        image
+            .flatten_to_u8()
+            .swap_remove(0)
+            .into_iter()
+            .map(|value| u16::from(value) * 257)
+            .collect()
+    };
+    let (pixels, colorspace) = if flatten {
+        (
+            flatten_alpha(&pixels, colorspace, background),
+            ColorSpace::RGB,
+        )
+    } else {
+        (pixels, colorspace)
+    };
+    Cow::Owned(if sixteen_bit && !reduce {
+        Image::from_u16(&pixels, width, height, colorspace)
+    } else {
+        Image::from_u8(
+            &dither(&pixels, width, colorspace),
+            width,
+            height,
+            colorspace,
+        )
+    })
+}
+
+/// Whether the colour space's last channel is alpha. Decoded images only use these two.
+fn has_alpha(colorspace: ColorSpace) -> bool {
+    matches!(colorspace, ColorSpace::RGBA | ColorSpace::LumaA)
+}
+
+/// Composites 16 bit RGBA or grey and alpha pixels onto an opaque background, returning RGB pixels.
+/// Like browsers, this blends the gamma encoded values.
+fn flatten_alpha(pixels: &[u16], colorspace: ColorSpace, background: Background) -> Vec<u16> {
+    let components = colorspace.num_components();
+    let background = background.0.map(|channel| u32::from(channel) * 257);
+    pixels
+        .chunks_exact(components)
+        .flat_map(|pixel| {
+            let alpha = u32::from(pixel[components - 1]);
+            let colour = match colorspace {
+                ColorSpace::LumaA => [pixel[0]; 3],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            };
+            colour
+                .into_iter()
+                .zip(background)
+                .map(move |(colour, background)| {
+                    let blended = u32::from(colour) * alpha + background * (65_535 - alpha);
+                    ((blended + 32_767) / 65_535) as u16
+                })
+        })
+        .collect()
+}
+
+/// Reduces 16 bit pixels to 8 bits with ordered dithering, so smooth gradients don't band.
+/// Alpha is rounded rather than dithered.
+fn dither(pixels: &[u16], width: usize, colorspace: ColorSpace) -> Vec<u8> {
+    let components = colorspace.num_components();
+    let alpha = has_alpha(colorspace).then_some(components - 1);
+    pixels
+        .chunks_exact(components)
+        .enumerate()
+        .flat_map(|(index, pixel)| {
+            let threshold = 2 * BAYER[(index / width) % 8][(index % width) % 8] + 1;
+            pixel.iter().enumerate().map(move |(channel, value)| {
+                let value = u32::from(*value);
+                if Some(channel) == alpha {
+                    ((value + 128) / 257) as u8
+                } else {
+                    // floor(value / 257 + threshold / 128), which never exceeds 255
+                    ((value * 128 + 257 * threshold) / (257 * 128)) as u8
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use load_image::export::rgb::alt::GrayAlpha;
+
+    #[test]
+    fn test_grey_and_alpha_keeps_sixteen_bits() {
+        let pixels = vec![GrayAlpha(1000, 65_535), GrayAlpha(60_000, 65_535)];
+        let decoded = from_imgvec(ImgVecKind::GRAYA16(ImgVec::new(pixels, 2, 1)));
+        assert_eq!(decoded.image.colorspace(), ColorSpace::LumaA);
+        assert_eq!(decoded.image.depth(), BitDepth::Sixteen);
+        assert_eq!(
+            decoded.image.flatten_frames::<u16>()[0],
+            [1000, 65_535, 60_000, 65_535]
+        );
+        assert!(!decoded.transparent);
+
+        let pixels = vec![GrayAlpha(1000, 65_535), GrayAlpha(60_000, 65_534)];
+        assert!(from_imgvec(ImgVecKind::GRAYA16(ImgVec::new(pixels, 2, 1))).transparent);
+    }
+
+    #[test]
+    fn test_alpha_is_only_flattened_for_formats_without_it() {
+        // Half transparent red
+        let image = Image::from_u8(&[255, 0, 0, 128], 1, 1, ColorSpace::RGBA);
+        let png = for_format(&image, OutputFormat::Png, Background::default());
+        assert!(matches!(png, Cow::Borrowed(_)));
+
+        let jpeg = for_format(&image, OutputFormat::Jpeg, Background::default());
+        assert_eq!(jpeg.colorspace(), ColorSpace::RGB);
+        assert_eq!(jpeg.flatten_to_u8()[0], [255, 127, 127]);
+    }
+
+    #[test]
+    fn test_flatten_alpha() {
+        // Opaque red, half transparent red and fully transparent grey
+        let pixels = [65_535, 0, 0, 65_535, 65_535, 0, 0, 32_768, 257, 257, 257, 0];
+        let flattened = flatten_alpha(&pixels, ColorSpace::RGBA, Background([255, 255, 255]));
+        assert_eq!(
+            flattened,
+            [65_535, 0, 0, 65_535, 32_767, 32_767, 65_535, 65_535, 65_535]
+        );
+
+        let grey = flatten_alpha(&[0, 0], ColorSpace::LumaA, Background([255, 0, 0]));
+        assert_eq!(grey, [65_535, 0, 0]);
+    }
+
+    #[test]
+    fn test_dither() {
+        // Exact 8 bit values are left alone
+        let exact = [0, 257 * 100, 65_535].repeat(64);
+        let dithered = dither(&exact, 8, ColorSpace::RGB);
+        assert!(dithered.chunks_exact(3).all(|pixel| pixel == [0, 100, 255]));
+
+        // A value halfway between two levels rounds up for half of each tile
+        let halfway = vec![257 * 100 + 128; 64];
+        let dithered = dither(&halfway, 8, ColorSpace::Luma);
+        assert_eq!(dithered.iter().filter(|value| **value == 101).count(), 32);
+        assert!(dithered.iter().all(|value| *value == 100 || *value == 101));
+
+        // Alpha is rounded
+        let dithered = dither(&[257 * 100 + 128, 257 * 100 + 128], 1, ColorSpace::LumaA);
+        assert_eq!(dithered[1], 100);
+    }
+
+    #[test]
+    fn test_background() {
+        assert_eq!("#ff8000".parse(), Ok(Background([255, 128, 0])));
+        assert_eq!("FF8000".parse(), Ok(Background([255, 128, 0])));
+        assert_eq!(Background([255, 128, 0]).to_string(), "#ff8000");
+        assert!("#fff".parse::<Background>().is_err());
+        assert!("white".parse::<Background>().is_err());
+    }
+}
@@ -0,0 +1,566 @@
+use serde::{Deserialize, Serialize};
+use zune_core::bit_depth::BitDepth;
+use zune_image::image::Image;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A rectangle of an image, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    /// The largest region of a `width` by `height` image with `aspect_ratio`, as close to centred on `focus` as fits
+    pub fn with_aspect_ratio(
+        width: usize,
+        height: usize,
+        aspect_ratio: AspectRatio,
+        focus: FocalPoint,
+    ) -> Self {
+        Self::scaled_with_aspect_ratio(width, height, aspect_ratio, focus, 1.0)
+    }
+
+    /// A region `scale` times the size of [`Region::with_aspect_ratio`], as close to centred on `focus` as fits
+    pub fn scaled_with_aspect_ratio(
+        width: usize,
+        height: usize,
+        aspect_ratio: AspectRatio,
+        focus: FocalPoint,
+        scale: f64,
+    ) -> Self {
+        let ratio = aspect_ratio.ratio();
+        let (crop_width, crop_height) = if ratio > width as f64 / height as f64 {
+            (width as f64, width as f64 / ratio)
+        } else {
+            (height as f64 * ratio, height as f64)
+        };
+        let crop_width = ((crop_width * scale).round() as usize).clamp(1, width);
+        let crop_height = ((crop_height * scale).round() as usize).clamp(1, height);
+        let offset = |size: usize, crop: usize, focus: f64| {
+            (size as f64 * focus - crop as f64 / 2.0)
+                .round()
+                .clamp(0.0, (size - crop) as f64) as usize
+        };
+        Self {
+            x: offset(width, crop_width, focus.x),
+            y: offset(height, crop_height, focus.y),
+            width: crop_width,
+            height: crop_height,
+        }
+    }
+
+    /// `rectangle` of a `width` by `height` image
+    pub fn from_rectangle(width: usize, height: usize, rectangle: CropRectangle) -> Self {
+        let x = (rectangle.x * width as f64).round() as usize;
+        let y = (rectangle.y * height as f64).round() as usize;
+        Self {
+            x,
+            y,
+            width: ((rectangle.width * width as f64).round() as usize)
+                .clamp(1, width - x.min(width - 1)),
+            height: ((rectangle.height * height as f64).round() as usize)
+                .clamp(1, height - y.min(height - 1)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Parses `count` comma separated numbers
+fn parse_numbers(s: &str, count: usize) -> Option<Vec<f64>> {
+    let numbers = s
+        .split(',')
+        .map(|n| n.trim().parse::<f64>().ok().filter(|n| n.is_finite()))
+        .collect::<Option<Vec<_>>>()?;
+    (numbers.len() == count).then_some(numbers)
+}
+
+/// The width and height of social media link previews, as Facebook, LinkedIn and X recommend
+pub const SOCIAL_CARD_SIZE: (usize, usize) = (1200, 630);
+
+/// The shape of a crop, width to height. e.g. `1:1` or `16:9`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AspectRatio {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl AspectRatio {
+    pub const SQUARE: AspectRatio = AspectRatio {
+        width: 1.0,
+        height: 1.0,
+    };
+
+    pub fn ratio(&self) -> f64 {
+        self.width / self.height
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once(':')
+            .and_then(|(width, height)| {
+                Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+            })
+            .filter(|(width, height): &(f64, f64)| *width > 0.0 && *height > 0.0)
+            .map(|(width, height)| Self { width, height })
+            .ok_or_else(|| format!("{s} is not an aspect ratio. Expected width:height. e.g. 16:9"))
+    }
+}
+
+/// The part of an image to keep in view, as fractions of its width and height. e.g. `0.5,0.5` is the centre
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FocalPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl FocalPoint {
+    pub const CENTRE: FocalPoint = FocalPoint { x: 0.5, y: 0.5 };
+}
+
+impl FromStr for FocalPoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_numbers(s, 2)
+            .filter(|n| n.iter().all(|n| (0.0..=1.0).contains(n)))
+            .map(|n| Self { x: n[0], y: n[1] })
+            .ok_or_else(|| format!("{s} is not a focal point. Expected x,y as fractions between 0 and 1. e.g. 0.5,0.3"))
+    }
+}
+
+/// An explicit crop, as fractions of an image's width and height. e.g. `0.1,0,0.8,1`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CropRectangle {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl FromStr for CropRectangle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_numbers(s, 4)
+            .filter(|n| {
+                n.iter().all(|n| (0.0..=1.0).contains(n))
+                    && n[2] > 0.0
+                    && n[3] > 0.0
+                    && n[0] + n[2] <= 1.0
+                    && n[1] + n[3] <= 1.0
+            })
+            .map(|n| Self {
+                x: n[0],
+                y: n[1],
+                width: n[2],
+                height: n[3],
+            })
+            .ok_or_else(|| {
+                format!("{s} is not a crop rectangle. Expected x,y,width,height as fractions of the image. e.g. 0.1,0,0.8,1")
+            })
+    }
+}
+
+impl TryFrom<String> for AspectRatio {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<AspectRatio> for String {
+    fn from(value: AspectRatio) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
+impl TryFrom<String> for FocalPoint {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<FocalPoint> for String {
+    fn from(value: FocalPoint) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for FocalPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+impl TryFrom<String> for CropRectangle {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<CropRectangle> for String {
+    fn from(value: CropRectangle) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for CropRectangle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// An art-directed crop of an image, shown instead of the full frame when `media` matches.
+/// ```toml
+/// [[layouts.content.crops]]
+/// media = "(max-width: 600px)"
+/// aspect_ratio = "1:1"
+/// focus = "0.5,0.4"
+/// ```
+/// With `smart = true` instead of a `focus`, the most interesting region is found with [`crate::image::smartcrop`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "CropDefinition")]
+pub struct Crop {
+    /// The media query the crop is used for. e.g. `(max-width: 600px)`
+    pub media: String,
+    /// The `sizes` attribute of the crop's sources. Defaults to the layout's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sizes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<AspectRatio>,
+    /// Where the crop is centred when cropping to `aspect_ratio`. Defaults to the centre of the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focus: Option<FocalPoint>,
+    /// Whether the most interesting region with `aspect_ratio` is found automatically instead of using `focus`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub smart: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rectangle: Option<CropRectangle>,
+}
+
+/// A [`Crop`] as written in configuration, before it is checked
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CropDefinition {
+    media: String,
+    sizes: Option<String>,
+    aspect_ratio: Option<AspectRatio>,
+    focus: Option<FocalPoint>,
+    #[serde(default)]
+    smart: bool,
+    rectangle: Option<CropRectangle>,
+}
+
+impl TryFrom<CropDefinition> for Crop {
+    type Error = String;
+
+    fn try_from(definition: CropDefinition) -> Result<Self, Self::Error> {
+        if definition.smart && (definition.focus.is_some() || definition.aspect_ratio.is_none()) {
+            return Err(format!(
+                "The smart crop for {} needs an aspect_ratio and finds its own focus",
+                definition.media
+            ));
+        }
+        match (
+            definition.aspect_ratio,
+            definition.focus,
+            definition.rectangle,
+        ) {
+            (Some(_), _, None) | (None, None, Some(_)) => Ok(Self {
+                media: definition.media,
+                sizes: definition.sizes,
+                aspect_ratio: definition.aspect_ratio,
+                focus: definition.focus,
+                smart: definition.smart,
+                rectangle: definition.rectangle,
+            }),
+            (None, _, None) => Err(format!(
+                "The crop for {} needs an aspect_ratio or a rectangle",
+                definition.media
+            )),
+            _ => Err(format!(
+                "The crop for {} can have an aspect_ratio and focus or a rectangle, not both",
+                definition.media
+            )),
+        }
+    }
+}
+
+impl Crop {
+    /// A smart crop to `aspect_ratio` for an image shown outside a `picture`, so without a media query.
+    /// e.g. a social card
+    pub fn smart(aspect_ratio: AspectRatio) -> Self {
+        Self {
+            media: String::new(),
+            sizes: None,
+            aspect_ratio: Some(aspect_ratio),
+            focus: None,
+            smart: true,
+            rectangle: None,
+        }
+    }
+
+    /// The region of a `width` by `height` image this crop keeps.
+    /// An image's `hints` take precedence over the crop's own `focus`, and crops to an aspect ratio stay inside
+    /// the hinted rectangle. A crop to a fixed rectangle keeps its size but is centred on the hinted subject.
+    pub fn region(&self, width: usize, height: usize, hints: &CropHints) -> Region {
+        let whole = Region {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        match (self.rectangle, self.aspect_ratio) {
+            (Some(rectangle), _) => {
+                let region = Region::from_rectangle(width, height, rectangle);
+                let Some(focus) = hints.centre() else {
+                    return region;
+                };
+                // The rectangle keeps its size but is moved onto the hinted subject, inside the hinted
+                // rectangle if it fits there
+                let bounds = hints
+                    .rectangle
+                    .map(|rectangle| Region::from_rectangle(width, height, rectangle))
+                    .filter(|bounds| bounds.width >= region.width && bounds.height >= region.height)
+                    .unwrap_or(whole);
+                let offset = |start: usize, size: usize, crop: usize, centre: f64| {
+                    (centre - crop as f64 / 2.0)
+                        .round()
+                        .clamp(start as f64, (start + size - crop) as f64)
+                        as usize
+                };
+                Region {
+                    x: offset(bounds.x, bounds.width, region.width, focus.x * width as f64),
+                    y: offset(
+                        bounds.y,
+                        bounds.height,
+                        region.height,
+                        focus.y * height as f64,
+                    ),
+                    ..region
+                }
+            }
+            (None, Some(aspect_ratio)) => {
+                let bounds = hints.rectangle.map_or(whole, |rectangle| {
+                    Region::from_rectangle(width, height, rectangle)
+                });
+                let focus = hints.focus.or(self.focus).unwrap_or(FocalPoint::CENTRE);
+                // The focus relative to the bounds
+                let focus = FocalPoint {
+                    x: ((focus.x * width as f64 - bounds.x as f64) / bounds.width as f64)
+                        .clamp(0.0, 1.0),
+                    y: ((focus.y * height as f64 - bounds.y as f64) / bounds.height as f64)
+                        .clamp(0.0, 1.0),
+                };
+                let region =
+                    Region::with_aspect_ratio(bounds.width, bounds.height, aspect_ratio, focus);
+                Region {
+                    x: bounds.x + region.x,
+                    y: bounds.y + region.y,
+                    ..region
+                }
+            }
+            (None, None) => whole,
+        }
+    }
+}
+
+/// Where the subject of an image is, given by hand for a single image. Every crop of the image honours them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CropHints {
+    /// The point crops are centred on
+    pub focus: Option<FocalPoint>,
+    /// The part of the image crops are taken from
+    pub rectangle: Option<CropRectangle>,
+}
+
+impl CropHints {
+    pub fn is_empty(&self) -> bool {
+        self.focus.is_none() && self.rectangle.is_none()
+    }
+
+    /// These hints, falling back to `other` for any that aren't set
+    pub fn or(self, other: CropHints) -> Self {
+        Self {
+            focus: self.focus.or(other.focus),
+            rectangle: self.rectangle.or(other.rectangle),
+        }
+    }
+
+    /// Where the subject is: the focus, or the middle of the hinted rectangle
+    fn centre(&self) -> Option<FocalPoint> {
+        self.focus.or_else(|| {
+            self.rectangle.map(|rectangle| FocalPoint {
+                x: rectangle.x + rectangle.width / 2.0,
+                y: rectangle.y + rectangle.height / 2.0,
+            })
+        })
+    }
+
+    /// The CSS `object-position` keeping the subject in view when an image is cropped by the browser.
+    /// e.g. `50% 30%`
+    pub fn object_position(&self) -> Option<String> {
+        let focus = self.centre()?;
+        let percent = |fraction: f64| (fraction * 1000.0).round() / 10.0;
+        Some(format!("{}% {}%", percent(focus.x), percent(focus.y)))
+    }
+}
+
+/// Copies `region` of `image` into a new image
+pub fn crop_image(image: &Image, region: Region) -> Image {
+    fn crop<T: Copy>(
+        pixels: &[T],
+        image_width: usize,
+        components: usize,
+        region: Region,
+    ) -> Vec<T> {
+        pixels
+            .chunks_exact(image_width * components)
+            .skip(region.y)
+            .take(region.height)
+            .flat_map(|row| &row[region.x * components..(region.x + region.width) * components])
+            .copied()
+            .collect()
+    }
+
+    let (width, _) = image.dimensions();
+    let colorspace = image.colorspace();
+    let components = colorspace.num_components();
+    match image.depth() {
+        BitDepth::Sixteen => {
+            let pixels = image.flatten_frames::<u16>().swap_remove(0);
+            Image::from_u16(
+                &crop(&pixels, width, components, region),
+                region.width,
+                region.height,
+                colorspace,
+            )
+        }
+        _ => {
+            let pixels = image.flatten_to_u8().swap_remove(0);
+            Image::from_u8(
+                &crop(&pixels, width, components, region),
+                region.width,
+                region.height,
+                colorspace,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_with_aspect_ratio() {
+        let square = "1:1".parse().unwrap();
+        assert_eq!(
+            Region::with_aspect_ratio(1600, 900, square, FocalPoint::CENTRE),
+            Region {
+                x: 350,
+                y: 0,
+                width: 900,
+                height: 900
+            }
+        );
+        // The crop is moved towards the focal point but stays inside the image
+        assert_eq!(
+            Region::with_aspect_ratio(1600, 900, square, "0.95,0.5".parse().unwrap()).x,
+            700
+        );
+        let wide = "16:9".parse().unwrap();
+        assert_eq!(
+            Region::with_aspect_ratio(900, 900, wide, "0.5,0.1".parse().unwrap()),
+            Region {
+                x: 0,
+                y: 0,
+                width: 900,
+                height: 506
+            }
+        );
+    }
+
+    #[test]
+    fn test_crops_honour_hints() {
+        let crop: Crop =
+            toml::from_str("media = \"(max-width: 600px)\"\naspect_ratio = \"1:1\"").unwrap();
+        let hints = CropHints {
+            focus: Some("0.9,0.5".parse().unwrap()),
+            rectangle: Some("0.25,0,0.5,1".parse().unwrap()),
+        };
+        // Kept inside the rectangle even though the focus is outside it
+        assert_eq!(
+            crop.region(1600, 400, &hints),
+            Region {
+                x: 800,
+                y: 0,
+                width: 400,
+                height: 400
+            }
+        );
+        assert_eq!(hints.object_position().as_deref(), Some("90% 50%"));
+
+        // A fixed rectangle keeps its size but moves onto the focus, staying inside the hinted rectangle
+        let crop: Crop =
+            toml::from_str("media = \"(max-width: 600px)\"\nrectangle = \"0,0,0.25,0.5\"").unwrap();
+        assert_eq!(
+            crop.region(1600, 400, &hints),
+            Region {
+                x: 800,
+                y: 100,
+                width: 400,
+                height: 200
+            }
+        );
+        assert_eq!(
+            crop.region(1600, 400, &CropHints::default()),
+            Region {
+                x: 0,
+                y: 0,
+                width: 400,
+                height: 200
+            }
+        );
+    }
+
+    #[test]
+    fn test_crop_definitions() {
+        let crop = |toml: &str| toml::from_str::<Crop>(toml);
+        assert!(crop("media = \"(max-width: 600px)\"\naspect_ratio = \"1:1\"").is_ok());
+        assert!(crop("media = \"(max-width: 600px)\"\nrectangle = \"0.1,0,0.8,1\"").is_ok());
+        assert!(crop("media = \"(max-width: 600px)\"").is_err());
+        assert!(crop("media = \"(max-width: 600px)\"\nrectangle = \"0.5,0,0.8,1\"").is_err());
+        assert!(crop(
+            "media = \"(max-width: 600px)\"\nfocus = \"0.5,0.5\"\nrectangle = \"0,0,1,1\""
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use jpegxl_rs::encoder_builder;
+use rimage::codecs::avif::{AvifEncoder, AvifOptions};
+use rimage::codecs::jpegli::{JpegliEncoder, JpegliOptions};
+use rimage::codecs::oxipng::OxiPngEncoder;
+use rimage::codecs::webp::WebPEncoder;
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_image::image::Image;
+use zune_image::traits::EncoderTrait;
+
+use std::fmt;
+use std::str::FromStr;
+
+const AVIF_QUALITY: f32 = 70.0;
+const JPEG_QUALITY: f32 = 90.0;
+/// The Butteraugli distance JPEG XL files are encoded to. 1 is visually lossless and higher is smaller.
+const JXL_DISTANCE: f32 = 1.0;
+
+/// A format images can be encoded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    Avif,
+    Jpeg,
+    /// JPEG XL
+    Jxl,
+    /// Encoded losslessly. Used as the fallback of transparent images when keeping their alpha.
+    Png,
+    Webp,
+}
+
+impl OutputFormat {
+    /// The format used for the `img` fallback. Every browser can display it.
+    pub const FALLBACK: OutputFormat = OutputFormat::Jpeg;
+    /// The format used for the `img` fallback of transparent images when the format is switched to keep their alpha
+    pub const TRANSPARENT_FALLBACK: OutputFormat = OutputFormat::Png;
+
+    /// The extension (without a leading `.`) of files in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Avif => "avif",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Jxl => "jxl",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    /// The value of a `source` element's `type` attribute for this format
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Avif => "image/avif",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Jxl => "image/jxl",
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+
+    /// A description of the encoder and its settings. Images encoded with different settings look different.
+    pub fn encoder_settings(&self) -> String {
+        match self {
+            OutputFormat::Avif => format!("ravif quality={AVIF_QUALITY}"),
+            OutputFormat::Jpeg => format!("jpegli quality={JPEG_QUALITY}"),
+            OutputFormat::Jxl => format!("libjxl distance={JXL_DISTANCE}"),
+            OutputFormat::Png => "oxipng default".to_owned(),
+            OutputFormat::Webp => "libwebp default".to_owned(),
+        }
+    }
+
+    /// Whether a colour profile can be embedded in files in this format. Other formats are always sRGB.
+    pub fn embeds_color_profile(&self) -> bool {
+        matches!(self, OutputFormat::Jpeg | OutputFormat::Webp)
+    }
+
+    /// Whether files in this format have an alpha channel. Transparent images are flattened for other formats.
+    pub fn supports_alpha(&self) -> bool {
+        !matches!(self, OutputFormat::Jpeg)
+    }
+
+    /// Whether the encoder takes 16 bits per channel. Images are dithered to 8 bits for other formats.
+    pub fn supports_sixteen_bit(&self) -> bool {
+        matches!(self, OutputFormat::Jxl)
+    }
+
+    /// Encodes the image, returning the encoded file's bytes
+    pub fn encode(&self, image: &Image) -> Result<Vec<u8>> {
+        let mut encoder: Box<dyn EncoderTrait> = match self {
+            OutputFormat::Avif => Box::new(AvifEncoder::new_with_options(AvifOptions {
+                quality: AVIF_QUALITY,
+                ..AvifOptions::default()
+            })),
+            OutputFormat::Jpeg => Box::new(JpegliEncoder::new_with_options(JpegliOptions {
+                quality: JPEG_QUALITY,
+                ..JpegliOptions::default()
+            })),
+            OutputFormat::Jxl => {
+                return encode_jxl(image)
+                    .with_context(|| format!("Failed to encode image as {self}"))
+            }
+            OutputFormat::Png => Box::new(OxiPngEncoder::new()),
+            OutputFormat::Webp => Box::new(WebPEncoder::new()),
+        };
+        encoder
+            .encode(image)
+            .with_context(|| format!("Failed to encode image as {self}"))
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "avif" => Ok(OutputFormat::Avif),
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "jxl" | "jpegxl" | "jpeg-xl" => Ok(OutputFormat::Jxl),
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::Webp),
+            _ => Err(format!(
+                "{s} is not a supported output format. Expected avif, jpeg, jxl, png or webp"
+            )),
+        }
+    }
+}
+
+/// Appends a segment with `marker` and `payload` to a JPEG being written
+pub(crate) fn push_jpeg_segment(jpeg: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    jpeg.extend([0xFF, marker]);
+    jpeg.extend(((payload.len() + 2) as u16).to_be_bytes());
+    jpeg.extend_from_slice(payload);
+}
+
+/// Encodes the image as a lossy JPEG XL with libjxl, which zune-image can only write losslessly.
+/// Grey images are encoded as RGB.
+fn encode_jxl(image: &Image) -> Result<Vec<u8>> {
+    /// Repeats the grey channel of each pixel as red, green and blue, keeping any alpha
+    fn rgb<T: Copy>(pixels: Vec<T>, colorspace: ColorSpace) -> Vec<T> {
+        match colorspace {
+            ColorSpace::Luma => pixels.into_iter().flat_map(|grey| [grey; 3]).collect(),
+            ColorSpace::LumaA => pixels
+                .chunks_exact(2)
+                .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+                .collect(),
+            _ => pixels,
+        }
+    }
+
+    let (width, height) = image.dimensions();
+    let colorspace = image.colorspace();
+    let mut encoder = encoder_builder()
+        .quality(JXL_DISTANCE)
+        .has_alpha(matches!(colorspace, ColorSpace::RGBA | ColorSpace::LumaA))
+        .build()?;
+    let encoded = match image.depth() {
+        BitDepth::Sixteen => {
+            let pixels = rgb(image.flatten_frames::<u16>().swap_remove(0), colorspace);
+            encoder
+                .encode::<u16, u16>(&pixels, width as u32, height as u32)?
+                .data
+        }
+        _ => {
+            let pixels = rgb(image.flatten_to_u8().swap_remove(0), colorspace);
+            encoder
+                .encode::<u8, u8>(&pixels, width as u32, height as u32)?
+                .data
+        }
+    };
+    Ok(encoded)
+}
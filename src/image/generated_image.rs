@@ -1,5 +1,9 @@
 use std::path::PathBuf;
 
+use super::conversion::Background;
+use super::crop::Region;
+use super::format::OutputFormat;
+use super::variant::Variant;
 use crate::upload::uploadable::Uploadable;
 
 #[derive(Debug, Clone)]
@@ -7,18 +11,55 @@ pub struct GeneratedImage {
     pub width: usize,
     pub height: usize,
     pub path: PathBuf,
+    pub format: OutputFormat,
+    pub variant: Variant,
+    /// The part of the input image resized, if it was cropped
+    pub region: Option<Region>,
+    /// Whether the file keeps the input image's colours and embeds its colour profile, rather than being sRGB
+    pub embeds_profile: bool,
+    /// The colour transparent pixels are flattened onto, if the image is transparent and the format has no alpha channel
+    pub background: Option<Background>,
     pub s3_path: Option<String>,
 }
 
 impl GeneratedImage {
-    pub fn new(width: usize, height: usize, path: PathBuf) -> Self {
+    pub fn new(
+        width: usize,
+        height: usize,
+        path: PathBuf,
+        format: OutputFormat,
+        variant: Variant,
+    ) -> Self {
         Self {
             width,
             height,
             path,
+            format,
+            variant,
+            region: None,
+            embeds_profile: false,
+            background: None,
             s3_path: None,
         }
     }
+
+    pub fn with_region(self, region: Region) -> Self {
+        Self {
+            region: Some(region),
+            ..self
+        }
+    }
+
+    pub fn with_embedded_profile(self, embeds_profile: bool) -> Self {
+        Self {
+            embeds_profile,
+            ..self
+        }
+    }
+
+    pub fn with_background(self, background: Option<Background>) -> Self {
+        Self { background, ..self }
+    }
 }
 
 impl Uploadable for GeneratedImage {
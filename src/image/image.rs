@@ -1,36 +1,44 @@
+use super::cache::{Cache, CachedSource, SourceManifest};
+use super::color::{ColorManagement, IccProfile};
+use super::conversion::{self, Transparency};
+use super::crop::{crop_image, AspectRatio, Crop, Region, SOCIAL_CARD_SIZE};
+use super::format::OutputFormat;
 use super::generated_image::GeneratedImage;
 use super::image_info::ImageInfo;
-use crate::data::exif::Exif;
+use super::orientation::orient_image;
+use super::placeholder::{self, make_placeholder, PlaceholderKind};
+use super::smartcrop::Saliency;
+use super::variant::Variant;
+use crate::config::Config;
+use crate::data::exif::{Exif, Orientation, EXIF_TAGS};
+use crate::data::metadata::Metadata;
 use crate::options::Options;
 use crate::original_image::OriginalImage;
+use crate::sidecar::{Manifest, Sidecar};
 use crate::AppError;
 use crate::Metrics;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fs::{create_dir_all, File};
 use std::io::BufReader;
 use std::io::Read;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
 
-use anyhow::{Context, Result};
-use indicatif::ProgressBar;
+use anyhow::{bail, Context, Result};
+use indicatif::{MultiProgress, ProgressBar};
 
-use load_image::export::imgref::ImgVecKind;
-use load_image::export::rgb::ComponentSlice;
 use load_image::Loader;
-use log::debug;
+use log::{debug, warn};
 
 use log::info;
+use rayon::prelude::*;
 
 use peck_exif::exif::Mode;
 use peck_exif::exif::{create_list_from_vec, Exif as PeckExif};
-use rimage::codecs::jpegli::JpegliEncoder;
-use rimage::codecs::jpegli::JpegliOptions;
-use zune_core::colorspace::ColorSpace;
 use zune_image::image::Image;
-use zune_image::traits::EncoderTrait;
 use zune_image::traits::OperationsTrait;
 
 // TODO: Replace this with something more native?
@@ -58,7 +66,12 @@ lazy_static::lazy_static! {
     pub static ref MIME_TABLE: HashMap<&'static str, &'static str> = {
         let mut hm = HashMap::new();
         hm.insert("avif", "image/avif");
+        hm.insert("bmp", "image/bmp");
+        hm.insert("ff", "image/x-farbfeld");
+        hm.insert("hdr", "image/vnd.radiance");
         hm.insert("png", "image/png");
+        hm.insert("psd", "image/vnd.adobe.photoshop");
+        hm.insert("qoi", "image/qoi");
         hm.insert("jpg", "image/jpeg");
         hm.insert("jpeg", "image/jpeg");
         hm.insert("jxl", "image/jxl");
@@ -67,116 +80,38 @@ lazy_static::lazy_static! {
     };
 }
 
-/// Given a load_image::Image return a zune_image by translating from the img_vec (stride based) to the zune_image format
-/// We use load_image::Image because of the fact it uses mozjpeg under the hood (which means we can use jpegli) and mozjpeg is a painful library to use.
-fn zune_image_from_loaded_image(image: load_image::Image) -> Image {
-    match image.into_imgvec() {
-        ImgVecKind::RGB8(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u8(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u8>>(),
-                width,
-                height,
-                ColorSpace::RGB,
-            )
-        }
-        ImgVecKind::RGBA8(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u8(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u8>>(),
-                width,
-                height,
-                ColorSpace::RGBA,
-            )
-        }
-        ImgVecKind::RGB16(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u16(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u16>>(),
-                width,
-                height,
-                ColorSpace::RGB,
-            )
-        }
-        ImgVecKind::RGBA16(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u16(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u16>>(),
-                width,
-                height,
-                ColorSpace::RGBA,
-            )
-        }
-        ImgVecKind::GRAY8(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u8(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u8>>(),
-                width,
-                height,
-                ColorSpace::Luma,
-            )
-        }
-        ImgVecKind::GRAY16(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u16(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u16>>(),
-                width,
-                height,
-                ColorSpace::Luma,
-            )
-        }
-        ImgVecKind::GRAYA8(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u8(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u8>>(),
-                width,
-                height,
-                ColorSpace::LumaA,
-            )
-        }
-        ImgVecKind::GRAYA16(pixels) => {
-            let (pixels, width, height) = pixels.into_contiguous_buf();
-            zune_image::image::Image::from_u16(
-                &pixels
-                    .into_iter()
-                    .flat_map(|px| px.as_slice().to_owned())
-                    .collect::<Vec<u16>>(),
-                width,
-                height,
-                ColorSpace::RGBA,
-            )
+/// Shared state used whilst processing a batch of images
+pub struct ProcessingResources<'a> {
+    pub progress: &'a MultiProgress,
+    pub cache: Option<&'a Cache>,
+    pub config: &'a Config,
+    /// The manifest of the folder the images are in
+    pub manifest: &'a Manifest,
+}
+
+/// Reads the EXIF of the image at `path`. Images without EXIF, such as most PNGs, have none rather than failing.
+fn read_exif(path: &Path) -> Exif {
+    match PeckExif::new(
+        path,
+        Mode::Whitelist(create_list_from_vec(EXIF_TAGS.to_vec())),
+    ) {
+        Ok(exif) => Exif::from(exif),
+        Err(error) => {
+            debug!("No EXIF read from {}: {:?}", path.to_string_lossy(), error);
+            Exif::default()
         }
     }
 }
 
 /// Process the image provided in the path.
-/// Iterate through the sizes and create a scaled image for each
+/// Iterate through the sizes and create a scaled image for each.
+/// Variants found in the cache are reused and only missing variants are encoded.
 pub fn process_image(
     input_file: &Path,
     output_directory: &Path,
     options: &Options,
-    m: &mut Metrics,
+    m: &Metrics,
+    resources: &ProcessingResources,
 ) -> Result<ImageInfo> {
     // TODO: Special handle how we decode JPEGs but otherwise use a generic decoder
     let file = File::open(input_file)
@@ -187,45 +122,142 @@ pub fn process_image(
         .read_to_end(&mut buf)
         .with_context(|| format!("Failed to read file {}", &input_file.to_string_lossy()))?;
 
-    debug!("Decoding {}", &input_file.to_string_lossy());
-
-    // TODO: Find a way to use Jpegli without having to do this
-    let decoded_image = Loader::new()
-        .metadata(true)
-        .load_data(buf.as_ref())
-        .with_context(|| format!("Failed to load image {}", &input_file.to_string_lossy()))?;
-
-    // TODO: Make this typed
-    // TODO: EXIF should be an option
-    let allow_list = create_list_from_vec(vec![
-        "FocalLength",
-        "ISO",
-        "CameraModelName",
-        "FNumber",
-        "ShutterSpeed",
-        "FocalLengthIn35mmFormat",
-        "HDR",
-        "Megapixels",
-        "LensID",
-        "LensType",
-        "Make",
-        "DeviceManufacturer",
-        "Aperture",
-        "ExposureCompensation",
-        "ColorTempKelvin",
-        "ImageSize",
-        "HyperfocalDistance",
-    ]);
-    let exif =
-        PeckExif::new(input_file, Mode::Whitelist(allow_list)).expect("Failed to parse EXIF");
-    let exif = Exif::from(exif);
+    let cached_source = resources.cache.map(|cache| cache.source(&buf));
+
+    // Reading EXIF runs exiftool, so it's cached along with the encoded images
+    let exif = if options.no_exif {
+        Exif::default()
+    } else {
+        match cached_source.as_ref().and_then(CachedSource::exif) {
+            Some(exif) => exif,
+            None => {
+                let exif = read_exif(input_file);
+                if let Some(cached_source) = &cached_source {
+                    cached_source.set_exif(&exif)?;
+                }
+                exif
+            }
+        }
+    };
+    let orientation = exif
+        .orientation
+        .or_else(|| Orientation::read(&buf))
+        .unwrap_or(Orientation::Normal);
+    // The generated images are turned upright, so that's the orientation published for them
+    let exif = Exif {
+        orientation: exif.orientation.map(|_| Orientation::Normal),
+        ..exif
+    };
+    // Decoded pixels are in the colours of the embedded profile, if there is one
+    let profile = IccProfile::read(&buf);
+    let wide_gamut = options.wide_gamut
+        && options.color_management == ColorManagement::Convert
+        && profile.as_ref().is_some_and(IccProfile::is_wide_gamut);
+    let embeds_profile = |variant: Variant, format: OutputFormat| {
+        profile.is_some()
+            && format.embeds_color_profile()
+            && (options.color_management == ColorManagement::Preserve
+                || matches!(variant, Variant::WideGamut(_)))
+    };
+
+    // Files embedding the profile are encoded from the decoded colours, so they're kept alongside the sRGB pixels
+    let keeps_profile =
+        profile.is_some() && (options.color_management == ColorManagement::Preserve || wide_gamut);
+
+    // Images are only decoded if a variant is missing from the cache.
+    // Their colours are converted to sRGB once, straight after decoding.
+    let mut decoded: Option<Pixels> = None;
+    let decode = || {
+        debug!("Decoding {}", &input_file.to_string_lossy());
+
+        // TODO: Find a way to use Jpegli without having to do this
+        let decoded_image = Loader::new()
+            .metadata(true)
+            .load_data(buf.as_ref())
+            .with_context(|| format!("Failed to load image {}", &input_file.to_string_lossy()))?;
+        // TODO: Confirm if this only works for JPEGs?
+        let converted = conversion::from_loaded_image(decoded_image);
+        let image = orient_image(converted.image, orientation);
+        let pixels = match &profile {
+            Some(profile) => Pixels {
+                srgb: profile.to_srgb(&image).with_context(|| {
+                    format!(
+                        "Failed to convert the colours of {} to sRGB",
+                        input_file.to_string_lossy()
+                    )
+                })?,
+                original: keeps_profile.then_some(image),
+            },
+            None => Pixels {
+                srgb: image,
+                original: None,
+            },
+        };
+        Ok::<_, anyhow::Error>((pixels, converted.transparent))
+    };
 
-    let width = decoded_image.width;
-    let height = decoded_image.height;
-    // TODO: Confirm if this only works for JPEGs?
-    let image = zune_image_from_loaded_image(decoded_image);
+    // The dimensions the image is stored with, before it's turned upright, and whether it's transparent
+    let manifest = match cached_source.as_ref().and_then(CachedSource::manifest) {
+        Some(manifest) => manifest,
+        None => {
+            let (pixels, transparent) = decode()?;
+            // The decoded image is upright so turning it back gives the stored dimensions
+            let (width, height) = pixels.srgb.dimensions();
+            let (width, height) = orientation.upright(width, height);
+            let manifest = SourceManifest {
+                width,
+                height,
+                transparent,
+            };
+            if let Some(cached_source) = &cached_source {
+                cached_source.set_manifest(&manifest)?;
+            }
+            decoded = Some(pixels);
+            manifest
+        }
+    };
+    let (stored_width, stored_height) = (manifest.width, manifest.height);
+    let (width, height) = orientation.upright(stored_width, stored_height);
+    let fallback = if manifest.transparent && options.transparency == Transparency::SwitchFormat {
+        OutputFormat::TRANSPARENT_FALLBACK
+    } else {
+        OutputFormat::FALLBACK
+    };
+    // Transparent images are flattened for formats without an alpha channel
+    let background = |format: OutputFormat| {
+        (manifest.transparent && !format.supports_alpha()).then_some(options.background)
+    };
 
-    let resizes = compute_resize_pairs(width, height, options.sizes.0.clone())
+    let file_name = input_file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Values given on the command line take precedence over the image's sidecar, which takes precedence
+    // over the IPTC and XMP metadata written into it
+    let metadata = Metadata::read(input_file, &buf)?;
+    let sidecar = options
+        .sidecar()
+        .or(Sidecar::load(input_file, resources.manifest)?.unwrap_or_default())
+        .or(metadata.sidecar());
+    let placeholder = make_cached_placeholder(
+        input_file,
+        sidecar.placeholder.unwrap_or(options.placeholder),
+        options,
+        cached_source.as_ref(),
+        &mut decoded,
+        || Ok(decode()?.0),
+    )?;
+    let layout = match &sidecar.layout {
+        Some(name) => Some(resources.config.layout(name)?),
+        None => resources.config.layout_for(&file_name)?,
+    }
+    .cloned();
+    let widths = match &layout {
+        Some(layout) => layout.useful_widths(&options.sizes.0),
+        None => options.sizes.0.clone(),
+    };
+
+    let resizes = compute_resize_pairs(stored_width, stored_height, orientation, widths.clone())
         .ok_or(AppError::ImageTooSmall)
         .with_context(|| {
             format!(
@@ -246,17 +278,21 @@ pub fn process_image(
         .max()
         .expect("No need to resizes");
 
-    // TODO: Make configurable
-    let ext = ".jpeg";
+    let formats = options.formats.with_fallback(fallback);
 
-    let full_size_reencoded_path =
-        create_destination_path(output_directory, input_file, options, "original", ext)
-            .with_context(|| {
-                format!(
-                    "Failed to create compute new image path for {}",
-                    input_file.to_string_lossy()
-                )
-            })?;
+    let full_size_reencoded_path = create_destination_path(
+        output_directory,
+        input_file,
+        options,
+        Variant::FullSize,
+        fallback.extension(),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to create compute new image path for {}",
+            input_file.to_string_lossy()
+        )
+    })?;
 
     create_dir_all(full_size_reencoded_path.with_file_name("")).with_context(|| {
         format!(
@@ -267,86 +303,397 @@ pub fn process_image(
         )
     })?;
 
-    if !options.skip_resize {
-        encode_image(&image, full_size_reencoded_path.clone()).with_context(|| {
-            format!(
-                "Failed to reencode image at full size: {}",
+    let full_size_reencoded_image = GeneratedImage::new(
+        width,
+        height,
+        full_size_reencoded_path,
+        fallback,
+        Variant::FullSize,
+    )
+    .with_embedded_profile(embeds_profile(Variant::FullSize, fallback))
+    .with_background(background(fallback));
+
+    // Every width of the full frame, followed by every width of each art-directed crop
+    let mut jobs = resizes
+        .iter()
+        .map(|resize| (Variant::Resized(resize.width), None, resize.clone()))
+        .collect::<Vec<_>>();
+    let crops = layout.as_ref().map_or(&[][..], |layout| &layout.crops);
+    let crop_hints = sidecar.crop_hints();
+    let mut saliency = None;
+    // The region of the upright image `crop` keeps. Smart crop debug images are named after `name`.
+    let mut region_of = |crop: &Crop, name: String| -> Result<Region> {
+        // Hints given by hand are used instead of finding a crop automatically
+        let smart = crop.smart && crop_hints.is_empty();
+        Ok(match crop.aspect_ratio.filter(|_| smart) {
+            Some(aspect_ratio) => {
+                if saliency.is_none() {
+                    if decoded.is_none() {
+                        decoded = Some(decode()?.0);
+                    }
+                    let pixels = decoded.as_ref().expect("The image was just decoded");
+                    saliency = Some(Saliency::of_image(&pixels.srgb));
+                }
+                let saliency = saliency.as_ref().expect("Saliency was just measured");
+                if let Some(directory) = &options.smart_crop_debug {
+                    let stem = input_file
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().replace(' ', "-"))
+                        .unwrap_or_default();
+                    let overlay = directory.join(format!("{stem}-{name}-saliency.jpeg"));
+                    create_dir_all(directory)?;
+                    saliency.write_overlay(&overlay, aspect_ratio)?;
+                }
+                saliency.crop(aspect_ratio, width, height)
+            }
+            None => crop.region(width, height, &crop_hints),
+        })
+    };
+    for (index, crop) in crops.iter().enumerate() {
+        let region = region_of(crop, format!("crop{}", index + 1))?;
+        match compute_resize_pairs(
+            region.width,
+            region.height,
+            Orientation::Normal,
+            widths.clone(),
+        ) {
+            Some(crop_resizes) => jobs.extend(crop_resizes.into_iter().map(|resize| {
+                let variant = Variant::Cropped {
+                    crop: index,
+                    width: resize.width,
+                };
+                (variant, Some(region), resize)
+            })),
+            None => warn!(
+                "Skipping the crop for {} of {}. The {region} crop is too small to generate images for",
+                crop.media,
                 input_file.to_string_lossy()
+            ),
+        }
+    }
+    // Social cards and thumbnails are each made at a single size from the most interesting region
+    let (card_width, card_height) = SOCIAL_CARD_SIZE;
+    let single_size_crops = options
+        .social_card
+        .then_some((
+            Variant::SocialCard,
+            AspectRatio {
+                width: card_width as f64,
+                height: card_height as f64,
+            },
+            Resize::new(card_width, card_height),
+        ))
+        .into_iter()
+        .chain(options.thumbnail.map(|size| {
+            (
+                Variant::Thumbnail(size),
+                AspectRatio::SQUARE,
+                Resize::new(size, size),
             )
-        })?;
+        }));
+    for (variant, aspect_ratio, resize) in single_size_crops {
+        let region = region_of(&Crop::smart(aspect_ratio), variant.to_string())?;
+        if region.width < resize.width {
+            warn!(
+                "Skipping the {variant} of {}. The {region} crop is smaller than {}x{}",
+                input_file.to_string_lossy(),
+                resize.width,
+                resize.height
+            );
+            continue;
+        }
+        jobs.push((variant, Some(region), resize));
     }
 
-    let mut generated_images: Vec<GeneratedImage> = Vec::with_capacity(resizes.len());
-
-    let progress_bar = ProgressBar::new(resizes.len() as u64).with_message("Resizing Images");
-    // TODO: Concurrency
-    for resize in &resizes {
-        let generated_image =
-            scale_and_save(input_file, output_directory, &image, resize, ext, options)
-                .with_context(|| {
-                    format!(
-                        "Failed to resize image to {:?} {}",
-                        resize,
-                        input_file.to_string_lossy()
+    // The new paths from names, sizes and file ext. Grouped by resize.
+    // Wide gamut copies of the full frame are made from the same resize as the sRGB images.
+    let generated_images = jobs
+        .iter()
+        .map(|(variant, region, resize)| {
+            let wide_gamut_variants = match variant {
+                Variant::Resized(width) if wide_gamut => formats
+                    .iter()
+                    .filter(|format| format.embeds_color_profile())
+                    .map(|format| (Variant::WideGamut(*width), *format))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            // Social cards and thumbnails are linked to rather than offered in a `picture`, so only need one format
+            let variant_formats = match variant {
+                Variant::SocialCard | Variant::Thumbnail(_) => vec![fallback],
+                _ => formats.clone(),
+            };
+            variant_formats
+                .iter()
+                .map(|format| (*variant, *format))
+                .chain(wide_gamut_variants)
+                .map(|(variant, format)| {
+                    let image_path = create_destination_path(
+                        output_directory,
+                        input_file,
+                        options,
+                        variant,
+                        format.extension(),
+                    )?;
+                    let generated = GeneratedImage::new(
+                        resize.width,
+                        resize.height,
+                        image_path,
+                        format,
+                        variant,
                     )
-                })?;
-        generated_images.push(generated_image);
-        progress_bar.inc(1);
+                    .with_embedded_profile(embeds_profile(variant, format))
+                    .with_background(background(format));
+                    Ok(match region {
+                        Some(region) => generated.with_region(*region),
+                        None => generated,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let restore = |image: &GeneratedImage| -> Result<bool> {
+        let restored = match &cached_source {
+            Some(cached_source) => cached_source.restore(image)?,
+            None => false,
+        };
+        if restored {
+            m.cached.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(restored)
+    };
+    let store = |image: &GeneratedImage| -> Result<()> {
+        match &cached_source {
+            Some(cached_source) => cached_source.store(image),
+            None => Ok(()),
+        }
+    };
+
+    let full_size_missing = !restore(&full_size_reencoded_image)?;
+    let missing = generated_images
+        .iter()
+        .map(|images| {
+            images
+                .iter()
+                .map(|image| Ok(!restore(image)?))
+                .collect::<Result<Vec<bool>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if !full_size_missing && missing.iter().flatten().all(|missing| !missing) {
+        debug!(
+            "Every variant of {} was cached",
+            &input_file.to_string_lossy()
+        );
+    } else if options.skip_resize {
+        bail!(
+            "--skip-resize was set but not every variant of {} is cached",
+            input_file.to_string_lossy()
+        );
+    } else {
+        let pixels = match decoded {
+            Some(decoded) => decoded,
+            None => decode()?.0,
+        };
+
+        if full_size_missing {
+            encode_images(
+                Some(&pixels.srgb),
+                pixels.original.as_ref(),
+                &[&full_size_reencoded_image],
+                profile.as_ref(),
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to reencode image at full size: {}",
+                    input_file.to_string_lossy()
+                )
+            })?;
+            store(&full_size_reencoded_image)?;
+        }
+
+        // Every width of a crop is resized from the same cropped image, so each region is only cropped once
+        let regions = jobs
+            .iter()
+            .zip(&missing)
+            .filter(|(_, missing)| missing.iter().any(|missing| *missing))
+            .filter_map(|((_, region, _), _)| *region)
+            .collect::<HashSet<Region>>();
+        let crops = regions
+            .into_par_iter()
+            .map(|region| (region, pixels.crop(region)))
+            .collect::<HashMap<_, _>>();
+
+        let progress_bar = resources.progress.add(
+            ProgressBar::new(jobs.len() as u64).with_message(
+                input_file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+        );
+        jobs.par_iter()
+            .zip(&generated_images)
+            .zip(&missing)
+            .map(|(((_, region, resize), images), missing)| {
+                let missing_images = images
+                    .iter()
+                    .zip(missing)
+                    .filter_map(|(image, missing)| missing.then_some(image))
+                    .collect::<Vec<_>>();
+                if !missing_images.is_empty() {
+                    let source = region.map_or(&pixels, |region| &crops[&region]);
+                    scale_and_save(source, resize, &missing_images, profile.as_ref())
+                        .with_context(|| {
+                            format!(
+                                "Failed to resize image to {:?} {}",
+                                resize,
+                                input_file.to_string_lossy()
+                            )
+                        })?;
+                    for image in missing_images {
+                        store(image)?;
+                    }
+                    m.resized.fetch_add(1, Ordering::Relaxed);
+                }
+                progress_bar.inc(1);
+                Ok(())
+            })
+            .collect::<Result<()>>()?;
+        progress_bar.finish_and_clear();
+        resources.progress.remove(&progress_bar);
     }
-    progress_bar.finish_and_clear();
-    m.count += 1;
-    m.resized += resizes.len();
+    m.count.fetch_add(1, Ordering::Relaxed);
 
     Ok(ImageInfo::new(
         max,
         input_file.to_path_buf(),
-        ext.to_owned(),
+        fallback,
         resizes,
-        generated_images,
-        GeneratedImage::new(width, height, full_size_reencoded_path.clone()),
+        generated_images.into_iter().flatten().collect(),
+        full_size_reencoded_image,
         OriginalImage::new(input_file.to_path_buf()),
         exif,
+        metadata,
+        layout,
+        sidecar,
+        placeholder,
     ))
 }
 
-fn encode_image(image: &Image, path: PathBuf) -> Result<()> {
-    let mut file = File::create_new(&path)?;
-    let encoder_options = JpegliOptions {
-        quality: 90.0,
-        ..JpegliOptions::default()
-    };
-    let mut encoder = JpegliEncoder::new_with_options(encoder_options);
-    let image = encoder.encode(image).with_context(|| {
-        format!(
-            "Failed to write new encoded version of {}",
-            path.to_string_lossy()
-        )
-    })?;
-    file.write_all(&image)?;
+/// Creates the placeholder of `kind` shown whilst the image at `path` loads, or reuses it from the cache.
+/// The image is only decoded with `decode`, into `decoded`, if it hasn't been already and the placeholder isn't cached.
+/// An image without a placeholder is still usable, so failures to make one are logged rather than returned.
+fn make_cached_placeholder(
+    path: &Path,
+    kind: PlaceholderKind,
+    options: &Options,
+    cached_source: Option<&CachedSource>,
+    decoded: &mut Option<Pixels>,
+    decode: impl Fn() -> Result<Pixels>,
+) -> Result<String> {
+    let sqip_options = options.sqip();
+    let settings = placeholder::settings(kind, &sqip_options);
+    if let Some(placeholder) = cached_source.and_then(|cached| cached.placeholder(&settings)) {
+        return Ok(placeholder);
+    }
+    if kind == PlaceholderKind::None {
+        return Ok(String::new());
+    }
+    if decoded.is_none() {
+        *decoded = Some(decode()?);
+    }
+    let pixels = decoded.as_ref().expect("The image was just decoded");
+    debug!("Making {kind} placeholder");
+    match make_placeholder(path, &pixels.srgb, kind, &sqip_options) {
+        Ok(placeholder) => {
+            if let Some(cached_source) = cached_source {
+                cached_source.set_placeholder(&settings, &placeholder)?;
+            }
+            Ok(placeholder)
+        }
+        Err(e) => {
+            warn!(
+                "Failed to create a placeholder for {}: {e:#}",
+                path.to_string_lossy()
+            );
+            Ok(String::new())
+        }
+    }
+}
+
+/// The pixels of a decoded, upright image, or of a crop of it, which files are encoded from
+#[derive(Clone)]
+pub struct Pixels {
+    /// Converted to sRGB. Placeholders and smart crops are also found from these.
+    pub srgb: Image,
+    /// In the colours of the image's embedded profile. Only kept when files embed the profile.
+    pub original: Option<Image>,
+}
+
+impl Pixels {
+    fn crop(&self, region: Region) -> Self {
+        Self {
+            srgb: crop_image(&self.srgb, region),
+            original: self
+                .original
+                .as_ref()
+                .map(|image| crop_image(image, region)),
+        }
+    }
+}
+
+/// Encodes each generated image to its path in its format. Files embedding `profile` are encoded from `original`,
+/// which is in the profile's colours, and every other file from `srgb`.
+/// The pixels are first converted to ones the format can encode.
+fn encode_images(
+    srgb: Option<&Image>,
+    original: Option<&Image>,
+    generated_images: &[&GeneratedImage],
+    profile: Option<&IccProfile>,
+) -> Result<()> {
+    for generated_image in generated_images {
+        let path = &generated_image.path;
+        let format = generated_image.format;
+        let source = if generated_image.embeds_profile {
+            original.expect("The original colours are kept for files embedding the profile")
+        } else {
+            srgb.expect("sRGB pixels are given for files which don't embed the profile")
+        };
+        let pixels = conversion::for_format(
+            source,
+            format,
+            generated_image.background.unwrap_or_default(),
+        );
+        let encoded = match profile {
+            Some(profile) if generated_image.embeds_profile => profile
+                .embed(format, &format.encode(&pixels)?)
+                .with_context(|| {
+                    format!("A colour profile can't be embedded in a {format} image")
+                })?,
+            _ => format.encode(&pixels)?,
+        };
+        let mut file = File::create_new(path)?;
+        file.write_all(&encoded).with_context(|| {
+            format!(
+                "Failed to write new encoded version of {}",
+                path.to_string_lossy()
+            )
+        })?;
+    }
     Ok(())
 }
 
-///  Resize the image provided by path and save the resulting new image onto output_directory
+///  Resize the image and save the resulting new image to the path of each generated image in its format.
+/// Only the pixels the generated images are encoded from are resized.
 pub fn scale_and_save(
-    path: &Path,
-    output_directory: &Path,
-    image: &Image,
+    pixels: &Pixels,
     resize: &Resize,
-    ext: &str,
-    options: &Options,
-) -> Result<GeneratedImage> {
-    // The new path from names, sizes and file ext
-    let image_path = create_destination_path(
-        output_directory,
-        path,
-        options,
-        &[resize.width.to_string(), "w".to_owned()].join(""),
-        ext,
-    )?;
-
-    let generated_image = GeneratedImage::new(resize.width, resize.height, image_path.clone());
-
-    if !options.skip_resize {
+    generated_images: &[&GeneratedImage],
+    profile: Option<&IccProfile>,
+) -> Result<()> {
+    let resized = |image: &Image| -> Result<Image> {
         let mut image = image.clone();
         rimage::operations::resize::Resize::new(
             resize.width,
@@ -356,18 +703,28 @@ pub fn scale_and_save(
             ),
         )
         .execute(&mut image)?;
+        Ok(image)
+    };
 
-        encode_image(&image, image_path.clone())?;
-    }
-
-    Ok(generated_image)
+    let srgb = generated_images
+        .iter()
+        .any(|image| !image.embeds_profile)
+        .then(|| resized(&pixels.srgb))
+        .transpose()?;
+    let original = pixels
+        .original
+        .as_ref()
+        .filter(|_| generated_images.iter().any(|image| image.embeds_profile))
+        .map(resized)
+        .transpose()?;
+    encode_images(srgb.as_ref(), original.as_ref(), generated_images, profile)
 }
 
 fn create_destination_path(
     output_directory: &Path,
     image_path: &Path,
     options: &Options,
-    suffix: &str,
+    variant: Variant,
     ext: &str,
 ) -> Result<PathBuf> {
     let file_name = image_path
@@ -385,10 +742,7 @@ fn create_destination_path(
             .expect("Unable to create destination path path")),
         &options.name.replace(' ', "-"),
         &file_name.replace(' ', "-"),
-        &([file_name, "-", suffix, ".", ext]
-            .join("")
-            .replace("..", ".")
-            .replace(' ', "-")),
+        &variant.file_name(file_name, ext),
     ]);
     Ok(img_path)
 }
@@ -405,12 +759,16 @@ impl Resize {
     }
 }
 
-/// Return an array of sizes of large and small images based on the provided max width
+/// Return an array of sizes of large and small images based on the provided max width.
+/// The width and height are swapped for images whose `orientation` displays them on their side.
 fn compute_resize_pairs(
     image_width: usize,
     image_height: usize,
+    orientation: Orientation,
     sizes: Vec<usize>,
 ) -> Option<Vec<Resize>> {
+    let (image_width, image_height) = orientation.upright(image_width, image_height);
+
     // This function makes extensive use of casting.
     // The casting works here as we are always going from an "equivalent sized" unsigned integer to float. (usize -> f64)
     // When we "downcast" we always do that with floats which have sensible mechanisms for downcasting.
@@ -445,7 +803,8 @@ pub fn digest_path(
     path: &Path,
     output_directory: &Path,
     options: &Options,
-    m: &mut Metrics,
+    m: &Metrics,
+    resources: &ProcessingResources,
 ) -> Result<Option<ImageInfo>> {
     // File written to bail as early as possible to avoid more syscalls than needed
 
@@ -482,7 +841,7 @@ pub fn digest_path(
         return Ok(None);
     }
 
-    let image_info = process_image(path, output_directory, options, m)?;
+    let image_info = process_image(path, output_directory, options, m, resources)?;
     Ok(Some(image_info))
 }
 
@@ -495,3 +854,36 @@ fn path_from_array(array: &[&str]) -> PathBuf {
     }
     pb
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize_pairs_are_upright() {
+        let sizes = |orientation| {
+            compute_resize_pairs(4000, 3000, orientation, vec![640, 3200])
+                .unwrap()
+                .iter()
+                .map(|resize| (resize.width, resize.height))
+                .collect::<Vec<_>>()
+        };
+        for orientation in [
+            Orientation::Normal,
+            Orientation::MirrorHorizontal,
+            Orientation::Rotate180,
+            Orientation::MirrorVertical,
+        ] {
+            assert_eq!(sizes(orientation), vec![(640, 480), (3200, 2400)]);
+        }
+        // Displayed on their side, so 3000 pixels wide and too narrow for 3200
+        for orientation in [
+            Orientation::MirrorHorizontalRotate270,
+            Orientation::Rotate90,
+            Orientation::MirrorHorizontalRotate90,
+            Orientation::Rotate270,
+        ] {
+            assert_eq!(sizes(orientation), vec![(640, 853)]);
+        }
+    }
+}
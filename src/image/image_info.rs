@@ -1,11 +1,16 @@
 use std::path::PathBuf;
 
 use crate::data::exif::Exif;
+use crate::data::metadata::Metadata;
+use crate::layout::Layout;
 use crate::options::Options;
 use crate::original_image::OriginalImage;
+use crate::sidecar::Sidecar;
 
+use super::format::OutputFormat;
 use super::generated_image::GeneratedImage;
 use super::image::Resize;
+use super::variant::Variant;
 
 #[derive(Debug, Clone)]
 pub struct ImageInfo {
@@ -13,11 +18,11 @@ pub struct ImageInfo {
     pub max_width: usize,
     /// The path to the input file
     pub input_path: PathBuf,
-    /// The extension of the generated files
-    pub ext: String,
+    /// The format of the `img` fallback. PNG rather than JPEG for transparent images when switching format.
+    pub fallback: OutputFormat,
     /// The resized image widths and heights
     pub resizes: Vec<Resize>,
-    // The resized (+any other post processing) images
+    // The resized (+any other post processing) images in every format
     pub generated_images: Vec<GeneratedImage>,
     /// The image at full resolution converted to a specified format
     pub full_size_reencoded_image: GeneratedImage,
@@ -25,28 +30,45 @@ pub struct ImageInfo {
     pub original_image: OriginalImage,
     /// EXIF data for the image
     pub exif: Exif,
+    /// IPTC and XMP metadata for the image
+    pub metadata: Metadata,
+    /// Where the image sits on a page, if a layout is configured for it
+    pub layout: Option<Layout>,
+    /// Descriptions and settings from the command line, the image's sidecar file and its folder's manifest
+    pub sidecar: Sidecar,
+    /// Shown whilst the image loads. Empty if one couldn't be made.
+    pub placeholder: String,
 }
 
 impl ImageInfo {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         max_width: usize,
         input_path: PathBuf,
-        ext: String,
+        fallback: OutputFormat,
         resizes: Vec<Resize>,
         generated_images: Vec<GeneratedImage>,
         full_size_reencoded_image: GeneratedImage,
         original_image: OriginalImage,
         exif: Exif,
+        metadata: Metadata,
+        layout: Option<Layout>,
+        sidecar: Sidecar,
+        placeholder: String,
     ) -> Self {
         Self {
             max_width,
             input_path,
-            ext,
+            fallback,
             resizes,
             generated_images,
             full_size_reencoded_image,
             original_image,
             exif,
+            metadata,
+            layout,
+            sidecar,
+            placeholder,
         }
     }
 
@@ -74,6 +96,38 @@ impl ImageInfo {
         }
     }
 
+    /// The resized images of the full frame encoded in `format`
+    pub fn generated_images_in(
+        &self,
+        format: OutputFormat,
+    ) -> impl Iterator<Item = &GeneratedImage> {
+        self.generated_images.iter().filter(move |image| {
+            image.format == format && matches!(image.variant, Variant::Resized(_))
+        })
+    }
+
+    /// The resized images of the full frame keeping a wide gamut colour profile, encoded in `format`
+    pub fn wide_gamut_images_in(
+        &self,
+        format: OutputFormat,
+    ) -> impl Iterator<Item = &GeneratedImage> {
+        self.generated_images.iter().filter(move |image| {
+            image.format == format && matches!(image.variant, Variant::WideGamut(_))
+        })
+    }
+
+    /// The resized images of the `crop`th art-directed crop encoded in `format`
+    pub fn cropped_images_in(
+        &self,
+        crop: usize,
+        format: OutputFormat,
+    ) -> impl Iterator<Item = &GeneratedImage> {
+        self.generated_images.iter().filter(move |image| {
+            image.format == format
+                && matches!(image.variant, Variant::Cropped { crop: index, .. } if index == crop)
+        })
+    }
+
     pub fn get_hugo_data_key(&self, options: &Options) -> String {
         [
             &options.name,
@@ -1,5 +1,15 @@
+pub mod budget;
+pub mod cache;
+pub mod color;
+pub mod conversion;
+pub mod crop;
+pub mod format;
 pub mod generated_image;
 pub mod image;
 pub mod image_info;
+pub mod orientation;
 pub mod original_image;
+pub mod placeholder;
+pub mod smartcrop;
 pub mod sqip;
+pub mod variant;
@@ -0,0 +1,98 @@
+use zune_core::bit_depth::BitDepth;
+use zune_image::image::Image;
+
+use crate::data::exif::Orientation;
+
+/// Rotates and flips the `width` by `height` pixels of a photo with `orientation` so they're upright.
+/// Each pixel is `components` values long.
+pub fn orient_pixels<T: Copy>(
+    pixels: &[T],
+    width: usize,
+    height: usize,
+    components: usize,
+    orientation: Orientation,
+) -> Vec<T> {
+    let (upright_width, upright_height) = orientation.upright(width, height);
+    let mut oriented = Vec::with_capacity(pixels.len());
+    for y in 0..upright_height {
+        for x in 0..upright_width {
+            // Where the upright pixel at x, y is stored
+            let (source_x, source_y) = match orientation {
+                Orientation::Normal => (x, y),
+                Orientation::MirrorHorizontal => (width - 1 - x, y),
+                Orientation::Rotate180 => (width - 1 - x, height - 1 - y),
+                Orientation::MirrorVertical => (x, height - 1 - y),
+                Orientation::MirrorHorizontalRotate270 => (y, x),
+                Orientation::Rotate90 => (y, height - 1 - x),
+                Orientation::MirrorHorizontalRotate90 => (width - 1 - y, height - 1 - x),
+                Orientation::Rotate270 => (width - 1 - y, x),
+            };
+            let start = (source_y * width + source_x) * components;
+            oriented.extend_from_slice(&pixels[start..start + components]);
+        }
+    }
+    oriented
+}
+
+/// Rotates and flips a decoded photo with `orientation` so it's upright
+pub fn orient_image(image: Image, orientation: Orientation) -> Image {
+    if orientation == Orientation::Normal {
+        return image;
+    }
+    let (width, height) = image.dimensions();
+    let (upright_width, upright_height) = orientation.upright(width, height);
+    let colorspace = image.colorspace();
+    let components = colorspace.num_components();
+    match image.depth() {
+        BitDepth::Sixteen => {
+            let pixels = image.flatten_frames::<u16>().swap_remove(0);
+            Image::from_u16(
+                &orient_pixels(&pixels, width, height, components, orientation),
+                upright_width,
+                upright_height,
+                colorspace,
+            )
+        }
+        _ => {
+            let pixels = image.flatten_to_u8().swap_remove(0);
+            Image::from_u8(
+                &orient_pixels(&pixels, width, height, components, orientation),
+                upright_width,
+                upright_height,
+                colorspace,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_orientation_is_upright() {
+        // Stored as
+        // a b c
+        // d e f
+        let stored = "abcdef".chars().collect::<Vec<_>>();
+        let expected = [
+            (Orientation::Normal, "abc/def"),
+            (Orientation::MirrorHorizontal, "cba/fed"),
+            (Orientation::Rotate180, "fed/cba"),
+            (Orientation::MirrorVertical, "def/abc"),
+            (Orientation::MirrorHorizontalRotate270, "ad/be/cf"),
+            (Orientation::Rotate90, "da/eb/fc"),
+            (Orientation::MirrorHorizontalRotate90, "fc/eb/da"),
+            (Orientation::Rotate270, "cf/be/ad"),
+        ];
+        for (orientation, upright) in expected {
+            let (width, _) = orientation.upright(3, 2);
+            let rows = orient_pixels(&stored, 3, 2, 1, orientation)
+                .chunks(width)
+                .map(|row| row.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("/");
+            assert_eq!(rows, upright, "{orientation:?}");
+        }
+    }
+}
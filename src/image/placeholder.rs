@@ -0,0 +1,269 @@
+use anyhow::Result;
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_image::image::Image;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::format::OutputFormat;
+use super::sqip::primitive::Raster;
+use super::sqip::{make_sqip, SqipOptions};
+
+/// The longest side of the image BlurHash and ThumbHash are computed from. ThumbHash accepts at most 100px.
+const HASH_SIZE: usize = 64;
+
+/// The number of horizontal and vertical components of a BlurHash
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// The longest side of a tiny JPEG placeholder
+const LQIP_SIZE: usize = 16;
+
+/// The number of levels each channel is reduced to when finding the dominant colour
+const COLOR_LEVELS: usize = 16;
+
+/// What is shown whilst an image loads. Recorded in the data file so templates know how to render the placeholder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceholderKind {
+    /// A base64 encoded, blurred SVG of a few shapes
+    Sqip,
+    /// A [BlurHash](https://blurha.sh/) string
+    Blurhash,
+    /// A base64 encoded [ThumbHash](https://evanw.github.io/thumbhash/)
+    Thumbhash,
+    /// A base64 encoded JPEG a few pixels wide
+    Lqip,
+    /// The dominant colour as a hex string. e.g. `#a0b1c2`
+    Color,
+    /// No placeholder
+    None,
+}
+
+impl PlaceholderKind {
+    const NAMES: &'static [(&'static str, PlaceholderKind)] = &[
+        ("sqip", PlaceholderKind::Sqip),
+        ("blurhash", PlaceholderKind::Blurhash),
+        ("thumbhash", PlaceholderKind::Thumbhash),
+        ("lqip", PlaceholderKind::Lqip),
+        ("color", PlaceholderKind::Color),
+        ("none", PlaceholderKind::None),
+    ];
+}
+
+impl FromStr for PlaceholderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        Self::NAMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| {
+                let names = Self::NAMES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{s} is not a placeholder. Expected one of {names}")
+            })
+    }
+}
+
+impl fmt::Display for PlaceholderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = Self::NAMES
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .map(|(name, _)| *name)
+            .unwrap_or_default();
+        write!(f, "{name}")
+    }
+}
+
+/// Everything besides the image that a placeholder of `kind` depends on, so it can be cached
+pub fn settings(kind: PlaceholderKind, sqip_options: &SqipOptions) -> String {
+    match kind {
+        PlaceholderKind::Sqip => format!(
+            "{kind} {} {} {}",
+            sqip_options.primitives, sqip_options.mode, sqip_options.alpha
+        ),
+        kind => kind.to_string(),
+    }
+}
+
+/// Creates a placeholder of `kind` for the decoded, upright `image` read from `path`
+pub fn make_placeholder(
+    path: &Path,
+    image: &Image,
+    kind: PlaceholderKind,
+    sqip_options: &SqipOptions,
+) -> Result<String> {
+    let placeholder = match kind {
+        PlaceholderKind::Sqip => make_sqip(&path.to_string_lossy(), image, sqip_options)?,
+        PlaceholderKind::Blurhash => {
+            let raster = raster(image, HASH_SIZE);
+            let (x, y) = BLURHASH_COMPONENTS;
+            blurhash::encode(
+                x,
+                y,
+                raster.width as u32,
+                raster.height as u32,
+                &rgba_bytes(&raster),
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to create a BlurHash: {e:?}"))?
+        }
+        PlaceholderKind::Thumbhash => {
+            let raster = raster(image, HASH_SIZE);
+            let hash =
+                thumbhash::rgba_to_thumb_hash(raster.width, raster.height, &rgba_bytes(&raster));
+            BASE64_STANDARD.encode(hash)
+        }
+        PlaceholderKind::Lqip => {
+            let raster = raster(image, LQIP_SIZE);
+            let pixels = raster
+                .pixels
+                .iter()
+                .flat_map(|pixel| pixel.map(|c| c.round() as u8))
+                .collect::<Vec<u8>>();
+            let image = Image::from_u8(&pixels, raster.width, raster.height, ColorSpace::RGB);
+            BASE64_STANDARD.encode(OutputFormat::Jpeg.encode(&image)?)
+        }
+        PlaceholderKind::Color => {
+            let [r, g, b] = dominant_color(&raster(image, HASH_SIZE));
+            format!("#{r:02x}{g:02x}{b:02x}")
+        }
+        PlaceholderKind::None => String::new(),
+    };
+    Ok(placeholder)
+}
+
+/// The image as RGB, with any transparency composited over white, averaged down so its longest side is at most
+/// `max_side`
+pub(crate) fn raster(image: &Image, max_side: usize) -> Raster {
+    fn composite<T: Copy + Into<f32>>(
+        values: &[T],
+        (width, height): (usize, usize),
+        components: usize,
+        max: f32,
+        max_side: usize,
+    ) -> Raster {
+        Raster::downscaled(width, height, max_side, |x, y| {
+            let start = (y * width + x) * components;
+            let component = |index: usize| values[start + index].into() * 255.0 / max;
+            let (color, alpha) = match components {
+                1 => ([component(0); 3], 255.0),
+                2 => ([component(0); 3], component(1)),
+                3 => ([component(0), component(1), component(2)], 255.0),
+                _ => ([component(0), component(1), component(2)], component(3)),
+            };
+            color.map(|c| c * alpha / 255.0 + 255.0 * (1.0 - alpha / 255.0))
+        })
+    }
+
+    let dimensions = image.dimensions();
+    let components = image.colorspace().num_components();
+    match image.depth() {
+        BitDepth::Sixteen => composite(
+            &image.flatten_frames::<u16>().swap_remove(0),
+            dimensions,
+            components,
+            65535.0,
+            max_side,
+        ),
+        _ => composite(
+            &image.flatten_to_u8().swap_remove(0),
+            dimensions,
+            components,
+            255.0,
+            max_side,
+        ),
+    }
+}
+
+fn rgba_bytes(raster: &Raster) -> Vec<u8> {
+    raster
+        .pixels
+        .iter()
+        .flat_map(|[r, g, b]| [*r, *g, *b, 255.0].map(|c| c.round() as u8))
+        .collect()
+}
+
+/// The average of the most common group of similar colours
+fn dominant_color(raster: &Raster) -> [u8; 3] {
+    let bucket_size = 256.0 / COLOR_LEVELS as f32;
+    let mut buckets: BTreeMap<[usize; 3], ([f32; 3], usize)> = BTreeMap::new();
+    for pixel in &raster.pixels {
+        let key = pixel.map(|c| ((c / bucket_size) as usize).min(COLOR_LEVELS - 1));
+        let (sum, count) = buckets.entry(key).or_insert(([0.0; 3], 0));
+        for (total, c) in sum.iter_mut().zip(pixel) {
+            *total += c;
+        }
+        *count += 1;
+    }
+    buckets
+        .into_values()
+        .max_by_key(|(_, count)| *count)
+        .map_or([0; 3], |(sum, count)| {
+            sum.map(|total| (total / count as f32).round() as u8)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dominant_color() {
+        let mut pixels = vec![[200.0, 20.0, 20.0]; 60];
+        pixels.extend(vec![[10.0, 10.0, 250.0]; 40]);
+        assert_eq!(dominant_color(&Raster::new(10, 10, pixels)), [200, 20, 20]);
+    }
+
+    #[test]
+    fn test_raster_composites_transparency_over_white() {
+        #[rustfmt::skip]
+        let pixels = [
+            0, 0, 0, 0,     0, 0, 0, 0,     0, 0, 0, 255,   0, 0, 0, 255,
+            0, 0, 0, 0,     0, 0, 0, 0,     0, 0, 0, 255,   0, 0, 0, 255,
+        ];
+        let image = Image::from_u8(&pixels, 4, 2, ColorSpace::RGBA);
+        let raster = raster(&image, 2);
+        assert_eq!((raster.width, raster.height), (2, 1));
+        assert_eq!(raster.pixels, vec![[255.0; 3], [0.0; 3]]);
+    }
+
+    #[test]
+    fn test_placeholder_kinds() {
+        let path = Path::new("photo.png");
+        let options = SqipOptions::default();
+        let pixels = (0..32 * 24)
+            .flat_map(|i| [(i % 32 * 8) as u8, (i / 32 * 10) as u8, 128])
+            .collect::<Vec<u8>>();
+        let image = Image::from_u8(&pixels, 32, 24, ColorSpace::RGB);
+        let placeholder = |kind| make_placeholder(path, &image, kind, &options).unwrap();
+
+        // 1 size, 1 maximum AC value, 4 DC and 2 for each of the other 11 components
+        assert_eq!(placeholder(PlaceholderKind::Blurhash).len(), 28);
+        let thumbhash = BASE64_STANDARD
+            .decode(placeholder(PlaceholderKind::Thumbhash))
+            .unwrap();
+        assert!(!thumbhash.is_empty());
+        let lqip = BASE64_STANDARD
+            .decode(placeholder(PlaceholderKind::Lqip))
+            .unwrap();
+        assert_eq!(&lqip[..2], &[0xFF, 0xD8]);
+        assert_eq!(placeholder(PlaceholderKind::None), "");
+
+        let solid = Image::from_u8(&[160, 177, 194].repeat(32 * 24), 32, 24, ColorSpace::RGB);
+        assert_eq!(
+            make_placeholder(path, &solid, PlaceholderKind::Color, &options).unwrap(),
+            "#a0b1c2"
+        );
+    }
+}
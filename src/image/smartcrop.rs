@@ -0,0 +1,316 @@
+use anyhow::{Context, Result};
+use zune_core::colorspace::ColorSpace;
+use zune_image::image::Image;
+
+use std::fs::write;
+use std::path::Path;
+
+use super::crop::{AspectRatio, FocalPoint, Region};
+use super::format::OutputFormat;
+use super::placeholder::raster;
+use super::sqip::primitive::Raster;
+
+/// The longest side of the image saliency is measured on
+const ANALYSIS_SIZE: usize = 256;
+
+/// Saliency is summed into square cells this many pixels wide before crops are scored
+const CELL_SIZE: usize = 4;
+
+/// The crop sizes considered, relative to the largest crop with the target aspect ratio
+const SCALES: &[f64] = &[1.0, 0.9, 0.8];
+
+/// How much each heuristic contributes to a pixel's saliency
+const EDGE_WEIGHT: f32 = 0.2;
+const SKIN_WEIGHT: f32 = 1.8;
+const SATURATION_WEIGHT: f32 = 0.3;
+
+/// A typical skin tone as a unit RGB vector and how close (0-1) a colour must be to count
+const SKIN_COLOR: [f32; 3] = [0.78, 0.57, 0.44];
+const SKIN_THRESHOLD: f32 = 0.8;
+
+/// How saturated (0-1) a colour must be to count
+const SATURATION_THRESHOLD: f32 = 0.4;
+
+/// The luminance range skin and saturated colours are looked for in. Near black and white are ignored.
+const LUMINANCE_RANGE: (f32, f32) = (0.05, 0.95);
+
+/// The importance of salient cells left outside a crop. Negative so cutting off a subject is penalised.
+const OUTSIDE_IMPORTANCE: f32 = -0.5;
+
+/// Which parts of an image are interesting, measured from edges, skin tones and saturated colours
+/// as [smartcrop.js](https://github.com/jwagner/smartcrop.js) does
+pub struct Saliency {
+    raster: Raster,
+    /// The saliency of each pixel of `raster`
+    pixels: Vec<f32>,
+    columns: usize,
+    rows: usize,
+    /// The summed saliency of each `CELL_SIZE` square of `raster`
+    cells: Vec<f32>,
+}
+
+impl Saliency {
+    /// Measures the saliency of the decoded, upright `image`
+    pub fn of_image(image: &Image) -> Self {
+        Self::of_raster(raster(image, ANALYSIS_SIZE))
+    }
+
+    pub fn of_raster(raster: Raster) -> Self {
+        let luminance = raster
+            .pixels
+            .iter()
+            .map(|[r, g, b]| (0.2126 * r + 0.7152 * g + 0.0722 * b) / 255.0)
+            .collect::<Vec<f32>>();
+        let (width, height) = (raster.width, raster.height);
+        let at = |x: usize, y: usize| luminance[y * width + x];
+
+        let pixels = raster
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(index, pixel)| {
+                let (x, y) = (index % width, index / width);
+                let neighbours = at(x.saturating_sub(1), y)
+                    + at((x + 1).min(width - 1), y)
+                    + at(x, y.saturating_sub(1))
+                    + at(x, (y + 1).min(height - 1));
+                let edge = (4.0 * luminance[index] - neighbours).abs().min(1.0);
+                let in_range = (LUMINANCE_RANGE.0..=LUMINANCE_RANGE.1).contains(&luminance[index]);
+                let (skin, saturation) = if in_range {
+                    (skin(pixel), saturation(pixel))
+                } else {
+                    (0.0, 0.0)
+                };
+                edge * EDGE_WEIGHT + skin * SKIN_WEIGHT + saturation * SATURATION_WEIGHT
+            })
+            .collect::<Vec<f32>>();
+
+        let columns = width.div_ceil(CELL_SIZE);
+        let rows = height.div_ceil(CELL_SIZE);
+        let mut cells = vec![0.0; columns * rows];
+        for (index, saliency) in pixels.iter().enumerate() {
+            let (x, y) = (index % width, index / width);
+            cells[(y / CELL_SIZE) * columns + x / CELL_SIZE] += saliency;
+        }
+
+        Self {
+            raster,
+            pixels,
+            columns,
+            rows,
+            cells,
+        }
+    }
+
+    /// The most salient region with `aspect_ratio`, in the analysed image's pixels, and its scale
+    fn best_region(&self, aspect_ratio: AspectRatio) -> (Region, f64) {
+        let (width, height) = (self.raster.width, self.raster.height);
+        SCALES
+            .iter()
+            .flat_map(|scale| {
+                let size = Region::scaled_with_aspect_ratio(
+                    width,
+                    height,
+                    aspect_ratio,
+                    FocalPoint::CENTRE,
+                    *scale,
+                );
+                (0..=width - size.width)
+                    .step_by(CELL_SIZE)
+                    .flat_map(move |x| {
+                        (0..=height - size.height)
+                            .step_by(CELL_SIZE)
+                            .map(move |y| (Region { x, y, ..size }, *scale))
+                    })
+            })
+            .map(|(region, scale)| (self.score(region), region, scale))
+            .max_by(|(a, ..), (b, ..)| a.total_cmp(b))
+            .map(|(_, region, scale)| (region, scale))
+            .expect("There is always a crop at full scale")
+    }
+
+    /// The saliency of every cell weighted by how prominent it would be in `region`, per unit of area
+    fn score(&self, region: Region) -> f32 {
+        let mut total = 0.0;
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let saliency = self.cells[row * self.columns + column];
+                if saliency == 0.0 {
+                    continue;
+                }
+                let x = ((column as f32 + 0.5) * CELL_SIZE as f32 - region.x as f32)
+                    / region.width as f32;
+                let y = ((row as f32 + 0.5) * CELL_SIZE as f32 - region.y as f32)
+                    / region.height as f32;
+                total += saliency * importance(x, y);
+            }
+        }
+        total / (region.width * region.height) as f32
+    }
+
+    /// The most salient region of a `width` by `height` image with `aspect_ratio`.
+    /// The image must be the one that was analysed, at any size.
+    pub fn crop(&self, aspect_ratio: AspectRatio, width: usize, height: usize) -> Region {
+        let (best, scale) = self.best_region(aspect_ratio);
+        let focus = FocalPoint {
+            x: (best.x as f64 + best.width as f64 / 2.0) / self.raster.width as f64,
+            y: (best.y as f64 + best.height as f64 / 2.0) / self.raster.height as f64,
+        };
+        Region::scaled_with_aspect_ratio(width, height, aspect_ratio, focus, scale)
+    }
+
+    /// Writes a JPEG to `path` showing what was found salient and the region chosen for `aspect_ratio`.
+    /// Saliency is tinted green, skin tones red and saturated colours blue.
+    pub fn write_overlay(&self, path: &Path, aspect_ratio: AspectRatio) -> Result<()> {
+        let (region, _) = self.best_region(aspect_ratio);
+        let (width, height) = (self.raster.width, self.raster.height);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for (index, pixel) in self.raster.pixels.iter().enumerate() {
+            let (x, y) = (index % width, index / width);
+            let inside = x >= region.x
+                && x < region.x + region.width
+                && y >= region.y
+                && y < region.y + region.height;
+            let on_border = inside
+                && (x < region.x + 2
+                    || x + 2 >= region.x + region.width
+                    || y < region.y + 2
+                    || y + 2 >= region.y + region.height);
+            let overlay = if on_border {
+                [255.0, 0.0, 255.0]
+            } else {
+                let tint = [
+                    skin(pixel) * 127.0,
+                    self.pixels[index].min(1.0) * 127.0,
+                    saturation(pixel) * 127.0,
+                ];
+                [0, 1, 2].map(|channel| pixel[channel] * 0.5 + tint[channel])
+            };
+            pixels.extend(overlay.map(|c| c.clamp(0.0, 255.0) as u8));
+        }
+        let image = Image::from_u8(&pixels, width, height, ColorSpace::RGB);
+        write(path, OutputFormat::Jpeg.encode(&image)?)
+            .with_context(|| format!("Failed to write crop overlay {}", path.to_string_lossy()))
+    }
+}
+
+/// How close (0-1) a colour is to a skin tone
+fn skin([r, g, b]: &[f32; 3]) -> f32 {
+    let magnitude = (r * r + g * g + b * b).sqrt();
+    if magnitude == 0.0 {
+        return 0.0;
+    }
+    let distance = [r, g, b]
+        .iter()
+        .zip(SKIN_COLOR)
+        .map(|(c, skin)| (*c / magnitude - skin).powi(2))
+        .sum::<f32>()
+        .sqrt();
+    let similarity = 1.0 - distance;
+    if similarity > SKIN_THRESHOLD {
+        (similarity - SKIN_THRESHOLD) / (1.0 - SKIN_THRESHOLD)
+    } else {
+        0.0
+    }
+}
+
+/// How saturated (0-1) a colour is, beyond `SATURATION_THRESHOLD`
+fn saturation(pixel: &[f32; 3]) -> f32 {
+    let max = pixel.iter().copied().fold(0.0, f32::max) / 255.0;
+    let min = pixel.iter().copied().fold(255.0, f32::min) / 255.0;
+    if max == min {
+        return 0.0;
+    }
+    let lightness = (max + min) / 2.0;
+    let saturation = if lightness > 0.5 {
+        (max - min) / (2.0 - max - min)
+    } else {
+        (max - min) / (max + min)
+    };
+    if saturation > SATURATION_THRESHOLD {
+        (saturation - SATURATION_THRESHOLD) / (1.0 - SATURATION_THRESHOLD)
+    } else {
+        0.0
+    }
+}
+
+/// How prominent a point at `x`, `y` (fractions of a crop's width and height) is in the crop.
+/// The centre and the rule of thirds lines matter most and the edges least.
+fn importance(x: f32, y: f32) -> f32 {
+    if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+        return OUTSIDE_IMPORTANCE;
+    }
+    let px = (0.5 - x).abs() * 2.0;
+    let py = (0.5 - y).abs() * 2.0;
+    // Fade out within 40% of the edges
+    let dx = (px - 0.6).max(0.0);
+    let dy = (py - 0.6).max(0.0);
+    let edge = (dx * dx + dy * dy) * -20.0;
+    let centre = 1.41 - (px * px + py * py).sqrt();
+    let thirds = |p: f32| {
+        let distance = ((p - 1.0 / 3.0 + 1.0) % 2.0 * 0.5 - 0.5) * 16.0;
+        (1.0 - distance * distance).max(0.0)
+    };
+    centre + edge + (centre + edge + 0.5).max(0.0) * 1.2 * (thirds(px) + thirds(py))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::crop::SOCIAL_CARD_SIZE;
+
+    #[test]
+    fn test_crop_follows_the_subject() {
+        // A grey image with a skin toned square near the right edge
+        let (width, height) = (200, 100);
+        let pixels = (0..width * height)
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                if (150..180).contains(&x) && (35..65).contains(&y) {
+                    [224.0, 160.0, 120.0]
+                } else {
+                    [128.0; 3]
+                }
+            })
+            .collect();
+        let saliency = Saliency::of_raster(Raster::new(width, height, pixels));
+        let region = saliency.crop("1:1".parse().unwrap(), 2000, 1000);
+        let centre = region.x + region.width / 2;
+        assert!(
+            region.x + region.width >= 1800,
+            "{region} cuts off the subject"
+        );
+        assert!(centre > 1300, "{region} isn't centred near the subject");
+    }
+
+    #[test]
+    fn test_social_card_and_thumbnail_crops() {
+        // A grey image with a skin toned square near the top
+        let (width, height) = (100, 200);
+        let pixels = (0..width * height)
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                if (40..60).contains(&x) && (20..40).contains(&y) {
+                    [224.0, 160.0, 120.0]
+                } else {
+                    [128.0; 3]
+                }
+            })
+            .collect();
+        let saliency = Saliency::of_raster(Raster::new(width, height, pixels));
+        let (card_width, card_height) = SOCIAL_CARD_SIZE;
+        let card = AspectRatio {
+            width: card_width as f64,
+            height: card_height as f64,
+        };
+        for aspect_ratio in [card, AspectRatio::SQUARE] {
+            let region = saliency.crop(aspect_ratio, 1000, 2000);
+            let ratio = region.width as f64 / region.height as f64;
+            assert!((ratio - aspect_ratio.ratio()).abs() < 0.01, "{region}");
+            assert!(
+                region.y <= 200 && region.y + region.height >= 400,
+                "{region} cuts off the subject"
+            );
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Calls the Go [SQIP FFI](https://github.com/arranf/sqip-ffi) library, which must be built into `./build/libsqip.a`
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_longlong};
+
+use log::error;
+
+use super::SqipOptions;
+use crate::error::AppError;
+
+extern "C" {
+    fn MakeSVG(
+        path: GoString,
+        number_of_primitives: c_longlong,
+        mode: c_longlong,
+        alpha: c_longlong,
+        workers: c_longlong,
+    ) -> *const c_char;
+}
+
+/// See [here](http://blog.ralch.com/tutorial/golang-sharing-libraries/) for `GoString` struct layout
+// See the generated header file: libsqif.h
+#[repr(C)]
+struct GoString {
+    a: *const c_char,
+    b: isize,
+}
+
+/// Returns the SVG generated by the Go library
+pub fn make_svg(path: &str, options: &SqipOptions) -> Result<String, AppError> {
+    let c_path = CString::new(path).map_err(|_| AppError::SQIP())?;
+    let ptr = c_path.as_ptr();
+    let go_string = GoString {
+        a: ptr,
+        b: c_path.as_bytes().len() as isize,
+    };
+
+    let result = unsafe {
+        MakeSVG(
+            go_string,
+            options.primitives as c_longlong,
+            options.mode.code(),
+            c_longlong::from(options.alpha),
+            options.workers as c_longlong,
+        )
+    };
+    let c_str = unsafe { CStr::from_ptr(result) };
+    let string = c_str.to_str().map_err(|_| AppError::SQIP())?;
+    if string.is_empty() || string.starts_with("Error") {
+        error!("Failed to get SQIP from SQIP library: {}", string);
+        Err(AppError::SQIP())
+    } else {
+        Ok(string.to_owned())
+    }
+}
@@ -0,0 +1,100 @@
+#[cfg(feature = "sqip-ffi")]
+mod ffi;
+pub mod primitive;
+
+use base64::prelude::*;
+use zune_image::image::Image;
+
+use crate::error::AppError;
+use primitive::ShapeMode;
+
+/// The knobs SQIP placeholders are generated with
+#[derive(Debug, Clone)]
+pub struct SqipOptions {
+    /// The number of shapes drawn
+    pub primitives: usize,
+    pub mode: ShapeMode,
+    /// The opacity of each shape, from 1 to 255
+    pub alpha: u8,
+    /// The number of threads searching for shapes
+    pub workers: usize,
+}
+
+impl Default for SqipOptions {
+    fn default() -> Self {
+        Self {
+            primitives: 10,
+            mode: ShapeMode::Combo,
+            alpha: 128,
+            workers: num_cpus::get(),
+        }
+    }
+}
+
+/// Creates a base64 encoded [SQIP](https://github.com/axe312ger/sqip) SVG placeholder for `image`, decoded from `path`.
+///
+/// The Go library is used when built with the `sqip-ffi` feature, otherwise shapes are found in `image` with the
+/// `native-sqip` generator. The Go library decodes `path` itself.
+pub fn make_sqip(path: &str, image: &Image, options: &SqipOptions) -> Result<String, AppError> {
+    let svg = make_svg(path, image, options)?;
+    Ok(BASE64_STANDARD.encode(svg))
+}
+
+#[cfg(feature = "sqip-ffi")]
+fn make_svg(path: &str, _image: &Image, options: &SqipOptions) -> Result<String, AppError> {
+    ffi::make_svg(path, options)
+}
+
+#[cfg(all(feature = "native-sqip", not(feature = "sqip-ffi")))]
+fn make_svg(_path: &str, image: &Image, options: &SqipOptions) -> Result<String, AppError> {
+    /// The longest side of the image shapes are fitted to. SQIP works at 256px, but the placeholder is blurred anyway.
+    const WORKING_SIZE: usize = 128;
+
+    let (width, height) = image.dimensions();
+    Ok(primitive::generate(
+        &crate::image::placeholder::raster(image, WORKING_SIZE),
+        width,
+        height,
+        options.primitives,
+        options.mode,
+        options.alpha,
+        options.workers,
+    ))
+}
+
+#[cfg(not(any(feature = "native-sqip", feature = "sqip-ffi")))]
+fn make_svg(_path: &str, _image: &Image, _options: &SqipOptions) -> Result<String, AppError> {
+    log::error!("Built without a SQIP generator. Enable the native-sqip or sqip-ffi feature");
+    Err(AppError::SQIP())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_dir;
+    use tempfile::tempdir;
+
+    const IMAGE_FILE: &str = "./test/test.png";
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    // TODO: make integration test
+    #[test]
+    fn test_make_sqip_happy() {
+        init();
+        let dest_dir = tempdir().unwrap();
+
+        // Nothing there to begin with
+        let paths = read_dir(dest_dir.path()).unwrap();
+        assert_eq!(0, paths.count());
+
+        let image = load_image::Loader::new().load_path(IMAGE_FILE).unwrap();
+        let image = crate::image::conversion::from_loaded_image(image).image;
+        let sqip = make_sqip(IMAGE_FILE, &image, &SqipOptions::default());
+        assert!(sqip.is_ok());
+
+        dest_dir.close().unwrap();
+    }
+}
@@ -0,0 +1,678 @@
+//! A pure Rust approximation of [primitive](https://github.com/fogleman/primitive), which SQIP is built on.
+//!
+//! Shapes are added one at a time. Each is the best of a batch of random candidates refined by hill climbing,
+//! where "best" means it reduces the squared error between the drawing and the image the most.
+
+use rayon::prelude::*;
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// The number of random shapes each worker tries before hill climbing
+const RANDOM_CANDIDATES: usize = 64;
+
+/// The number of failed mutations in a row after which hill climbing stops
+const MAX_AGE: usize = 100;
+
+/// The largest initial size of a shape, in pixels of the working image
+const MAX_SHAPE_SIZE: f32 = 32.0;
+
+/// The number of points used to draw an ellipse when rasterizing
+const ELLIPSE_POINTS: usize = 24;
+
+/// The blur applied to the shapes. SQIP blurs its 256px drawings by 12.
+const BLUR_STD_DEVIATION: f32 = 6.0;
+
+/// An RGB image with components from 0 to 255
+#[derive(Debug, Clone)]
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl Raster {
+    pub fn new(width: usize, height: usize, pixels: Vec<[f32; 3]>) -> Self {
+        debug_assert_eq!(width * height, pixels.len());
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Averages blocks of pixels so the longest side is at most `max_side`
+    pub fn downscale(&self, max_side: usize) -> Raster {
+        Self::downscaled(self.width, self.height, max_side, |x, y| {
+            self.pixels[y * self.width + x]
+        })
+    }
+
+    /// Averages blocks of the `width` by `height` pixels given by `pixel(x, y)` so the longest side is at most
+    /// `max_side`, without holding every pixel at once
+    pub fn downscaled(
+        source_width: usize,
+        source_height: usize,
+        max_side: usize,
+        pixel: impl Fn(usize, usize) -> [f32; 3],
+    ) -> Raster {
+        let scale = (source_width.max(source_height) as f32 / max_side as f32).max(1.0);
+        let width = ((source_width as f32 / scale).round() as usize).max(1);
+        let height = ((source_height as f32 / scale).round() as usize).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let (y0, y1) = block(y, height, source_height);
+            for x in 0..width {
+                let (x0, x1) = block(x, width, source_width);
+                let mut sum = [0.0; 3];
+                for source_y in y0..y1 {
+                    for source_x in x0..x1 {
+                        let pixel = pixel(source_x, source_y);
+                        for channel in 0..3 {
+                            sum[channel] += pixel[channel];
+                        }
+                    }
+                }
+                let count = ((y1 - y0) * (x1 - x0)) as f32;
+                pixels.push(sum.map(|total| total / count));
+            }
+        }
+        Raster::new(width, height, pixels)
+    }
+
+    fn average(&self) -> [f32; 3] {
+        let mut sum = [0.0; 3];
+        for pixel in &self.pixels {
+            for channel in 0..3 {
+                sum[channel] += pixel[channel];
+            }
+        }
+        sum.map(|total| total / self.pixels.len() as f32)
+    }
+}
+
+/// The range of source pixels averaged into pixel `index` of `size` when scaling from `source_size`
+fn block(index: usize, size: usize, source_size: usize) -> (usize, usize) {
+    let start = index * source_size / size;
+    let end = ((index + 1) * source_size / size).max(start + 1);
+    (start, end.min(source_size))
+}
+
+/// Which shapes a placeholder is drawn with. Matches primitive's `-m` modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeMode {
+    Combo,
+    Triangle,
+    Rectangle,
+    Ellipse,
+    Circle,
+    RotatedRectangle,
+    RotatedEllipse,
+}
+
+impl ShapeMode {
+    const NAMES: &'static [(&'static str, ShapeMode)] = &[
+        ("combo", ShapeMode::Combo),
+        ("triangle", ShapeMode::Triangle),
+        ("rectangle", ShapeMode::Rectangle),
+        ("ellipse", ShapeMode::Ellipse),
+        ("circle", ShapeMode::Circle),
+        ("rotated-rectangle", ShapeMode::RotatedRectangle),
+        ("rotated-ellipse", ShapeMode::RotatedEllipse),
+    ];
+
+    /// primitive's number for the mode
+    pub fn code(&self) -> i64 {
+        match self {
+            ShapeMode::Combo => 0,
+            ShapeMode::Triangle => 1,
+            ShapeMode::Rectangle => 2,
+            ShapeMode::Ellipse => 3,
+            ShapeMode::Circle => 4,
+            ShapeMode::RotatedRectangle => 5,
+            ShapeMode::RotatedEllipse => 7,
+        }
+    }
+}
+
+impl FromStr for ShapeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        Self::NAMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, mode)| *mode)
+            .ok_or_else(|| {
+                let names = Self::NAMES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{s} is not a shape mode. Expected one of {names}")
+            })
+    }
+}
+
+impl fmt::Display for ShapeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = Self::NAMES
+            .iter()
+            .find(|(_, mode)| mode == self)
+            .map(|(name, _)| *name)
+            .unwrap_or_default();
+        write!(f, "{name}")
+    }
+}
+
+/// A small, seedable random number generator (xorshift64*) so placeholders are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number in `0..1`
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// A normally distributed number with a standard deviation of 1
+    fn normal(&mut self) -> f32 {
+        let u1 = self.unit().max(f32::EPSILON);
+        let u2 = self.unit();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Shape {
+    Triangle([(f32, f32); 3]),
+    Rectangle {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+    },
+    Ellipse {
+        cx: f32,
+        cy: f32,
+        rx: f32,
+        ry: f32,
+    },
+    Circle {
+        cx: f32,
+        cy: f32,
+        r: f32,
+    },
+    RotatedRectangle {
+        cx: f32,
+        cy: f32,
+        sx: f32,
+        sy: f32,
+        angle: f32,
+    },
+    RotatedEllipse {
+        cx: f32,
+        cy: f32,
+        rx: f32,
+        ry: f32,
+        angle: f32,
+    },
+}
+
+impl Shape {
+    fn random(mode: ShapeMode, width: f32, height: f32, rng: &mut Rng) -> Self {
+        let mode = match mode {
+            ShapeMode::Combo => ShapeMode::NAMES[1 + rng.below(ShapeMode::NAMES.len() - 1)].1,
+            mode => mode,
+        };
+        let x = rng.unit() * width;
+        let y = rng.unit() * height;
+        let mut size = || 1.0 + rng.unit() * MAX_SHAPE_SIZE;
+        let (a, b) = (size(), size());
+        match mode {
+            ShapeMode::Triangle => {
+                let mut offset = || (rng.unit() - 0.5) * MAX_SHAPE_SIZE;
+                Shape::Triangle([
+                    (x, y),
+                    (x + offset(), y + offset()),
+                    (x + offset(), y + offset()),
+                ])
+            }
+            ShapeMode::Rectangle => Shape::Rectangle {
+                x1: x,
+                y1: y,
+                x2: (x + a).min(width),
+                y2: (y + b).min(height),
+            },
+            ShapeMode::Ellipse => Shape::Ellipse {
+                cx: x,
+                cy: y,
+                rx: a,
+                ry: b,
+            },
+            ShapeMode::Circle => Shape::Circle { cx: x, cy: y, r: a },
+            ShapeMode::RotatedRectangle => Shape::RotatedRectangle {
+                cx: x,
+                cy: y,
+                sx: a,
+                sy: b,
+                angle: rng.unit() * 360.0,
+            },
+            ShapeMode::RotatedEllipse | ShapeMode::Combo => Shape::RotatedEllipse {
+                cx: x,
+                cy: y,
+                rx: a,
+                ry: b,
+                angle: rng.unit() * 360.0,
+            },
+        }
+    }
+
+    /// Nudges one of the shape's parameters
+    fn mutate(&mut self, width: f32, height: f32, rng: &mut Rng) {
+        let clamp_x = |v: f32| v.clamp(0.0, width - 1.0);
+        let clamp_y = |v: f32| v.clamp(0.0, height - 1.0);
+        let step = 16.0 * rng.normal();
+        let size = |v: f32| (v + step).max(1.0);
+        match self {
+            Shape::Triangle(points) => {
+                let point = &mut points[rng.below(3)];
+                point.0 = clamp_x(point.0 + 16.0 * rng.normal());
+                point.1 = clamp_y(point.1 + 16.0 * rng.normal());
+            }
+            Shape::Rectangle { x1, y1, x2, y2 } => match rng.below(2) {
+                0 => {
+                    *x1 = clamp_x(*x1 + step);
+                    *y1 = clamp_y(*y1 + 16.0 * rng.normal());
+                }
+                _ => {
+                    *x2 = clamp_x(*x2 + step);
+                    *y2 = clamp_y(*y2 + 16.0 * rng.normal());
+                }
+            },
+            Shape::Ellipse { cx, cy, rx, ry } => match rng.below(3) {
+                0 => {
+                    *cx = clamp_x(*cx + step);
+                    *cy = clamp_y(*cy + 16.0 * rng.normal());
+                }
+                1 => *rx = size(*rx),
+                _ => *ry = size(*ry),
+            },
+            Shape::Circle { cx, cy, r } => match rng.below(2) {
+                0 => {
+                    *cx = clamp_x(*cx + step);
+                    *cy = clamp_y(*cy + 16.0 * rng.normal());
+                }
+                _ => *r = size(*r),
+            },
+            Shape::RotatedRectangle {
+                cx,
+                cy,
+                sx: a,
+                sy: b,
+                angle,
+            }
+            | Shape::RotatedEllipse {
+                cx,
+                cy,
+                rx: a,
+                ry: b,
+                angle,
+            } => match rng.below(4) {
+                0 => {
+                    *cx = clamp_x(*cx + step);
+                    *cy = clamp_y(*cy + 16.0 * rng.normal());
+                }
+                1 => *a = size(*a),
+                2 => *b = size(*b),
+                _ => *angle += 2.0 * step,
+            },
+        }
+    }
+
+    /// The outline of the shape as a polygon
+    fn polygon(&self) -> Vec<(f32, f32)> {
+        let ellipse = |cx: f32, cy: f32, rx: f32, ry: f32, angle: f32| {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            (0..ELLIPSE_POINTS)
+                .map(|i| {
+                    let theta = 2.0 * PI * i as f32 / ELLIPSE_POINTS as f32;
+                    let (x, y) = (rx * theta.cos(), ry * theta.sin());
+                    (cx + x * cos - y * sin, cy + x * sin + y * cos)
+                })
+                .collect()
+        };
+        match *self {
+            Shape::Triangle(points) => points.to_vec(),
+            Shape::Rectangle { x1, y1, x2, y2 } => {
+                vec![(x1, y1), (x2, y1), (x2, y2), (x1, y2)]
+            }
+            Shape::Ellipse { cx, cy, rx, ry } => ellipse(cx, cy, rx, ry, 0.0),
+            Shape::Circle { cx, cy, r } => ellipse(cx, cy, r, r, 0.0),
+            Shape::RotatedRectangle {
+                cx,
+                cy,
+                sx,
+                sy,
+                angle,
+            } => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                    .iter()
+                    .map(|(x, y)| {
+                        let (x, y) = (x * sx / 2.0, y * sy / 2.0);
+                        (cx + x * cos - y * sin, cy + x * sin + y * cos)
+                    })
+                    .collect()
+            }
+            Shape::RotatedEllipse {
+                cx,
+                cy,
+                rx,
+                ry,
+                angle,
+            } => ellipse(cx, cy, rx, ry, angle),
+        }
+    }
+
+    /// The pixels covered by the shape as `(y, first x, last x)` spans
+    fn scanlines(&self, width: usize, height: usize) -> Vec<(usize, usize, usize)> {
+        let polygon = self.polygon();
+        let min_y = polygon
+            .iter()
+            .map(|p| p.1)
+            .fold(f32::MAX, f32::min)
+            .max(0.0) as usize;
+        let max_y = polygon
+            .iter()
+            .map(|p| p.1)
+            .fold(f32::MIN, f32::max)
+            .min(height as f32 - 1.0);
+        if max_y < 0.0 {
+            return Vec::new();
+        }
+        let mut lines = Vec::new();
+        let mut crossings = Vec::new();
+        for y in min_y..=max_y as usize {
+            let sample_y = y as f32 + 0.5;
+            crossings.clear();
+            for (i, &(x0, y0)) in polygon.iter().enumerate() {
+                let (x1, y1) = polygon[(i + 1) % polygon.len()];
+                if (y0 <= sample_y) != (y1 <= sample_y) {
+                    crossings.push(x0 + (sample_y - y0) / (y1 - y0) * (x1 - x0));
+                }
+            }
+            crossings.sort_by(f32::total_cmp);
+            for pair in crossings.chunks_exact(2) {
+                let start = (pair[0] - 0.5).ceil().max(0.0);
+                let end = (pair[1] - 0.5).floor().min(width as f32 - 1.0);
+                if start <= end {
+                    lines.push((y, start as usize, end as usize));
+                }
+            }
+        }
+        lines
+    }
+
+    fn svg(&self, color: [f32; 3]) -> String {
+        let fill = format!(
+            "#{:02x}{:02x}{:02x}",
+            color[0] as u8, color[1] as u8, color[2] as u8
+        );
+        let points = self
+            .polygon()
+            .iter()
+            .map(|(x, y)| format!("{x:.1},{y:.1}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        match *self {
+            Shape::Rectangle { x1, y1, x2, y2 } => format!(
+                r#"<rect fill="{fill}" x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}"/>"#,
+                x1.min(x2),
+                y1.min(y2),
+                (x2 - x1).abs(),
+                (y2 - y1).abs()
+            ),
+            Shape::Ellipse { cx, cy, rx, ry } => format!(
+                r#"<ellipse fill="{fill}" cx="{cx:.1}" cy="{cy:.1}" rx="{rx:.1}" ry="{ry:.1}"/>"#
+            ),
+            Shape::Circle { cx, cy, r } => {
+                format!(r#"<circle fill="{fill}" cx="{cx:.1}" cy="{cy:.1}" r="{r:.1}"/>"#)
+            }
+            Shape::RotatedEllipse {
+                cx,
+                cy,
+                rx,
+                ry,
+                angle,
+            } => format!(
+                r#"<ellipse fill="{fill}" transform="rotate({angle:.1} {cx:.1} {cy:.1})" cx="{cx:.1}" cy="{cy:.1}" rx="{rx:.1}" ry="{ry:.1}"/>"#
+            ),
+            Shape::Triangle(_) | Shape::RotatedRectangle { .. } => {
+                format!(r#"<polygon fill="{fill}" points="{points}"/>"#)
+            }
+        }
+    }
+}
+
+/// A shape, the colour it's best drawn in and how much it changes the error by
+struct Candidate {
+    shape: Shape,
+    color: [f32; 3],
+    delta: f32,
+}
+
+/// The image being approximated and the drawing so far
+struct Model<'a> {
+    target: &'a Raster,
+    current: Vec<[f32; 3]>,
+    alpha: f32,
+}
+
+impl Model<'_> {
+    fn evaluate(&self, shape: Shape) -> Candidate {
+        let lines = shape.scanlines(self.target.width, self.target.height);
+        let pixels = || {
+            lines
+                .iter()
+                .flat_map(move |&(y, x1, x2)| (x1..=x2).map(move |x| y * self.target.width + x))
+        };
+
+        // The colour which, blended at `alpha`, best matches the target
+        let mut sum = [0.0; 3];
+        let mut count = 0.0;
+        for i in pixels() {
+            let (target, current) = (self.target.pixels[i], self.current[i]);
+            for channel in 0..3 {
+                sum[channel] +=
+                    (target[channel] - current[channel] * (1.0 - self.alpha)) / self.alpha;
+            }
+            count += 1.0;
+        }
+        if count == 0.0 {
+            return Candidate {
+                shape,
+                color: [0.0; 3],
+                delta: 0.0,
+            };
+        }
+        let color = sum.map(|total| (total / count).clamp(0.0, 255.0));
+
+        let mut delta = 0.0;
+        for i in pixels() {
+            let (target, current) = (self.target.pixels[i], self.current[i]);
+            for channel in 0..3 {
+                let blended = current[channel] * (1.0 - self.alpha) + color[channel] * self.alpha;
+                delta += (target[channel] - blended).powi(2)
+                    - (target[channel] - current[channel]).powi(2);
+            }
+        }
+        Candidate {
+            shape,
+            color,
+            delta,
+        }
+    }
+
+    /// The best of a batch of random shapes, improved by mutating it until that stops helping
+    fn search(&self, mode: ShapeMode, rng: &mut Rng) -> Candidate {
+        let (width, height) = (self.target.width as f32, self.target.height as f32);
+        let mut best = (0..RANDOM_CANDIDATES)
+            .map(|_| self.evaluate(Shape::random(mode, width, height, rng)))
+            .min_by(|a, b| a.delta.total_cmp(&b.delta))
+            .expect("At least one candidate is tried");
+        let mut age = 0;
+        while age < MAX_AGE {
+            let mut shape = best.shape.clone();
+            shape.mutate(width, height, rng);
+            let candidate = self.evaluate(shape);
+            if candidate.delta < best.delta {
+                best = candidate;
+                age = 0;
+            } else {
+                age += 1;
+            }
+        }
+        best
+    }
+
+    fn draw(&mut self, candidate: &Candidate) {
+        for (y, x1, x2) in candidate
+            .shape
+            .scanlines(self.target.width, self.target.height)
+        {
+            for x in x1..=x2 {
+                let pixel = &mut self.current[y * self.target.width + x];
+                for (component, color) in pixel.iter_mut().zip(candidate.color) {
+                    *component = *component * (1.0 - self.alpha) + color * self.alpha;
+                }
+            }
+        }
+    }
+}
+
+/// Approximates `target` with `primitives` translucent shapes, returning a blurred SVG of `width` by `height`.
+/// The shapes are searched for on `workers` threads.
+pub fn generate(
+    target: &Raster,
+    width: usize,
+    height: usize,
+    primitives: usize,
+    mode: ShapeMode,
+    alpha: u8,
+    workers: usize,
+) -> String {
+    let background = target.average();
+    let mut model = Model {
+        target,
+        current: vec![background; target.pixels.len()],
+        alpha: f32::from(alpha.max(1)) / 255.0,
+    };
+    let mut shapes = Vec::with_capacity(primitives);
+    for step in 0..primitives {
+        let best = (0..workers.max(1))
+            .into_par_iter()
+            .map(|worker| {
+                let mut rng = Rng::new((step * workers.max(1) + worker) as u64 + 1);
+                model.search(mode, &mut rng)
+            })
+            .min_by(|a, b| a.delta.total_cmp(&b.delta))
+            .expect("At least one worker searches");
+        if best.delta >= 0.0 {
+            break;
+        }
+        model.draw(&best);
+        shapes.push(best.shape.svg(best.color));
+    }
+
+    let [r, g, b] = background.map(|c| c as u8);
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+            r#"<filter id="b"><feGaussianBlur stdDeviation="{}"/></filter>"#,
+            r##"<rect width="100%" height="100%" fill="#{:02x}{:02x}{:02x}"/>"##,
+            r#"<g filter="url(#b)" fill-opacity="{:.3}">{}</g></svg>"#
+        ),
+        width,
+        height,
+        target.width,
+        target.height,
+        BLUR_STD_DEVIATION,
+        r,
+        g,
+        b,
+        model.alpha,
+        shapes.join("")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A dark image with a bright square in the middle
+    fn square() -> Raster {
+        let pixels = (0..64 * 48)
+            .map(|i| {
+                let (x, y) = (i % 64, i / 64);
+                if (16..48).contains(&x) && (12..36).contains(&y) {
+                    [240.0, 200.0, 40.0]
+                } else {
+                    [20.0, 30.0, 60.0]
+                }
+            })
+            .collect();
+        Raster::new(64, 48, pixels)
+    }
+
+    #[test]
+    fn test_generate_is_deterministic_svg() {
+        let raster = square();
+        let svg = generate(&raster, 640, 480, 5, ShapeMode::Combo, 128, 2);
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="640" height="480""#)
+        );
+        assert!(svg.ends_with("</g></svg>"));
+        assert_eq!(
+            svg,
+            generate(&raster, 640, 480, 5, ShapeMode::Combo, 128, 2)
+        );
+    }
+
+    #[test]
+    fn test_shapes_reduce_error() {
+        let raster = square();
+        let model = Model {
+            target: &raster,
+            current: vec![raster.average(); raster.pixels.len()],
+            alpha: 0.5,
+        };
+        for mode in ShapeMode::NAMES.iter().map(|(_, mode)| *mode) {
+            let candidate = model.search(mode, &mut Rng::new(1));
+            assert!(candidate.delta < 0.0, "{mode} didn't improve the drawing");
+        }
+    }
+
+    #[test]
+    fn test_downscale() {
+        let raster = square().downscale(16);
+        assert_eq!((raster.width, raster.height), (16, 12));
+        assert_eq!(raster.pixels[0], [20.0, 30.0, 60.0]);
+    }
+}
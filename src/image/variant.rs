@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// Which version of an input image a file is.
+/// Every file name and key is derived from its variant so that they match wherever they are computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Variant {
+    /// Resized to one of the requested widths
+    Resized(usize),
+    /// The `crop`th art-directed crop (counting from 0), resized to `width`
+    Cropped { crop: usize, width: usize },
+    /// Resized to one of the requested widths keeping the source's wide gamut colour profile
+    WideGamut(usize),
+    /// The most interesting region with a social media link preview's aspect ratio, at its recommended size
+    SocialCard,
+    /// The most interesting square region, resized to `width`
+    Thumbnail(usize),
+    /// Re-encoded at full resolution
+    FullSize,
+    /// The untouched input file
+    Original,
+}
+
+impl Variant {
+    /// The suffix added to the input file's stem. e.g. `320w`
+    pub fn suffix(&self) -> String {
+        match self {
+            Variant::Resized(width) => format!("{width}w"),
+            Variant::Cropped { crop, width } => format!("crop{}-{width}w", crop + 1),
+            Variant::WideGamut(width) => format!("{width}w-wide-gamut"),
+            Variant::SocialCard => "social-card".to_owned(),
+            Variant::Thumbnail(width) => format!("thumbnail-{width}w"),
+            Variant::FullSize => "original".to_owned(),
+            Variant::Original => "copy-of-original".to_owned(),
+        }
+    }
+
+    /// The name of this variant's file, with spaces replaced. e.g. `my-photo-320w.avif`
+    pub fn file_name(&self, stem: &str, extension: &str) -> String {
+        format!("{stem}-{}.{extension}", self.suffix()).replace(' ', "-")
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.suffix())
+    }
+}
@@ -0,0 +1,273 @@
+use serde::{Deserialize, Serialize};
+
+use crate::image::crop::Crop;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The device pixel ratio images are generated for when a layout doesn't set one
+pub const DEFAULT_MAX_DENSITY: f64 = 2.0;
+
+/// How wide an image is rendered, in CSS pixels or as a percentage of the viewport. e.g. `720px` or `50vw`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RenderedWidth {
+    Pixels(usize),
+    ViewportWidth(f64),
+}
+
+impl RenderedWidth {
+    /// The widest the image is rendered, in CSS pixels, on a viewport at most `viewport` pixels wide.
+    /// `None` when the width grows with an unbounded viewport.
+    fn max_pixels(&self, viewport: Option<usize>) -> Option<f64> {
+        match self {
+            RenderedWidth::Pixels(pixels) => Some(*pixels as f64),
+            RenderedWidth::ViewportWidth(vw) => {
+                viewport.map(|viewport| viewport as f64 * vw / 100.0)
+            }
+        }
+    }
+}
+
+impl FromStr for RenderedWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let parsed = if let Some(pixels) = s.strip_suffix("px") {
+            pixels.trim().parse().ok().map(RenderedWidth::Pixels)
+        } else if let Some(vw) = s.strip_suffix("vw") {
+            vw.trim()
+                .parse()
+                .ok()
+                .filter(|vw: &f64| *vw > 0.0)
+                .map(RenderedWidth::ViewportWidth)
+        } else {
+            None
+        };
+        parsed.ok_or_else(|| {
+            format!("{s} is not a rendered width. Expected pixels or vw. e.g. 720px or 50vw")
+        })
+    }
+}
+
+impl TryFrom<String> for RenderedWidth {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<RenderedWidth> for String {
+    fn from(width: RenderedWidth) -> Self {
+        width.to_string()
+    }
+}
+
+impl fmt::Display for RenderedWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderedWidth::Pixels(pixels) => write!(f, "{pixels}px"),
+            RenderedWidth::ViewportWidth(vw) => write!(f, "{vw}vw"),
+        }
+    }
+}
+
+/// The width an image is rendered at on viewports up to `max_width` pixels wide
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Breakpoint {
+    /// The widest viewport, in CSS pixels, this breakpoint applies to. Unset for the last breakpoint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub max_width: Option<usize>,
+    pub width: RenderedWidth,
+}
+
+/// Where an image sits on a page. e.g. a content column, a two-up gallery or a sidebar.
+///
+/// A layout computes an image's `sizes` attribute and decides which of the requested widths are worth generating.
+/// ```toml
+/// [layouts.content]
+/// breakpoints = [{ max_width = 760, width = "100vw" }, { width = "720px" }]
+///
+/// [[layouts.content.crops]]
+/// media = "(max-width: 600px)"
+/// aspect_ratio = "1:1"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "LayoutDefinition")]
+pub struct Layout {
+    /// Checked in order, as browsers do. Only the last breakpoint has no `max_width`.
+    pub breakpoints: Vec<Breakpoint>,
+    /// File names this layout is used for. `*` matches any run of characters. e.g. `*-sidebar.jpg`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// The highest device pixel ratio images are generated for
+    pub max_density: f64,
+    /// Art-directed crops, each shown instead of the full frame when its media query matches
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub crops: Vec<Crop>,
+}
+
+/// A [`Layout`] as written in configuration, before it is checked
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutDefinition {
+    breakpoints: Vec<Breakpoint>,
+    #[serde(default)]
+    files: Vec<String>,
+    max_density: Option<f64>,
+    #[serde(default)]
+    crops: Vec<Crop>,
+}
+
+impl TryFrom<LayoutDefinition> for Layout {
+    type Error = String;
+
+    fn try_from(definition: LayoutDefinition) -> Result<Self, Self::Error> {
+        let Some((last, rest)) = definition.breakpoints.split_last() else {
+            return Err("A layout needs at least one breakpoint".to_owned());
+        };
+        if last.max_width.is_some() {
+            return Err(format!(
+                "The last breakpoint of a layout applies to every wider viewport and can't have a max_width, but it is {}",
+                last.max_width.unwrap_or_default()
+            ));
+        }
+        if rest.iter().any(|breakpoint| breakpoint.max_width.is_none()) {
+            return Err(
+                "Every breakpoint of a layout other than the last needs a max_width".to_owned(),
+            );
+        }
+        let max_density = definition.max_density.unwrap_or(DEFAULT_MAX_DENSITY);
+        if max_density < 1.0 {
+            return Err(format!(
+                "A layout's max_density must be at least 1, but it is {max_density}"
+            ));
+        }
+        Ok(Self {
+            breakpoints: definition.breakpoints,
+            files: definition.files,
+            max_density,
+            crops: definition.crops,
+        })
+    }
+}
+
+impl Layout {
+    /// The `sizes` attribute for images rendered with this layout. e.g. `(max-width: 760px) 100vw, 720px`
+    pub fn sizes(&self) -> String {
+        self.breakpoints
+            .iter()
+            .map(|breakpoint| match breakpoint.max_width {
+                Some(max_width) => format!("(max-width: {max_width}px) {}", breakpoint.width),
+                None => breakpoint.width.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The widest the image is ever rendered, in device pixels, or `None` if that is unbounded
+    fn max_rendered_width(&self) -> Option<f64> {
+        self.breakpoints
+            .iter()
+            .map(|breakpoint| breakpoint.width.max_pixels(breakpoint.max_width))
+            .try_fold(0.0, |widest: f64, pixels| {
+                pixels.map(|pixels| widest.max(pixels))
+            })
+            .map(|widest| widest * self.max_density)
+    }
+
+    /// Removes widths larger than the image is ever displayed at, keeping the smallest width that covers it.
+    /// The order of `widths` is preserved.
+    pub fn useful_widths(&self, widths: &[usize]) -> Vec<usize> {
+        let Some(max) = self.max_rendered_width() else {
+            return widths.to_vec();
+        };
+        let covering = widths
+            .iter()
+            .copied()
+            .filter(|width| *width as f64 >= max)
+            .min();
+        widths
+            .iter()
+            .copied()
+            .filter(|width| (*width as f64) < max || Some(*width) == covering)
+            .collect()
+    }
+
+    /// How specifically this layout lists the file called `file_name`: the number of characters, other than `*`,
+    /// of the longest pattern in `files` matching it. `None` when no pattern matches.
+    pub fn specificity(&self, file_name: &str) -> Option<usize> {
+        self.files
+            .iter()
+            .filter(|pattern| wildcard_match(pattern, file_name))
+            .map(|pattern| pattern.chars().filter(|c| *c != '*').count())
+            .max()
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any run of characters
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` in the pattern
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(toml: &str) -> Result<Layout, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    #[test]
+    fn test_sizes_and_useful_widths() {
+        let content =
+            layout(r#"breakpoints = [{ max_width = 760, width = "100vw" }, { width = "720px" }]"#)
+                .unwrap();
+        assert_eq!(content.sizes(), "(max-width: 760px) 100vw, 720px");
+        // Rendered at most 760px wide at 2x, so 1600 covers it and the larger widths are wasted
+        assert_eq!(
+            content.useful_widths(&[320, 640, 1024, 1600, 1920, 1440, 1800]),
+            vec![320, 640, 1024, 1600, 1440]
+        );
+
+        let full_width = layout(r#"breakpoints = [{ width = "100vw" }]"#).unwrap();
+        assert_eq!(full_width.sizes(), "100vw");
+        assert_eq!(full_width.useful_widths(&[320, 1920]), vec![320, 1920]);
+    }
+
+    #[test]
+    fn test_invalid_layouts_are_rejected() {
+        assert!(layout("breakpoints = []").is_err());
+        assert!(layout(r#"breakpoints = [{ max_width = 600, width = "100vw" }]"#).is_err());
+        assert!(layout(r#"breakpoints = [{ width = "50vw" }, { width = "300px" }]"#).is_err());
+        assert!(layout(r#"breakpoints = [{ width = "50%" }]"#).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*-sidebar.*", "cat-sidebar.jpg"));
+        assert!(wildcard_match("cat.jpg", "cat.jpg"));
+        assert!(!wildcard_match("cat.jpg", "cat.jpeg"));
+        assert!(!wildcard_match("*-sidebar.*", "cat.jpg"));
+        assert!(wildcard_match("gallery-*", "gallery-1.png"));
+    }
+}
@@ -1,100 +1,174 @@
 #![warn(clippy::all)]
 
+pub mod config;
 pub mod constants;
 mod data;
 pub mod error;
 pub mod image;
+pub mod layout;
+pub mod lint;
 pub mod metrics;
 pub mod options;
+pub mod privacy;
+pub mod sidecar;
 pub mod upload;
 
+use crate::config::Config;
+use crate::constants::DEFAULT_DATA_FILE;
 use crate::data::hugo::HugoData;
 use crate::error::AppError;
 use crate::image::*;
+use crate::layout::Layout;
 use crate::metrics::Metrics;
 use crate::options::Options;
-use crate::sqip::*;
+use crate::privacy::scrub;
+use crate::sidecar::{Manifest, Sidecar};
 
+use budget::{estimate_decoded_size, MemoryBudget};
+use color::WIDE_GAMUT_MEDIA;
 use data::fallback_image::FallbackImage;
+use data::image_file::ImageFile;
+use data::source::Source;
+use format::OutputFormat;
 use generated_image::GeneratedImage;
-use image::image::{digest_path, process_image, MIME_TABLE};
+use image::image::{digest_path, process_image, ProcessingResources};
 use image_info::ImageInfo;
 use itertools::Itertools;
+use variant::Variant;
 
-use anyhow::{Context, Result};
-use chrono::prelude::*;
-use indicatif::ProgressBar;
+use anyhow::{bail, Context, Result};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::{debug, info, warn};
-use s3::bucket::Bucket;
-use s3::creds::Credentials;
-use s3::Region;
-use upload::constants::{BUCKET_NAME, REGION, WEB_PREFIX};
+use rayon::prelude::*;
+use upload::conflict::{upload_object, UploadReport};
+use upload::constants::IMMUTABLE_CACHE_CONTROL;
+use upload::keys::KeyClaims;
+use upload::object::{KeyContext, ObjectFile};
+use upload::storage::{PutOptions, Storage};
 use upload::uploadable::Uploadable;
 
 use std::fs::{create_dir_all, metadata, read_to_string};
 use std::io::Read;
 use std::iter::once;
 use std::path::{Path, PathBuf};
-
-/// Upload images from a directory to S3
+use std::sync::atomic::Ordering;
+
+/// Upload every image to storage.
+///
+/// Uploads run concurrently on a pool of `config.upload_parallelism` workers and transient failures are retried
+/// with exponential backoff. The returned images are in the same order as `images`.
+///
+/// Files identical to an object already at their key aren't uploaded again. A different object at a key is handled
+/// according to `config.on_conflict`. The returned report lists what was written and what was skipped.
 pub fn upload_images(
-    image: ImageInfo,
-    s3_sub_directory: &Option<String>,
-    now: DateTime<Local>,
-) -> Result<ImageInfo> {
-    // TODO: Do this in a way that handles errors and is not potentially misleading
-    let total_size: u64 = once(&image.full_size_reencoded_image)
-        .chain(&image.generated_images)
-        .filter_map(|image| metadata(image.path()).ok())
+    images: &[ImageInfo],
+    storage: &dyn Storage,
+    context: &KeyContext,
+) -> Result<(Vec<ImageInfo>, UploadReport)> {
+    let config = context.config;
+    let paths = images
+        .iter()
+        .flat_map(|image| {
+            once(image.full_size_reencoded_image.path())
+                .chain(once(image.original_image.path()))
+                .chain(image.generated_images.iter().map(|i| i.path()))
+        })
+        .collect::<Vec<_>>();
+    // Files which can't be read are reported when they are uploaded
+    let total_size: u64 = paths
+        .iter()
+        .filter_map(|path| metadata(path).ok())
         .map(|a| a.len())
-        .sum::<u64>()
-        // Figure out how to make this less ugly just because you can't chain it
-        + metadata(image.original_image.path()).ok().unwrap().len();
-
-    let prefix = get_uploaded_prefix(s3_sub_directory, now);
-
-    let region = REGION.parse::<Region>().map_err(AppError::RegionParse)?;
-
-    // Loads from environment variables
-    let credentials = Credentials::default()?;
-    let bucket = Bucket::new(BUCKET_NAME, region, credentials)?.with_path_style();
-
-    // TODO: Concurrency
-    let progress_bar = ProgressBar::new(total_size);
-    let mut s3_images: Vec<GeneratedImage> = Vec::with_capacity(image.generated_images.len() + 1);
-    let full_size_reencoded_image = upload_image(
-        &image.full_size_reencoded_image,
-        &prefix,
-        &bucket,
-        &progress_bar,
-        None,
-    )?;
-    let original_image = upload_image(
-        &image.original_image,
-        &prefix,
-        &bucket,
-        &progress_bar,
-        Some("copy-of-original".to_owned()),
-    )?;
-    for image in &image.generated_images {
-        let s3_image = upload_image(image, &prefix, &bucket, &progress_bar, None)?;
-        s3_images.push(s3_image)
-    }
-    progress_bar.finish_and_clear();
-    Ok(image
-        .with_full_size_reencoded_image(full_size_reencoded_image)
-        .with_original_image(original_image)
-        .with_generated_images(s3_images))
+        .sum();
+
+    let multi_progress = MultiProgress::new();
+    let state = UploadState {
+        bytes: multi_progress.add(ProgressBar::new(total_size).with_style(
+            ProgressStyle::with_template(
+                "{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+            )?,
+        )),
+        files: multi_progress.add(ProgressBar::new(paths.len() as u64).with_style(
+            ProgressStyle::with_template("{pos}/{len} files {wide_msg}")?,
+        )),
+        report: UploadReport::default(),
+        keys: KeyClaims::default(),
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.upload_parallelism.max(1))
+        .build()?;
+    let uploaded = pool.install(|| {
+        images
+            .par_iter()
+            .map(|image| {
+                let ((full_size_reencoded_image, original_image), generated_images) = rayon::join(
+                    || {
+                        rayon::join(
+                            || {
+                                upload_image(
+                                    &image.full_size_reencoded_image,
+                                    &ObjectFile::generated(image, &image.full_size_reencoded_image),
+                                    storage,
+                                    &state,
+                                    context,
+                                )
+                            },
+                            || {
+                                upload_image(
+                                    &image.original_image,
+                                    &ObjectFile::original(image),
+                                    storage,
+                                    &state,
+                                    context,
+                                )
+                            },
+                        )
+                    },
+                    || {
+                        image
+                            .generated_images
+                            .par_iter()
+                            .map(|generated| {
+                                upload_image(
+                                    generated,
+                                    &ObjectFile::generated(image, generated),
+                                    storage,
+                                    &state,
+                                    context,
+                                )
+                            })
+                            .collect::<Result<Vec<_>>>()
+                    },
+                );
+                Ok(image
+                    .with_full_size_reencoded_image(full_size_reencoded_image?)
+                    .with_original_image(original_image?)
+                    .with_generated_images(generated_images?))
+            })
+            .collect::<Result<Vec<_>>>()
+    });
+    state.bytes.finish_and_clear();
+    state.files.finish_and_clear();
+    Ok((uploaded?, state.report))
+}
+
+/// The progress and outcome of every upload in a call to [`upload_images`]
+struct UploadState {
+    bytes: ProgressBar,
+    files: ProgressBar,
+    report: UploadReport,
+    keys: KeyClaims,
 }
 
 fn upload_image<T: Uploadable>(
     image: &T,
-    prefix: &String,
-    bucket: &Bucket,
-    progress_bar: &ProgressBar,
-    suffix: Option<String>,
+    file: &ObjectFile,
+    storage: &dyn Storage,
+    state: &UploadState,
+    context: &KeyContext,
 ) -> Result<T> {
-    let s3_path = get_file_s3_bucket_path(&image.path(), prefix, suffix);
+    let config = context.config;
     let mut file_contents = std::fs::File::open(image.path())?;
     let size = file_contents.metadata()?.len();
     let mut bytes: Vec<u8> = Vec::with_capacity(size.try_into().with_context(|| {
@@ -104,103 +178,280 @@ fn upload_image<T: Uploadable>(
         )
     })?);
     file_contents.read_to_end(&mut bytes)?;
-    let extension = image
-        .path()
-        .extension()
-        .with_context(|| {
-            format!(
-                "Error getting extension for {}",
-                &image.path().to_string_lossy()
-            )
-        })?
-        .to_str()
-        .with_context(|| {
-            format!(
-                "Error getting extension for {}",
-                &image.path().to_string_lossy()
-            )
-        })?
-        .to_lowercase();
-    let mime_type = &MIME_TABLE.get(&extension as &str).with_context(|| {
-        format!(
-            "Failed to get a matching mimetype when processing {}. {} does not map to a mime-type.",
-            &image.path().to_string_lossy(),
-            extension
-        )
-    })?;
-    bucket.put_object_with_content_type_blocking(&s3_path, &bytes, mime_type)?;
-    progress_bar.inc(size);
-    Ok(image.with_s3_path(Some([WEB_PREFIX, &s3_path].join(""))))
+    let Some(bytes) = scrub(&bytes, config.privacy) else {
+        warn!(
+            "Not uploading {} as its metadata can't be removed from its format. Set privacy to keep-all to publish it anyway",
+            &image.path().to_string_lossy()
+        );
+        state.bytes.inc(size);
+        state.files.inc(1);
+        return Ok(image.with_s3_path(None));
+    };
+    let mime_type = file.content_type()?;
+    let s3_path = file.key(Some(bytes.as_ref()), context);
+    state.keys.claim(&s3_path, &image.path())?;
+    let mut put_options = PutOptions::new(mime_type);
+    if config.hashed_keys {
+        put_options.cache_control = Some(IMMUTABLE_CACHE_CONTROL);
+    }
+    let (key, outcome) = upload_object(
+        storage,
+        &s3_path,
+        &bytes,
+        put_options,
+        config.on_conflict,
+        config.upload_retries,
+    )?;
+    state.bytes.inc(size);
+    state.files.inc(1);
+    state.files.set_message(key.clone());
+    let url = storage.public_url(&key);
+    state.report.record(key, outcome);
+    Ok(image.with_s3_path(Some(url)))
 }
 
-// This is only public so main can use it. See: See: https://users.rust-lang.org/t/lib-rs-declare-module-publicly-visible-only-to-main-rs/97368
-#[doc(hidden)]
-/// Gets the path from a bucket's root to a file
-pub fn get_file_s3_bucket_path(path: &Path, prefix: &String, suffix: Option<String>) -> String {
-    let file_name = path
-        .file_name()
-        .with_context(|| format!("Failed to get file name for {}", &path.to_string_lossy()))
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .replace(' ', "-");
-
-    [
-        prefix.to_owned(),
-        file_name,
-        suffix.unwrap_or_else(|| String::from("")),
-    ]
-    .join("")
+/// Calculates the URLs the images would be uploaded to and returns the modified image info, without uploading anything.
+/// Keys are computed exactly as they are by [`upload_images`], but existing objects aren't checked for.
+pub fn fake_upload_images(images: &[ImageInfo], context: &KeyContext) -> Result<Vec<ImageInfo>> {
+    let keys = KeyClaims::default();
+    let web_prefix = context.config.web_prefix.as_str();
+    // `None` for files which wouldn't be uploaded as their metadata can't be removed
+    let locate = |path: &Path, file: &ObjectFile| -> Result<Option<String>> {
+        let contents = std::fs::read(path)?;
+        let Some(scrubbed) = scrub(&contents, context.config.privacy) else {
+            warn!(
+                "Not uploading {} as its metadata can't be removed from its format. Set privacy to keep-all to publish it anyway",
+                path.to_string_lossy()
+            );
+            return Ok(None);
+        };
+        // Keys are hashed from the contents that would be uploaded
+        let bytes = context.needs_contents().then_some(scrubbed.as_ref());
+        let key = file.key(bytes, context);
+        keys.claim(&key, path)?;
+        Ok(Some([web_prefix, &key].join("")))
+    };
+
+    images
+        .iter()
+        .map(|image| {
+            let generated_images = image
+                .generated_images
+                .iter()
+                .map(|generated| {
+                    let url = locate(&generated.path, &ObjectFile::generated(image, generated))?;
+                    Ok(generated.with_s3_path(url))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let full_size_reencoded_image = &image.full_size_reencoded_image;
+            let full_size_url = locate(
+                &full_size_reencoded_image.path,
+                &ObjectFile::generated(image, full_size_reencoded_image),
+            )?;
+            let original_url = locate(&image.original_image.path, &ObjectFile::original(image))?;
+            Ok(image
+                .with_generated_images(generated_images)
+                .with_full_size_reencoded_image(
+                    full_size_reencoded_image.with_s3_path(full_size_url),
+                )
+                .with_original_image(image.original_image.with_s3_path(original_url)))
+        })
+        .collect()
 }
 
 /// Creates the data to be written to file
-pub fn generate_data(s3_images: Vec<ImageInfo>, options: &Options) -> Vec<HugoData> {
+pub fn generate_data(
+    s3_images: Vec<ImageInfo>,
+    options: &Options,
+    config: &Config,
+) -> Result<Vec<HugoData>> {
     let mut data: Vec<HugoData> = Vec::with_capacity(s3_images.len());
     for image in s3_images {
-        let image = image.clone();
-        let srcset = image
-            .clone()
-            .generated_images
+        // Generated files whose metadata couldn't be removed weren't uploaded, so aren't listed
+        let image = image.with_generated_images(
+            image
+                .generated_images
+                .iter()
+                .filter(|generated| generated.s3_path.is_some())
+                .cloned()
+                .collect(),
+        );
+        let srcset = get_srcset(image.generated_images_in(image.fallback));
+
+        let src = image
+            .generated_images_in(image.fallback)
+            .max_by_key(|x| x.width)
+            .and_then(|x| x.s3_path.clone())
+            .with_context(|| {
+                format!(
+                    "No {} file of {} was uploaded",
+                    image.fallback.extension(),
+                    image.original_image.path.to_string_lossy()
+                )
+            })?;
+
+        let sizes = image.layout.as_ref().map_or_else(
+            || format!("(max-width: {0}px) 100vw, {0}px", image.max_width),
+            Layout::sizes,
+        );
+
+        let placeholder_kind = image.sidecar.placeholder.unwrap_or(options.placeholder);
+        let placeholder = image.placeholder.clone();
+
+        let source_formats = options
+            .formats
+            .with_fallback(image.fallback)
+            .into_iter()
+            .filter(|format| *format != image.fallback)
+            .collect::<Vec<_>>();
+
+        // Browsers use the first matching source, so art-directed crops come before the full frame.
+        // Each crop lists the fallback format last as the `img` only shows the full frame.
+        let crops = image
+            .layout
+            .as_ref()
+            .map_or(&[][..], |layout| &layout.crops);
+        let art_directed = crops.iter().enumerate().flat_map(|(index, crop)| {
+            source_formats
+                .iter()
+                .copied()
+                .chain(once(image.fallback))
+                .filter(|format| image.cropped_images_in(index, *format).next().is_some())
+                .map(|format| {
+                    Source::new(
+                        Some(crop.media.clone()),
+                        crop.sizes.clone().unwrap_or_else(|| sizes.clone()),
+                        get_srcset(image.cropped_images_in(index, format)),
+                        placeholder.clone(),
+                        Some(format.mime_type().to_owned()),
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+        // Files keeping a wide gamut colour profile are only offered to displays which can show it
+        let wide_gamut = source_formats
             .iter()
-            .map(|i| format!("{} {}w", i.s3_path.as_ref().unwrap(), i.width))
-            .intersperse(",".to_owned())
+            .copied()
+            .chain(once(image.fallback))
+            .filter(|format| image.wide_gamut_images_in(*format).next().is_some())
+            .map(|format| {
+                Source::new(
+                    Some(WIDE_GAMUT_MEDIA.to_owned()),
+                    sizes.clone(),
+                    get_srcset(image.wide_gamut_images_in(format)),
+                    placeholder.clone(),
+                    Some(format.mime_type().to_owned()),
+                )
+            })
+            .collect::<Vec<_>>();
+        let sources = art_directed
+            .chain(wide_gamut)
+            .chain(
+                source_formats
+                    .iter()
+                    .filter(|format| image.generated_images_in(**format).next().is_some())
+                    .map(|format| {
+                        Source::new(
+                            None,
+                            sizes.clone(),
+                            get_srcset(image.generated_images_in(*format)),
+                            placeholder.clone(),
+                            Some(format.mime_type().to_owned()),
+                        )
+                    }),
+            )
             .collect();
 
-        let src_image = &image
+        let fallback = FallbackImage::new(src, sizes, srcset, placeholder);
+
+        let (width, height) = (
+            image.full_size_reencoded_image.width,
+            image.full_size_reencoded_image.height,
+        );
+        let image_file = |generated: &GeneratedImage| -> Result<ImageFile> {
+            let bytes = metadata(&generated.path)
+                .with_context(|| {
+                    format!(
+                        "Failed to read the size of {}",
+                        generated.path.to_string_lossy()
+                    )
+                })?
+                .len();
+            Ok(ImageFile::new(
+                generated.s3_path.clone().unwrap_or_default(),
+                generated.width,
+                generated.height,
+                generated.format.mime_type().to_owned(),
+                bytes,
+            ))
+        };
+        let variants = image
             .generated_images
             .iter()
-            .max_by_key(|x| x.width)
-            .unwrap();
-
-        let sizes = format!("(max-width: {0}px) 100vw, {0}px", image.max_width);
+            .sorted_by_key(|generated| (generated.width, generated.format.extension()))
+            .map(image_file)
+            .collect::<Result<Vec<_>>>()?;
+        let social_card = image
+            .generated_images
+            .iter()
+            .find(|generated| generated.variant == Variant::SocialCard)
+            .map(image_file)
+            .transpose()?;
+        let thumbnail = image
+            .generated_images
+            .iter()
+            .find(|generated| matches!(generated.variant, Variant::Thumbnail(_)))
+            .map(image_file)
+            .transpose()?;
 
-        let fallback = get_fallback_image(
-            src_image.s3_path.as_ref().unwrap().clone(),
-            srcset,
-            sizes,
-            &image.input_path,
-        );
+        let object_position = image.sidecar.crop_hints().object_position();
 
         data.push(HugoData {
             name: image.get_hugo_data_key(options),
             fallback,
-            sources: vec![],
-            hqimage: Some(
-                image
-                    .full_size_reencoded_image
-                    .s3_path
-                    .expect("High quality image missing S3 bucket path when generating data."),
-            ),
-            original_image: Some(
-                image
-                    .original_image
-                    .s3_path
-                    .expect("High quality image missing S3 bucket path when generating data."),
-            ),
-            exif: Some(image.exif),
+            sources,
+            placeholder_kind: Some(placeholder_kind),
+            width: Some(width),
+            height: Some(height),
+            aspect_ratio: Some(aspect_ratio(width, height)),
+            variants,
+            social_card,
+            thumbnail,
+            alt: image.sidecar.alt,
+            caption: image.sidecar.caption,
+            title: image.sidecar.title,
+            credit: image.sidecar.credit,
+            license: image.sidecar.license,
+            tags: image.sidecar.tags,
+            object_position,
+            focus: image.sidecar.focus,
+            crop: image.sidecar.crop,
+            hqimage: Some(image.full_size_reencoded_image.s3_path.with_context(|| {
+                format!(
+                    "{} wasn't uploaded",
+                    image.full_size_reencoded_image.path.to_string_lossy()
+                )
+            })?),
+            // Originals whose metadata couldn't be removed aren't uploaded
+            original_image: image.original_image.s3_path,
+            exif: Some(image.exif.only(&config.exif_fields)).filter(|exif| !exif.is_empty()),
+            metadata: Some(image.metadata).filter(|metadata| !metadata.is_empty()),
         });
     }
-    data
+    Ok(data)
+}
+
+/// `width / height`, rounded to 4 decimal places
+fn aspect_ratio(width: usize, height: usize) -> f64 {
+    (width as f64 / height.max(1) as f64 * 10_000.0).round() / 10_000.0
+}
+
+/// A `srcset` attribute listing each image's uploaded path and width. Images which weren't uploaded are left out.
+fn get_srcset<'a>(images: impl Iterator<Item = &'a GeneratedImage>) -> String {
+    images
+        .filter_map(|i| Some(format!("{} {}w", i.s3_path.as_ref()?, i.width)))
+        .intersperse(",".to_owned())
+        .collect()
 }
 
 /// Checks if the name key is already used in the hugo data template
@@ -211,7 +462,7 @@ pub fn is_hugo_data_template_name_collision(
     let name = name.to_owned();
     let output_location = output_location
         .to_owned()
-        .unwrap_or_else(|| PathBuf::from("./data/images.json"));
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_FILE));
     if output_location.exists() {
         let existing_data: Vec<HugoData> =
             serde_json::from_str(&read_to_string(&output_location)?)?;
@@ -229,7 +480,7 @@ pub fn write_data_to_hugo_data_template(
     should_overwrite: bool,
 ) -> Result<(), AppError> {
     let mut existing_data: Vec<HugoData>;
-    let output_location = output_location.unwrap_or_else(|| PathBuf::from("./data/images.json"));
+    let output_location = output_location.unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_FILE));
 
     if output_location.exists() {
         existing_data = serde_json::from_str(&read_to_string(&output_location)?)?;
@@ -261,99 +512,95 @@ pub fn write_data_to_hugo_data_template(
     Ok(())
 }
 
-/// Given the path to a directory of images, or a single image, generate resized images
+/// Given the path to a directory of images, or a single image, generate resized images.
+///
+/// Images, and each width of an image, are processed in parallel on `options.jobs()` threads.
+/// Memory for each image is reserved from the budget before it's handed to a thread.
+/// Images in a directory are returned in file name order.
 pub fn generate_images(
     image_path: &PathBuf,
     output_directory: &Path,
     options: &Options,
+    config: &Config,
 ) -> Result<Vec<ImageInfo>> {
-    let mut m = Metrics::default();
+    let m = Metrics::default();
+    let progress = MultiProgress::new();
+    let budget = MemoryBudget::new(options.memory_limit * 1024 * 1024);
+    let cache = options.cache();
+    // Every image processed is in the same folder, so its manifest is read once
+    let folder = if image_path.is_dir() {
+        image_path.as_path()
+    } else {
+        image_path.parent().unwrap_or(Path::new(""))
+    };
+    let manifest = Manifest::load(folder)?;
+    let resources = ProcessingResources {
+        progress: &progress,
+        cache: cache.as_ref(),
+        config,
+        manifest: &manifest,
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs())
+        .build()?;
+
+    // Each width being resized at once holds its own copy of the image
+    let copies = options.sizes.0.len().min(options.jobs());
+    let estimate = |path: &PathBuf| estimate_decoded_size(path, copies);
+
     if !image_path.is_dir() {
         debug!("Processing {}", image_path.to_string_lossy());
-        let image_info = vec![process_image(
-            image_path,
-            output_directory,
-            options,
-            &mut m,
-        )?];
+        let image_info = budget
+            .map(&pool, std::slice::from_ref(image_path), estimate, |path| {
+                process_image(path, output_directory, options, &m, &resources)
+            })
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
         debug!("Metrics {:?}", m);
         return Ok(image_info);
     }
 
+    if options.sidecar() != Sidecar::default() {
+        bail!("--alt, --caption, --title, --credit, --focus and --crop only apply to a single image. Set them in the sidecar file of each image in a folder instead");
+    }
+
     // An error here (permission denied) will bail the walk. Dont bail the walk. Instead continue back to the parent
     let directory = std::fs::read_dir(image_path)?;
-    let directory = directory.collect::<Vec<_>>();
-    let item_count = directory.len();
-
-    let progress_bar = ProgressBar::new(item_count as u64);
-    let mut image_infos = Vec::with_capacity(item_count);
-    for entry in directory {
-        m.traversed += 1;
-
-        // TODO: Special case this for lib vs command line
-        // We ignore errors accessing files or performing other IO as one file we can't access isn't an indication the input parameters are incorrect necessarily.
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(e) => {
-                warn!("WARNING: Processing error {:?}", e);
-                continue;
-            }
-        };
-
-        let path = entry.path();
 
+    // TODO: Special case this for lib vs command line
+    // We ignore errors accessing files or performing other IO as one file we can't access isn't an indication the input parameters are incorrect necessarily.
+    let mut paths = directory
+        .filter_map(|entry| {
+            m.traversed.fetch_add(1, Ordering::Relaxed);
+            match entry {
+                Ok(entry) => Some(entry.path()),
+                Err(e) => {
+                    warn!("WARNING: Processing error {:?}", e);
+                    None
+                }
+            }
+        })
         // We don't recurse
-        if path.is_dir() {
-            continue;
-        } else {
-            match digest_path(&path, output_directory, options, &mut m)? {
-                Some(image_info) => image_infos.push(image_info),
-                None => m.skipped += 1,
+        .filter(|path| !path.is_dir())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let progress_bar = progress.add(ProgressBar::new(paths.len() as u64));
+    let image_infos = budget
+        .map(&pool, &paths, estimate, |path| {
+            let image_info = digest_path(path, output_directory, options, &m, &resources)?;
+            if image_info.is_none() {
+                m.skipped.fetch_add(1, Ordering::Relaxed);
             }
-        }
-        progress_bar.inc(1);
-    }
+            progress_bar.inc(1);
+            Ok(image_info)
+        })
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
     progress_bar.finish_and_clear();
 
     debug!("Metrics {:?}", m);
-    Ok(image_infos)
-}
-
-// TODO: Make this _way_ less opinionated
-// This is only public so main can use it. See: https://users.rust-lang.org/t/lib-rs-declare-module-publicly-visible-only-to-main-rs/97368
-/// The prefix that should be prepended to all filenames to match the AWS path
-/// Example: images/12/25/christmas/
-#[doc(hidden)]
-pub fn get_uploaded_prefix(s3_path_suffix: &Option<String>, now: DateTime<Local>) -> String {
-    let year = now.year();
-    let month = constants::MONTH_NAMES[now.month0() as usize];
-
-    match s3_path_suffix {
-        Some(directory) => format!(
-            "images/{0}/{1}/{2}/",
-            year,
-            month,
-            directory.trim_end_matches('/').trim_start_matches('/')
-        ),
-        None => format!("images/{0}/{1}/", year, month),
-    }
-}
-
-/// Create a [SQIP](https://www.afasterweb.com/2018/04/25/smooth-out-low-quality-placeholders-with-sqip/) to use as a placeholder for **only** the `img` tag
-fn get_fallback_image(
-    src: String,
-    srcset: String,
-    sizes: String,
-    image_path: &Path,
-) -> FallbackImage {
-    let svg_placeholder = if cfg!(test) {
-        String::new()
-    } else {
-        debug!("Making SVG placeholder");
-        make_sqip(&image_path.to_string_lossy()).expect("Error getting SQIP")
-    };
-
-    FallbackImage::new(src, sizes.to_owned(), srcset, svg_placeholder)
+    Ok(image_infos.into_iter().flatten().collect())
 }
 
 // #[cfg(test)]
@@ -491,3 +738,207 @@ fn get_fallback_image(
 //         )
 //     }
 // }
+
+#[cfg(test)]
+mod upload_tests {
+    use super::*;
+    use crate::data::exif::Exif;
+    use crate::data::metadata::Metadata;
+    use crate::image::image::Resize;
+    use crate::image::original_image::OriginalImage;
+    use crate::image::variant::Variant;
+    use crate::upload::memory::MemoryStorage;
+    use chrono::prelude::*;
+    use std::fs::{remove_file, write};
+    use structopt::StructOpt;
+    use tempfile::tempdir;
+
+    /// An image with placeholder files for every variant
+    fn image_info(directory: &Path, stem: &str, original_extension: &str) -> ImageInfo {
+        let original = directory.join(format!("{stem}.{original_extension}"));
+        write(&original, format!("{stem} original")).unwrap();
+        let generated = |variant: Variant, width: usize, format: OutputFormat| {
+            let path = directory.join(variant.file_name(stem, format.extension()));
+            write(&path, format!("{stem} {variant} {format}")).unwrap();
+            GeneratedImage::new(width, width, path, format, variant)
+        };
+        ImageInfo::new(
+            640,
+            original.clone(),
+            OutputFormat::Jpeg,
+            vec![Resize::new(320, 320), Resize::new(640, 640)],
+            vec![
+                generated(Variant::Resized(320), 320, OutputFormat::Jpeg),
+                generated(Variant::Resized(320), 320, OutputFormat::Avif),
+                generated(Variant::Resized(640), 640, OutputFormat::Jpeg),
+            ],
+            generated(Variant::FullSize, 1000, OutputFormat::Jpeg),
+            OriginalImage::new(original),
+            Exif::default(),
+            Metadata::default(),
+            None,
+            Sidecar::default(),
+            String::new(),
+        )
+    }
+
+    /// An AVIF with EXIF in it, which can't be removed
+    const AVIF_WITH_EXIF: &[u8] =
+        b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miafII*\0\x08\0\0\0\0\0\0\0\0\0";
+
+    fn urls(image: &ImageInfo) -> Vec<Option<String>> {
+        image
+            .generated_images
+            .iter()
+            .map(|generated| generated.s3_path.clone())
+            .chain(once(image.full_size_reencoded_image.s3_path.clone()))
+            .chain(once(image.original_image.s3_path.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_data_lists_every_variant_and_its_size() {
+        let directory = tempdir().unwrap();
+        let config = Config::default();
+        let context = KeyContext {
+            config: &config,
+            name: "Holiday",
+            directory: None,
+            now: Local.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap(),
+        };
+        let image = image_info(directory.path(), "sunset", "jpg");
+        let images = fake_upload_images(&[image], &context).unwrap();
+        let options = Options::from_iter(["rith", "--name", "Holiday", "sunset.jpg"]);
+
+        let data = generate_data(images.clone(), &options, &config).unwrap();
+        let variants = &data[0].variants;
+        assert_eq!(
+            variants
+                .iter()
+                .map(|variant| (variant.width, variant.mime_type.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (320, "image/avif"),
+                (320, "image/jpeg"),
+                (640, "image/jpeg")
+            ]
+        );
+        let avif = &images[0].generated_images[1];
+        assert_eq!(
+            variants[0],
+            ImageFile::new(
+                avif.s3_path.clone().unwrap(),
+                320,
+                320,
+                "image/avif".to_owned(),
+                "sunset 320w avif".len() as u64
+            )
+        );
+
+        // A missing file is an error rather than a size of 0
+        remove_file(&avif.path).unwrap();
+        assert!(generate_data(images, &options, &config).is_err());
+    }
+
+    #[test]
+    fn test_fake_upload_matches_upload() {
+        let directory = tempdir().unwrap();
+        let images = vec![
+            image_info(directory.path(), "IMG 0001", "PNG"),
+            image_info(directory.path(), "sunset", "jpg"),
+        ];
+        for hashed_keys in [false, true] {
+            let mut config = Config::default();
+            config.hashed_keys = hashed_keys;
+            let context = KeyContext {
+                config: &config,
+                name: "Holiday",
+                directory: Some("trips"),
+                now: Local.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap(),
+            };
+            let storage = MemoryStorage::new(config.web_prefix.clone());
+
+            let (uploaded, _) = upload_images(&images, &storage, &context).unwrap();
+            let faked = fake_upload_images(&images, &context).unwrap();
+
+            assert_eq!(
+                uploaded.iter().map(urls).collect::<Vec<_>>(),
+                faked.iter().map(urls).collect::<Vec<_>>()
+            );
+            if !hashed_keys {
+                let original = storage
+                    .head("images/2024/Mar/trips/IMG-0001-copy-of-original.png")
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(original.content_type.as_deref(), Some("image/png"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_originals_with_metadata_which_cant_be_removed_are_not_uploaded() {
+        let directory = tempdir().unwrap();
+        let image = image_info(directory.path(), "photo", "avif");
+        write(&image.original_image.path, AVIF_WITH_EXIF).unwrap();
+        let config = Config::default();
+        let context = KeyContext {
+            config: &config,
+            name: "Holiday",
+            directory: None,
+            now: Local.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap(),
+        };
+        let storage = MemoryStorage::new(config.web_prefix.clone());
+
+        let (uploaded, _) = upload_images(&[image.clone()], &storage, &context).unwrap();
+        let faked = fake_upload_images(&[image], &context).unwrap();
+
+        assert_eq!(uploaded[0].original_image.s3_path, None);
+        assert!(uploaded[0].full_size_reencoded_image.s3_path.is_some());
+        assert_eq!(urls(&uploaded[0]), urls(&faked[0]));
+        assert_eq!(storage.list("").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_files_which_were_not_uploaded_are_left_out_of_the_data() {
+        let directory = tempdir().unwrap();
+        let image = image_info(directory.path(), "photo", "jpg");
+        write(&image.generated_images[1].path, AVIF_WITH_EXIF).unwrap();
+        let config = Config::default();
+        let context = KeyContext {
+            config: &config,
+            name: "Holiday",
+            directory: None,
+            now: Local.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap(),
+        };
+        let storage = MemoryStorage::new(config.web_prefix.clone());
+        let options = Options::from_iter([
+            "rith",
+            "--name",
+            "Holiday",
+            "--formats",
+            "avif",
+            "photo.jpg",
+        ]);
+
+        let (uploaded, _) = upload_images(&[image], &storage, &context).unwrap();
+        assert_eq!(uploaded[0].generated_images[1].s3_path, None);
+
+        let data = generate_data(uploaded.clone(), &options, &config).unwrap();
+        assert!(data[0].sources.is_empty());
+        assert_eq!(data[0].fallback.srcset.matches("w,").count(), 1);
+        assert!(data[0]
+            .variants
+            .iter()
+            .all(|variant| variant.mime_type == "image/jpeg"));
+
+        // Without the fallback or full size file there's nothing to show
+        let mut missing_full_size = uploaded.clone();
+        missing_full_size[0].full_size_reencoded_image.s3_path = None;
+        assert!(generate_data(missing_full_size, &options, &config).is_err());
+        let mut missing_fallback = uploaded;
+        for generated in &mut missing_fallback[0].generated_images {
+            generated.s3_path = None;
+        }
+        assert!(generate_data(missing_fallback, &options, &config).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::data::hugo::HugoData;
+use crate::error::AppError;
+
+/// Something missing from an entry in the data file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintProblem {
+    /// The image has no alt text, so people who can't see it aren't told what it shows
+    MissingAlt,
+}
+
+/// A problem with a single entry in the data file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintIssue {
+    /// The name of the entry. e.g. `Holiday-heron.jpg`
+    pub name: String,
+    pub problem: LintProblem,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problem {
+            LintProblem::MissingAlt => write!(f, "{}: no alt text", self.name),
+        }
+    }
+}
+
+/// Checks every entry in the data file at `path`, returning the problems found
+pub fn lint_data_file(path: &Path) -> Result<Vec<LintIssue>, AppError> {
+    let data: Vec<HugoData> = serde_json::from_str(&read_to_string(path)?)?;
+    Ok(lint(&data))
+}
+
+fn lint(data: &[HugoData]) -> Vec<LintIssue> {
+    data.iter()
+        .filter(|image| {
+            image
+                .alt
+                .as_deref()
+                .map_or(true, |alt| alt.trim().is_empty())
+        })
+        .map(|image| LintIssue {
+            name: image.name.clone(),
+            problem: LintProblem::MissingAlt,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint_flags_missing_alt() {
+        let entry = |name: &str, alt: Option<&str>| {
+            let alt = alt
+                .map(|alt| format!(r#""alt": "{alt}","#))
+                .unwrap_or_default();
+            format!(
+                r#"{{"name": "{name}", {alt} "sources": [], "fallback": {{"src": "", "sizes": "", "srcset": "", "placeholder": ""}}}}"#
+            )
+        };
+        let data: Vec<HugoData> = serde_json::from_str(&format!(
+            "[{}, {}, {}]",
+            entry("described", Some("A heron")),
+            entry("blank", Some(" ")),
+            entry("missing", None)
+        ))
+        .unwrap();
+        let names = lint(&data)
+            .into_iter()
+            .map(|issue| issue.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["blank", "missing"]);
+    }
+}
@@ -2,18 +2,19 @@
 
 use anyhow::Result;
 use log::{debug, error, info};
-use responsive_image_for_hugo::image::generated_image::GeneratedImage;
+use responsive_image_for_hugo::config::Config;
 use responsive_image_for_hugo::image::image_info::ImageInfo;
-use responsive_image_for_hugo::upload::constants::WEB_PREFIX;
-use responsive_image_for_hugo::upload::uploadable::Uploadable;
+use responsive_image_for_hugo::lint::lint_data_file;
+use responsive_image_for_hugo::privacy::audit;
+use responsive_image_for_hugo::upload::object::KeyContext;
+use responsive_image_for_hugo::upload::storage_from_config;
 use std::time::Duration;
 
 use env_logger::Env;
 
 use chrono::prelude::*;
 use responsive_image_for_hugo::error::AppError;
-use responsive_image_for_hugo::options::Options;
-use structopt::StructOpt;
+use responsive_image_for_hugo::options::{Command, ConfigCommand, Invocation};
 use tempfile::Builder;
 
 use indicatif::ProgressBar;
@@ -22,7 +23,7 @@ use indicatif::ProgressBar;
 ///
 /// This program:
 /// 1. Takes an image (or directory of images) as input
-/// 2. Converts each input image to `.webp` (whilst preserving its orientation).
+/// 2. Converts each input image to JPEG and any other requested formats (AVIF, WebP, JPEG XL), turned upright according to its EXIF orientation.
 /// 3. Creates resized versions of each input image suitable for different screen sizes.
 /// 4. Uploads all image versions to S3.
 /// 5. Generates a [srcset](https://css-tricks.com/a-guide-to-the-responsive-images-syntax-in-html/#using-srcset) and [sizes](https://css-tricks.com/a-guide-to-the-responsive-images-syntax-in-html/#aa-using-srcset-w-sizes) attribute for each input image
@@ -34,10 +35,15 @@ fn main() -> Result<(), AppError> {
     env_logger::Builder::from_env(Env::new().filter_or("responsive_image_for_hugo_LOG", "info"))
         .init();
 
+    let options = match Invocation::from_args() {
+        Invocation::Process(options) => *options,
+        Invocation::Command(command) => return run_command(command),
+    };
+
     // Generate a single timestamp to use for the whole program
     let now = Local::now();
 
-    let options = Options::from_args();
+    let config = Config::load(&options.config)?;
     let temp_dir = Builder::new().prefix("rith").tempdir()?;
     let temp_dir_path = &temp_dir.path().to_path_buf();
 
@@ -57,35 +63,31 @@ fn main() -> Result<(), AppError> {
         &options.image_location,
         temp_dir_path,
         &options,
+        &config,
     )?;
 
     // Sorted
     images.sort_by_key(|i| i.get_original_input_path_as_str());
 
-    let mut images_with_s3_paths: Vec<ImageInfo> =
-        Vec::with_capacity(images.iter().map(|i| i.generated_images.len() + 2).sum());
-
-    if options.skip_upload {
-        for image in &images {
-            let image_info = fake_upload_images(image, &options.s3_directory, now);
-            images_with_s3_paths.push(image_info);
-        }
+    let key_context = KeyContext::new(&config, &options, now);
+    let images_with_s3_paths: Vec<ImageInfo> = if options.skip_upload {
+        responsive_image_for_hugo::fake_upload_images(&images, &key_context)?
     } else {
         info!("Uploading images");
-        for image in &images {
-            let image_info = responsive_image_for_hugo::upload_images(
-                image.clone(),
-                &options.s3_directory,
-                now,
-            )?;
-            images_with_s3_paths.push(image_info);
+        let storage = storage_from_config(&config)?;
+        let (uploaded, report) =
+            responsive_image_for_hugo::upload_images(&images, storage.as_ref(), &key_context)?;
+        for (key, outcome) in report.outcomes() {
+            debug!("{key}: {outcome:?}");
         }
-    }
+        info!("Upload complete: {report}");
+        uploaded
+    };
 
     let spinner = ProgressBar::new_spinner();
     spinner.enable_steady_tick(Duration::from_millis(100));
     info!("Generating data file");
-    let data = responsive_image_for_hugo::generate_data(images_with_s3_paths, &options);
+    let data = responsive_image_for_hugo::generate_data(images_with_s3_paths, &options, &config)?;
     debug!("Writing data");
     responsive_image_for_hugo::write_data_to_hugo_data_template(
         data,
@@ -99,11 +101,21 @@ fn main() -> Result<(), AppError> {
         for image in images {
             let name = image.get_hugo_data_key(&options);
             println!("-  name: {name}");
+            if let Some(caption) = &image.sidecar.caption {
+                println!("   caption: \"{}\"", caption.replace('"', "\\\""));
+            }
         }
     } else {
+        let image = images.first().unwrap();
         println!(
-            "Shortcode: \n \n{{{{< picture name=\"{0}\" caption=\"\" >}}}}\n",
-            images.first().unwrap().get_hugo_data_key(&options)
+            "Shortcode: \n \n{{{{< picture name=\"{0}\" caption=\"{1}\" >}}}}\n",
+            image.get_hugo_data_key(&options),
+            image
+                .sidecar
+                .caption
+                .as_deref()
+                .unwrap_or_default()
+                .replace('"', "\\\"")
         );
     }
 
@@ -111,51 +123,33 @@ fn main() -> Result<(), AppError> {
     Ok(())
 }
 
-/// Calculates the paths the images would be uploaded to in S3 and returns the modified
-fn fake_upload_images(
-    image: &ImageInfo,
-    s3_sub_directory: &Option<String>,
-    now: DateTime<Local>,
-) -> ImageInfo {
-    let prefix = responsive_image_for_hugo::get_uploaded_prefix(s3_sub_directory, now);
-    let s3_images = image
-        .generated_images
-        .iter()
-        .map(|image| {
-            let s3_path =
-                responsive_image_for_hugo::get_file_s3_bucket_path(&image.path, &prefix, None);
-            image.with_s3_path(Some([WEB_PREFIX, &s3_path].join("")))
-        })
-        .collect::<Vec<GeneratedImage>>();
-
-    let full_size_reencoded_image = image.full_size_reencoded_image.with_s3_path(Some(
-        [
-            WEB_PREFIX,
-            &responsive_image_for_hugo::get_file_s3_bucket_path(
-                &image.full_size_reencoded_image.path,
-                &prefix,
-                None,
-            ),
-        ]
-        .join(""),
-    ));
-
-    let original_image_copy = image.original_image.with_s3_path(Some(
-        [
-            WEB_PREFIX,
-            &responsive_image_for_hugo::get_file_s3_bucket_path(
-                &image.full_size_reencoded_image.path,
-                &prefix,
-                // TODO: If change here - change in actual lib.
-                // TODO: Extract this!
-                Some("copy-of-original".to_owned()),
-            ),
-        ]
-        .join(""),
-    ));
-
-    image
-        .with_generated_images(s3_images)
-        .with_full_size_reencoded_image(full_size_reencoded_image)
-        .with_original_image(original_image_copy)
+/// Runs a subcommand that doesn't process images
+fn run_command(command: Command) -> Result<(), AppError> {
+    match command {
+        Command::Config(ConfigCommand::Show { config }) => {
+            println!("{}", Config::load(&config)?.show()?);
+        }
+        Command::Lint { output } => {
+            let issues = lint_data_file(&output)?;
+            for issue in &issues {
+                println!("{issue}");
+            }
+            if !issues.is_empty() {
+                return Err(AppError::LintFailed(issues.len()));
+            }
+        }
+        Command::Audit { prefix, config } => {
+            let config = Config::load(&config)?;
+            let storage = storage_from_config(&config)?;
+            let prefix = prefix.unwrap_or_else(|| config.key_prefix.clone());
+            let located = audit(storage.as_ref(), &prefix)?;
+            for key in &located {
+                println!("{}", storage.public_url(key));
+            }
+            if !located.is_empty() {
+                return Err(AppError::AuditFailed(located.len()));
+            }
+        }
+    }
+    Ok(())
 }
@@ -1,7 +1,11 @@
+use std::sync::atomic::AtomicUsize;
+
 #[derive(Debug, Default)]
 pub struct Metrics {
-    pub count: usize,
-    pub resized: usize,
-    pub traversed: usize,
-    pub skipped: usize,
+    pub count: AtomicUsize,
+    pub resized: AtomicUsize,
+    pub traversed: AtomicUsize,
+    pub skipped: AtomicUsize,
+    /// Variants reused from the cache rather than encoded
+    pub cached: AtomicUsize,
 }
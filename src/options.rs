@@ -1,7 +1,20 @@
+use structopt::clap::{AppSettings, ArgMatches};
 use structopt::StructOpt;
 
 use std::path::PathBuf;
 
+use crate::config::ConfigOverrides;
+use crate::constants::DEFAULT_DATA_FILE;
+use crate::image::cache::Cache;
+use crate::image::color::ColorManagement;
+use crate::image::conversion::{Background, Transparency};
+use crate::image::crop::{CropRectangle, FocalPoint};
+use crate::image::format::OutputFormat;
+use crate::image::placeholder::PlaceholderKind;
+use crate::image::sqip::primitive::ShapeMode;
+use crate::image::sqip::SqipOptions;
+use crate::sidecar::Sidecar;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Sizes(pub Vec<usize>);
 
@@ -17,10 +30,40 @@ impl std::str::FromStr for Sizes {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Formats(pub Vec<OutputFormat>);
+
+impl Formats {
+    /// Every format to encode to: the `fallback` format followed by each requested format without duplicates
+    pub fn with_fallback(&self, fallback: OutputFormat) -> Vec<OutputFormat> {
+        let mut formats = vec![fallback];
+        for format in &self.0 {
+            if !formats.contains(format) {
+                formats.push(*format);
+            }
+        }
+        formats
+    }
+}
+
+impl std::str::FromStr for Formats {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let formats = s
+            .split(',')
+            .map(str::parse::<OutputFormat>)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(formats))
+    }
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "Responsive Image to Shortcode",
-    about = "A tool to generate responsive images for a Hugo site"
+    about = "A tool to generate responsive images for a Hugo site",
+    after_help = "Run with `config show` to see the merged configuration and where each value came from.",
+    setting = AppSettings::SubcommandsNegateReqs
 )]
 pub struct Options {
     /// The path to a folder of images or a single image
@@ -43,10 +86,18 @@ pub struct Options {
     #[structopt(long = "skip-upload")]
     pub skip_upload: bool,
 
-    /// Skip image resizing
+    /// Skip image resizing. Every variant must already be in the cache.
     #[structopt(long = "skip-resize")]
     pub skip_resize: bool,
 
+    /// The directory encoded images are cached in. Defaults to `$XDG_CACHE_HOME/rith`
+    #[structopt(long = "cache-dir", parse(from_os_str))]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Don't reuse or store encoded images in the cache
+    #[structopt(long = "no-cache")]
+    pub no_cache: bool,
+
     /// Force overwrite of existing data
     #[structopt(short = "f", long = "force", alias = "clobber")]
     pub force_overwrite: bool,
@@ -56,4 +107,260 @@ pub struct Options {
         default_value = "320,480,640,768,960,1024,1366,1600,1920,1440,1800"
     )]
     pub sizes: Sizes,
+
+    /// The formats to encode each image to. Each format other than JPEG is added as a `source` in the order given. JPEG is always generated as the fallback,
+    /// unless transparent images switch to PNG.
+    #[structopt(long, default_value = "jpeg")]
+    pub formats: Formats,
+
+    /// What happens to colour profiles embedded in images: convert (to sRGB) or preserve (embedded in JPEG and WebP files)
+    #[structopt(long = "color-management", default_value = "convert")]
+    pub color_management: ColorManagement,
+
+    /// Also generate JPEG and WebP files keeping the colour profile of wide gamut images, such as Display P3 photos.
+    /// They're added as sources for wide gamut displays.
+    #[structopt(long = "wide-gamut")]
+    pub wide_gamut: bool,
+
+    /// What happens to transparent images encoded to JPEG, which has no alpha channel: flatten (onto --background)
+    /// or switch-format (use PNG rather than JPEG as their fallback)
+    #[structopt(long, default_value = "flatten")]
+    pub transparency: Transparency,
+
+    /// The colour transparent images are flattened onto, e.g. `#ffffff`
+    #[structopt(long, default_value = "#ffffff")]
+    pub background: Background,
+
+    /// The number of images (and widths of each image) to process at once. Defaults to the number of CPUs.
+    #[structopt(short = "j", long = "jobs")]
+    pub jobs: Option<usize>,
+
+    /// The approximate memory, in MiB, images being processed at once may use. Huge images wait for others to finish.
+    #[structopt(long = "memory-limit", default_value = "4096")]
+    pub memory_limit: u64,
+
+    /// The placeholder shown whilst an image loads: sqip, blurhash, thumbhash, lqip, color or none
+    #[structopt(long, default_value = "sqip")]
+    pub placeholder: PlaceholderKind,
+
+    /// The number of shapes SQIP placeholders are drawn with
+    #[structopt(long = "sqip-primitives", default_value = "10")]
+    pub sqip_primitives: usize,
+
+    /// The shapes SQIP placeholders are drawn with: combo, triangle, rectangle, ellipse, circle, rotated-rectangle or rotated-ellipse
+    #[structopt(long = "sqip-mode", default_value = "combo")]
+    pub sqip_mode: ShapeMode,
+
+    /// The opacity (1-255) of each shape in SQIP placeholders
+    #[structopt(long = "sqip-alpha", default_value = "128")]
+    pub sqip_alpha: u8,
+
+    /// Write an image showing what was found interesting, and the region chosen, for every smart crop to this directory
+    #[structopt(long = "smart-crop-debug", parse(from_os_str))]
+    pub smart_crop_debug: Option<PathBuf>,
+
+    /// Generate a 1200x630 smart crop of each image for social media link previews. e.g. `og:image`
+    #[structopt(long = "social-card")]
+    pub social_card: bool,
+
+    /// Generate a square smart crop of each image this many pixels wide. e.g. for galleries and listings
+    #[structopt(long)]
+    pub thumbnail: Option<usize>,
+
+    /// A description of a single image for people who can't see it.
+    /// For folders, set `alt` in each image's sidecar file instead.
+    #[structopt(long)]
+    pub alt: Option<String>,
+
+    /// The caption of a single image
+    #[structopt(long)]
+    pub caption: Option<String>,
+
+    /// The title of a single image
+    #[structopt(long)]
+    pub title: Option<String>,
+
+    /// Who made a single image
+    #[structopt(long)]
+    pub credit: Option<String>,
+
+    /// The point crops of a single image are centred on, as fractions of its width and height. e.g. `0.5,0.3`.
+    /// For folders, set `focus` in each image's sidecar file instead.
+    #[structopt(long)]
+    pub focus: Option<FocalPoint>,
+
+    /// The part of a single image crops are taken from, as x,y,width,height fractions of the image. e.g. `0.1,0,0.8,1`.
+    /// For folders, set `crop` in each image's sidecar file instead.
+    #[structopt(long)]
+    pub crop: Option<CropRectangle>,
+
+    /// Don't read EXIF from images or write it to the data file
+    #[structopt(long = "no-exif")]
+    pub no_exif: bool,
+
+    #[structopt(flatten)]
+    pub config: ConfigOverrides,
+
+    /// Always `None`. A subcommand is returned by [`Invocation::from_args`] instead of these options.
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Options {
+    /// The cache of encoded images, unless caching is disabled
+    pub fn cache(&self) -> Option<Cache> {
+        if self.no_cache {
+            return None;
+        }
+        self.cache_dir
+            .clone()
+            .or_else(Cache::default_directory)
+            .map(Cache::new)
+    }
+
+    /// The number of threads to process images with
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(num_cpus::get).max(1)
+    }
+
+    /// The descriptions and crop hints given on the command line for a single image
+    pub fn sidecar(&self) -> Sidecar {
+        Sidecar {
+            alt: self.alt.clone(),
+            caption: self.caption.clone(),
+            title: self.title.clone(),
+            credit: self.credit.clone(),
+            focus: self.focus,
+            crop: self.crop,
+            ..Sidecar::default()
+        }
+    }
+
+    /// How SQIP placeholders are generated
+    pub fn sqip(&self) -> SqipOptions {
+        SqipOptions {
+            primitives: self.sqip_primitives,
+            mode: self.sqip_mode,
+            alpha: self.sqip_alpha,
+            workers: self.jobs(),
+        }
+    }
+}
+
+/// What the program was asked to do
+#[derive(Debug)]
+pub enum Invocation {
+    /// Process the image or folder of images given
+    Process(Box<Options>),
+    /// Run a subcommand
+    Command(Command),
+}
+
+impl Invocation {
+    /// Parses the command line arguments, exiting with a usage message if they're invalid.
+    /// The image to process is only required when no subcommand is given.
+    pub fn from_args() -> Self {
+        Self::from_clap(&Options::clap().get_matches())
+    }
+
+    fn from_clap(matches: &ArgMatches) -> Self {
+        if matches.subcommand_name().is_some() {
+            Invocation::Command(Command::from_clap(matches))
+        } else {
+            Invocation::Process(Box::new(Options::from_clap(matches)))
+        }
+    }
+}
+
+/// Subcommands which don't process images
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Inspect the configuration
+    Config(ConfigCommand),
+    /// Check every image in the data file has alt text. Exits with an error if any don't.
+    Lint {
+        /// The data file to check
+        #[structopt(short = "o", long = "output", parse(from_os_str), default_value = DEFAULT_DATA_FILE)]
+        output: PathBuf,
+    },
+    /// List the public URL of every uploaded file which still records where it was taken.
+    /// Exits with an error if there are any.
+    Audit {
+        /// Only check keys starting with this prefix. Defaults to the configured key prefix.
+        #[structopt(long)]
+        prefix: Option<String>,
+        #[structopt(flatten)]
+        config: ConfigOverrides,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ConfigCommand {
+    /// Show the merged configuration and where each value came from
+    Show {
+        #[structopt(flatten)]
+        config: ConfigOverrides,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(args: &[&str]) -> Invocation {
+        let args = std::iter::once("rith").chain(args.iter().copied());
+        Invocation::from_clap(&Options::clap().get_matches_from(args))
+    }
+
+    #[test]
+    fn test_formats() {
+        let formats = "avif, WebP,jpeg-xl,jpg".parse::<Formats>().unwrap();
+        assert_eq!(
+            formats.0,
+            [
+                OutputFormat::Avif,
+                OutputFormat::Webp,
+                OutputFormat::Jxl,
+                OutputFormat::Jpeg
+            ]
+        );
+        assert!("avif,gif".parse::<Formats>().is_err());
+        assert!("".parse::<Formats>().is_err());
+
+        // The fallback comes first and each format is only encoded once
+        let formats = Formats(vec![
+            OutputFormat::Webp,
+            OutputFormat::Jpeg,
+            OutputFormat::Webp,
+        ]);
+        assert_eq!(
+            formats.with_fallback(OutputFormat::Jpeg),
+            [OutputFormat::Jpeg, OutputFormat::Webp]
+        );
+        assert_eq!(
+            formats.with_fallback(OutputFormat::Png),
+            [OutputFormat::Png, OutputFormat::Webp, OutputFormat::Jpeg]
+        );
+    }
+
+    #[test]
+    fn test_subcommands_are_dispatched() {
+        assert!(matches!(
+            invocation(&["lint"]),
+            Invocation::Command(Command::Lint { .. })
+        ));
+        assert!(matches!(
+            invocation(&["config", "show", "--bucket", "example.com"]),
+            Invocation::Command(Command::Config(ConfigCommand::Show { config }))
+                if config.bucket.as_deref() == Some("example.com")
+        ));
+        // Subcommand names are only subcommands where the image would be given
+        match invocation(&["--name", "lint", "./audit"]) {
+            Invocation::Process(options) => {
+                assert_eq!(options.name, "lint");
+                assert_eq!(options.image_location, PathBuf::from("./audit"));
+            }
+            Invocation::Command(command) => panic!("{command:?} was invoked"),
+        }
+    }
 }
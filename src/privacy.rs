@@ -0,0 +1,624 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+use crate::data::exif::{Tiff, ORIENTATION_TAG};
+use crate::data::metadata::split_jpeg;
+use crate::image::format::push_jpeg_segment;
+use crate::upload::storage::Storage;
+
+/// The TIFF tags pointing to the GPS and EXIF directories
+const GPS_IFD_TAG: u16 = 0x8825;
+const EXIF_IFD_TAG: u16 = 0x8769;
+
+const COPYRIGHT_TAG: u16 = 0x8298;
+
+/// The EXIF tags identifying the camera or lens a photo was taken with, or its owner
+const SERIAL_TAGS: &[u16] = &[
+    0xA430, // CameraOwnerName
+    0xA431, // BodySerialNumber
+    0xA435, // LensSerialNumber
+    0xC62F, // CameraSerialNumber
+];
+
+/// The EXIF tag holding the camera maker's own metadata, which often includes the body and lens serial numbers
+const MAKER_NOTE_TAG: u16 = 0x927C;
+
+/// How much of the start of each object is downloaded by [`audit`]. Metadata is written before the pixels.
+const AUDIT_HEADER_SIZE: u64 = 256 * 1024;
+
+/// JPEG segment markers
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const APP2: u8 = 0xE2;
+const APP14: u8 = 0xEE;
+const COMMENT: u8 = 0xFE;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// The VP8X flags saying a WebP has EXIF or XMP
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+lazy_static::lazy_static! {
+    /// XMP properties holding where a photo was taken or identifying the camera, as attributes or simple elements
+    static ref XMP_PRIVATE_PROPERTIES: regex::Regex = {
+        let names = r"(?:exif:GPS\w*|aux:(?:Lens)?SerialNumber|exifEX:(?:Body|Lens)SerialNumber|exifEX:CameraOwnerName)\b";
+        regex::Regex::new(&format!(
+            r#"(?s)\s{names}\s*=\s*(?:"[^"]*"|'[^']*')|<{names}[^>]*?(?:/>|>[^<]*</[^>]+>)"#
+        ))
+        .unwrap()
+    };
+}
+
+/// What metadata is removed from files before they're published
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrivacyPolicy {
+    /// Publish files with all of their metadata
+    KeepAll,
+    /// Remove where photos were taken and the serial numbers of cameras and lenses
+    StripGps,
+    /// Remove every description, EXIF and XMP, except the copyright notice and the orientation
+    StripAllButCopyright,
+}
+
+impl FromStr for PrivacyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keep-all" => Ok(PrivacyPolicy::KeepAll),
+            "strip-gps" => Ok(PrivacyPolicy::StripGps),
+            "strip-all-but-copyright" => Ok(PrivacyPolicy::StripAllButCopyright),
+            _ => Err(format!(
+                "{s} is not a privacy policy. Expected keep-all, strip-gps or strip-all-but-copyright"
+            )),
+        }
+    }
+}
+
+/// The contents of a file with the metadata `policy` doesn't allow removed. Pixels are never re-encoded.
+///
+/// JPEG, PNG and WebP files have their metadata rewritten. Other formats, e.g. AVIF, JPEG XL and PSD, are published
+/// unchanged unless they contain EXIF or XMP. That can't be removed from them, so `None` is returned and the file
+/// mustn't be published.
+pub fn scrub(contents: &[u8], policy: PrivacyPolicy) -> Option<Cow<'_, [u8]>> {
+    if policy == PrivacyPolicy::KeepAll {
+        return Some(Cow::Borrowed(contents));
+    }
+    let scrubbed = scrub_jpeg(contents, policy)
+        .or_else(|| scrub_png(contents, policy))
+        .or_else(|| scrub_webp(contents, policy));
+    match scrubbed {
+        Some(scrubbed) => Some(Cow::Owned(scrubbed)),
+        None if contains_metadata(contents) => None,
+        None => Some(Cow::Borrowed(contents)),
+    }
+}
+
+/// Whether a file records where it was taken, in EXIF or XMP
+pub fn contains_gps(contents: &[u8]) -> bool {
+    let xmp_gps = [b"exif:GPSLatitude".as_slice(), b"exif:GPSLongitude"]
+        .iter()
+        .any(|property| find(contents, property).is_some());
+    xmp_gps || tiff_offsets(contents).any(|start| tiff_has_gps(&contents[start..]))
+}
+
+/// Lists the keys of every object below `prefix` which records where it was taken.
+/// Only the start of each object, where its metadata is, is downloaded.
+pub fn audit(storage: &dyn Storage, prefix: &str) -> Result<Vec<String>> {
+    let keys = storage
+        .list(prefix)
+        .with_context(|| format!("Failed to list objects below {prefix}"))?;
+    let mut located = keys
+        .into_par_iter()
+        .map(|key| {
+            let contents = storage
+                .get_range(&key, AUDIT_HEADER_SIZE)
+                .with_context(|| format!("Failed to download {key}"))?;
+            Ok(contents
+                .filter(|contents| contains_gps(contents))
+                .map(|_| key))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
+    located.sort();
+    Ok(located)
+}
+
+fn scrub_jpeg(contents: &[u8], policy: PrivacyPolicy) -> Option<Vec<u8>> {
+    let (segments, Some(image_data)) = split_jpeg(contents) else {
+        return None;
+    };
+    let mut scrubbed = vec![0xFF, 0xD8];
+    match policy {
+        PrivacyPolicy::KeepAll => return Some(contents.to_vec()),
+        PrivacyPolicy::StripGps => {
+            for (marker, payload) in segments {
+                let payload = match marker {
+                    APP1 if payload.starts_with(EXIF_HEADER) => {
+                        let mut payload = payload.to_vec();
+                        blank_location_and_serials(&mut payload[EXIF_HEADER.len()..]);
+                        Cow::Owned(payload)
+                    }
+                    APP1 if payload.starts_with(XMP_HEADER) => {
+                        let xmp = strip_xmp(&payload[XMP_HEADER.len()..])?;
+                        Cow::Owned([XMP_HEADER, &xmp].concat())
+                    }
+                    // The rest of XMP too long for a single segment
+                    APP1 => continue,
+                    _ => Cow::Borrowed(payload),
+                };
+                push_jpeg_segment(&mut scrubbed, marker, &payload);
+            }
+        }
+        PrivacyPolicy::StripAllButCopyright => {
+            let exif = segments
+                .iter()
+                .find(|(marker, payload)| *marker == APP1 && payload.starts_with(EXIF_HEADER))
+                .and_then(|(_, payload)| minimal_exif(&payload[EXIF_HEADER.len()..]));
+            let mut exif = exif.map(|exif| [EXIF_HEADER, &exif].concat());
+            for (marker, payload) in segments {
+                // The JFIF header, colour profile and Adobe colour transform are needed to display the image
+                let metadata = (APP0..=0xEF).contains(&marker) || marker == COMMENT;
+                if metadata && ![APP0, APP2, APP14].contains(&marker) {
+                    continue;
+                }
+                if marker != APP0 {
+                    if let Some(exif) = exif.take() {
+                        push_jpeg_segment(&mut scrubbed, APP1, &exif);
+                    }
+                }
+                push_jpeg_segment(&mut scrubbed, marker, payload);
+            }
+            if let Some(exif) = exif {
+                push_jpeg_segment(&mut scrubbed, APP1, &exif);
+            }
+        }
+    }
+    scrubbed.extend_from_slice(image_data);
+    Some(scrubbed)
+}
+
+fn scrub_png(contents: &[u8], policy: PrivacyPolicy) -> Option<Vec<u8>> {
+    let mut rest = contents.strip_prefix(PNG_SIGNATURE)?;
+    let mut scrubbed = PNG_SIGNATURE.to_vec();
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes(rest[..4].try_into().ok()?) as usize;
+        let kind: [u8; 4] = rest[4..8].try_into().ok()?;
+        let data = rest.get(8..8 + length)?;
+        rest = rest.get(12 + length..)?;
+        let keyword = data.split(|byte| *byte == 0).next().unwrap_or_default();
+        let data = match (&kind, policy) {
+            (_, PrivacyPolicy::KeepAll) => Some(Cow::Borrowed(data)),
+            (b"eXIf", PrivacyPolicy::StripGps) => {
+                let mut data = data.to_vec();
+                blank_location_and_serials(&mut data);
+                Some(Cow::Owned(data))
+            }
+            (b"iTXt", PrivacyPolicy::StripGps) if keyword == PNG_XMP_KEYWORD => {
+                strip_png_xmp(data).map(Cow::Owned)
+            }
+            // e.g. EXIF hex encoded by ImageMagick as `Raw profile type exif`
+            (b"tEXt" | b"zTXt" | b"iTXt", PrivacyPolicy::StripGps)
+                if keyword.starts_with(b"Raw profile type") =>
+            {
+                None
+            }
+            (b"eXIf", PrivacyPolicy::StripAllButCopyright) => minimal_exif(data).map(Cow::Owned),
+            (b"tEXt" | b"zTXt" | b"iTXt", PrivacyPolicy::StripAllButCopyright) => {
+                (keyword == b"Copyright").then_some(Cow::Borrowed(data))
+            }
+            _ => Some(Cow::Borrowed(data)),
+        };
+        if let Some(data) = data {
+            push_chunk(&mut scrubbed, &kind, &data);
+        }
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    Some(scrubbed)
+}
+
+/// An iTXt chunk of XMP without private properties, or `None` if it's compressed and can't be edited
+fn strip_png_xmp(data: &[u8]) -> Option<Vec<u8>> {
+    // keyword\0, compression flag, compression method, language\0, translated keyword\0, text
+    let keyword_end = data.iter().position(|byte| *byte == 0)?;
+    if data.get(keyword_end + 1) != Some(&0) {
+        return None;
+    }
+    let language = keyword_end + 3;
+    let language_end = language + data.get(language..)?.iter().position(|byte| *byte == 0)?;
+    let translated_end = language_end
+        + 1
+        + data
+            .get(language_end + 1..)?
+            .iter()
+            .position(|byte| *byte == 0)?;
+    let xmp = strip_xmp(&data[translated_end + 1..])?;
+    Some([&data[..=translated_end], &xmp].concat())
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend_from_slice(data);
+    png.extend(crc32(kind.iter().chain(data)).to_be_bytes());
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn scrub_webp(contents: &[u8], policy: PrivacyPolicy) -> Option<Vec<u8>> {
+    if !(contents.starts_with(b"RIFF") && contents.get(8..12) == Some(b"WEBP")) {
+        return None;
+    }
+    let mut chunks = Vec::new();
+    let mut rest = &contents[12..];
+    while rest.len() >= 8 {
+        let kind: [u8; 4] = rest[..4].try_into().ok()?;
+        let length = u32::from_le_bytes(rest[4..8].try_into().ok()?) as usize;
+        let data = rest.get(8..8 + length)?;
+        rest = rest.get(8 + length + length % 2..).unwrap_or_default();
+        let data = match (&kind, policy) {
+            (_, PrivacyPolicy::KeepAll) => Some(Cow::Borrowed(data)),
+            (b"EXIF", PrivacyPolicy::StripGps) => {
+                let mut data = data.to_vec();
+                let tiff = if data.starts_with(EXIF_HEADER) {
+                    EXIF_HEADER.len()
+                } else {
+                    0
+                };
+                blank_location_and_serials(&mut data[tiff..]);
+                Some(Cow::Owned(data))
+            }
+            (b"XMP ", PrivacyPolicy::StripGps) => strip_xmp(data).map(Cow::Owned),
+            (b"EXIF", PrivacyPolicy::StripAllButCopyright) => {
+                minimal_exif(data.strip_prefix(EXIF_HEADER).unwrap_or(data)).map(Cow::Owned)
+            }
+            (b"XMP ", PrivacyPolicy::StripAllButCopyright) => None,
+            _ => Some(Cow::Borrowed(data)),
+        };
+        if let Some(data) = data {
+            chunks.push((kind, data));
+        }
+    }
+
+    let has = |kind: &[u8; 4]| chunks.iter().any(|(chunk, _)| chunk == kind);
+    let (has_exif, has_xmp) = (has(b"EXIF"), has(b"XMP "));
+    let mut body = b"WEBP".to_vec();
+    for (kind, data) in chunks {
+        let mut data = data.into_owned();
+        if &kind == b"VP8X" {
+            if let Some(flags) = data.first_mut() {
+                if !has_exif {
+                    *flags &= !WEBP_EXIF_FLAG;
+                }
+                if !has_xmp {
+                    *flags &= !WEBP_XMP_FLAG;
+                }
+            }
+        }
+        body.extend(kind);
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(&data);
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut scrubbed = b"RIFF".to_vec();
+    scrubbed.extend((body.len() as u32).to_le_bytes());
+    scrubbed.extend(body);
+    Some(scrubbed)
+}
+
+/// Empties the GPS directory, and zeroes camera and lens serial numbers and the maker note which may hold them too,
+/// of the TIFF structure EXIF is stored as.
+/// The structure stays the same size so nothing it points to moves.
+fn blank_location_and_serials(tiff: &mut [u8]) {
+    let Some(reader) = Tiff::new(tiff) else {
+        return;
+    };
+    let Some(first_ifd) = reader.first_ifd(tiff) else {
+        return;
+    };
+    if let Some(gps_ifd) = reader.sub_ifd(tiff, first_ifd, GPS_IFD_TAG) {
+        let entries = reader.entries(tiff, gps_ifd);
+        for value in entries
+            .iter()
+            .filter_map(|entry| reader.value(tiff, *entry))
+            .collect::<Vec<_>>()
+        {
+            tiff[value].fill(0);
+        }
+        // No entries and no next directory
+        let end = (gps_ifd + 2 + entries.len() * 12 + 4).min(tiff.len());
+        if gps_ifd < end {
+            tiff[gps_ifd..end].fill(0);
+        }
+    }
+    let exif_ifd = reader.sub_ifd(tiff, first_ifd, EXIF_IFD_TAG);
+    for ifd in std::iter::once(first_ifd).chain(exif_ifd) {
+        let serials = reader
+            .entries(tiff, ifd)
+            .into_iter()
+            .filter(|entry| {
+                let tag = reader.u16(tiff, *entry).unwrap_or_default();
+                SERIAL_TAGS.contains(&tag) || tag == MAKER_NOTE_TAG
+            })
+            .filter_map(|entry| reader.value(tiff, entry))
+            .collect::<Vec<_>>();
+        for value in serials {
+            tiff[value].fill(0);
+        }
+    }
+}
+
+/// Whether the TIFF structure at the start of `tiff` has a GPS directory with any entries
+fn tiff_has_gps(tiff: &[u8]) -> bool {
+    let Some(reader) = Tiff::new(tiff) else {
+        return false;
+    };
+    reader
+        .first_ifd(tiff)
+        .and_then(|ifd| reader.sub_ifd(tiff, ifd, GPS_IFD_TAG))
+        .is_some_and(|gps_ifd| !reader.entries(tiff, gps_ifd).is_empty())
+}
+
+/// A TIFF structure holding only the copyright notice and orientation of `tiff`, or `None` if it has neither
+fn minimal_exif(tiff: &[u8]) -> Option<Vec<u8>> {
+    let reader = Tiff::new(tiff)?;
+    let first_ifd = reader.first_ifd(tiff)?;
+    let orientation = reader
+        .find(tiff, first_ifd, ORIENTATION_TAG)
+        .and_then(|entry| reader.u16(tiff, entry + 8));
+    let copyright = reader
+        .find(tiff, first_ifd, COPYRIGHT_TAG)
+        .and_then(|entry| reader.value(tiff, entry))
+        .map(|value| {
+            let mut copyright = tiff[value].to_vec();
+            while copyright.last() == Some(&0) {
+                copyright.pop();
+            }
+            copyright
+        })
+        .filter(|copyright| !copyright.is_empty());
+    if orientation.is_none() && copyright.is_none() {
+        return None;
+    }
+
+    let entries = orientation.is_some() as u16 + copyright.is_some() as u16;
+    let mut exif = b"MM\0*\0\0\0\x08".to_vec();
+    exif.extend(entries.to_be_bytes());
+    // Entries are sorted by tag
+    if let Some(orientation) = orientation {
+        exif.extend(ORIENTATION_TAG.to_be_bytes());
+        // A single SHORT
+        exif.extend(3u16.to_be_bytes());
+        exif.extend(1u32.to_be_bytes());
+        exif.extend(orientation.to_be_bytes());
+        exif.extend([0, 0]);
+    }
+    let mut values = Vec::new();
+    if let Some(copyright) = copyright {
+        let text = [copyright.as_slice(), &[0]].concat();
+        exif.extend(COPYRIGHT_TAG.to_be_bytes());
+        // ASCII
+        exif.extend(2u16.to_be_bytes());
+        exif.extend((text.len() as u32).to_be_bytes());
+        if text.len() <= 4 {
+            exif.extend(&text);
+            exif.extend(vec![0; 4 - text.len()]);
+        } else {
+            let offset = 8 + 2 + entries as usize * 12 + 4;
+            exif.extend((offset as u32).to_be_bytes());
+            values = text;
+        }
+    }
+    // No next directory
+    exif.extend([0, 0, 0, 0]);
+    exif.extend(values);
+    Some(exif)
+}
+
+/// XMP without the properties recording where a photo was taken or identifying the camera.
+/// `None` if it isn't UTF-8 and can't be edited.
+fn strip_xmp(xmp: &[u8]) -> Option<Vec<u8>> {
+    let xmp = std::str::from_utf8(xmp).ok()?;
+    Some(
+        XMP_PRIVATE_PROPERTIES
+            .replace_all(xmp, "")
+            .into_owned()
+            .into_bytes(),
+    )
+}
+
+/// Whether a file has EXIF or XMP anywhere in it
+fn contains_metadata(contents: &[u8]) -> bool {
+    find(contents, b"<x:xmpmeta").is_some() || tiff_offsets(contents).next().is_some()
+}
+
+/// Where each TIFF header in a file is. EXIF is stored as a TIFF structure in every format, with the first
+/// directory straight after the header.
+fn tiff_offsets(contents: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    contents
+        .windows(8)
+        .enumerate()
+        .filter(|(_, window)| *window == b"II*\0\x08\0\0\0" || *window == b"MM\0*\0\0\0\x08")
+        .map(|(offset, _)| offset)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::memory::MemoryStorage;
+    use crate::upload::storage::PutOptions;
+
+    /// A little endian TIFF structure with an orientation, copyright notice, body serial number and location
+    fn exif() -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+            [
+                tag.to_le_bytes().as_slice(),
+                &kind.to_le_bytes(),
+                &count.to_le_bytes(),
+                &value,
+            ]
+            .concat()
+        };
+        // IFD0 at 8 with 4 entries, the EXIF directory at 62 and GPS directory at 80 with 1 entry each,
+        // then the copyright notice at 98 and serial number at 115
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend(4u16.to_le_bytes());
+        tiff.extend(entry(ORIENTATION_TAG, 3, 1, [6, 0, 0, 0]));
+        tiff.extend(entry(COPYRIGHT_TAG, 2, 17, 98u32.to_le_bytes()));
+        tiff.extend(entry(EXIF_IFD_TAG, 4, 1, 62u32.to_le_bytes()));
+        tiff.extend(entry(GPS_IFD_TAG, 4, 1, 80u32.to_le_bytes()));
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(entry(0xA431, 2, 7, 115u32.to_le_bytes()));
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(1u16.to_le_bytes());
+        // GPSLatitudeRef
+        tiff.extend(entry(0x0001, 2, 2, *b"N\0\0\0"));
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(b"(c) Arran France\0");
+        tiff.extend(b"123456\0");
+        tiff
+    }
+
+    fn jpeg(exif: &[u8], xmp: &str) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        push_jpeg_segment(&mut jpeg, APP0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        push_jpeg_segment(&mut jpeg, APP1, &[EXIF_HEADER, exif].concat());
+        push_jpeg_segment(&mut jpeg, APP1, &[XMP_HEADER, xmp.as_bytes()].concat());
+        jpeg.extend([0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    const XMP: &str = r#"<x:xmpmeta><rdf:Description dc:format="image/jpeg" exif:GPSLatitude="51,30.6N"><exif:GPSLongitude>0,7.2W</exif:GPSLongitude><aux:SerialNumber>123456</aux:SerialNumber></rdf:Description></x:xmpmeta>"#;
+
+    #[test]
+    fn test_strip_gps() {
+        let original = jpeg(&exif(), XMP);
+        assert!(contains_gps(&original));
+
+        let scrubbed = scrub(&original, PrivacyPolicy::StripGps).unwrap();
+        assert!(!contains_gps(&scrubbed));
+        assert_eq!(find(&scrubbed, b"123456"), None);
+        assert!(find(&scrubbed, b"(c) Arran France").is_some());
+        assert!(find(&scrubbed, br#"dc:format="image/jpeg""#).is_some());
+        assert!(scrubbed.ends_with(&[0xFF, 0xDA, 0, 2, 0xFF, 0xD9]));
+
+        assert_eq!(scrub(&original, PrivacyPolicy::KeepAll).unwrap(), original);
+    }
+
+    #[test]
+    fn test_maker_notes_are_blanked() {
+        // IFD0 at 8 pointing to the EXIF directory at 26, which has a maker note at 44
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(EXIF_IFD_TAG.to_le_bytes());
+        tiff.extend(4u16.to_le_bytes());
+        tiff.extend(1u32.to_le_bytes());
+        tiff.extend(26u32.to_le_bytes());
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(1u16.to_le_bytes());
+        tiff.extend(MAKER_NOTE_TAG.to_le_bytes());
+        // UNDEFINED
+        tiff.extend(7u16.to_le_bytes());
+        tiff.extend(8u32.to_le_bytes());
+        tiff.extend(44u32.to_le_bytes());
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(b"SN998877");
+
+        let original = jpeg(&tiff, "");
+        assert!(find(&original, b"SN998877").is_some());
+        let scrubbed = scrub(&original, PrivacyPolicy::StripGps).unwrap();
+        assert_eq!(find(&scrubbed, b"SN998877"), None);
+    }
+
+    #[test]
+    fn test_formats_metadata_cant_be_removed_from() {
+        // An AVIF's `ftyp` box followed by EXIF
+        let mut avif = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf".to_vec();
+        avif.extend(exif());
+        assert_eq!(scrub(&avif, PrivacyPolicy::StripGps), None);
+        assert_eq!(
+            scrub(&avif, PrivacyPolicy::KeepAll).as_deref(),
+            Some(avif.as_slice())
+        );
+
+        let without_metadata = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+        assert_eq!(
+            scrub(without_metadata, PrivacyPolicy::StripGps).as_deref(),
+            Some(&without_metadata[..])
+        );
+    }
+
+    #[test]
+    fn test_strip_all_but_copyright() {
+        let original = jpeg(&exif(), XMP);
+        let scrubbed = scrub(&original, PrivacyPolicy::StripAllButCopyright).unwrap();
+        assert!(!contains_gps(&scrubbed));
+        assert_eq!(find(&scrubbed, b"<x:xmpmeta"), None);
+        assert_eq!(find(&scrubbed, b"123456"), None);
+        assert!(find(&scrubbed, b"(c) Arran France").is_some());
+        assert!(find(&scrubbed, b"JFIF").is_some());
+
+        let exif = split_jpeg(&scrubbed)
+            .0
+            .into_iter()
+            .find(|(marker, _)| *marker == APP1)
+            .map(|(_, payload)| payload[EXIF_HEADER.len()..].to_vec())
+            .unwrap();
+        let reader = Tiff::new(&exif).unwrap();
+        let orientation = reader.find(&exif, 8, ORIENTATION_TAG).unwrap();
+        assert_eq!(reader.u16(&exif, orientation + 8), Some(6));
+    }
+
+    #[test]
+    fn test_audit() {
+        let storage = MemoryStorage::new(String::new());
+        let options = PutOptions::new("image/jpeg");
+        let located = jpeg(&exif(), "");
+        storage
+            .put("images/located.jpg", &located, options)
+            .unwrap();
+        let scrubbed = scrub(&located, PrivacyPolicy::StripGps).unwrap();
+        storage
+            .put("images/scrubbed.jpg", &scrubbed, options)
+            .unwrap();
+        storage.put("other/located.jpg", &located, options).unwrap();
+
+        assert_eq!(
+            audit(&storage, "images/").unwrap(),
+            vec!["images/located.jpg"]
+        );
+    }
+}
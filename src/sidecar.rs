@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::image::crop::{CropHints, CropRectangle, FocalPoint};
+use crate::image::placeholder::PlaceholderKind;
+
+/// The name of the file in a folder describing every image in it
+pub const MANIFEST_FILE: &str = "rith.yaml";
+
+/// Settings and descriptions for a single image.
+///
+/// Read from a YAML file next to the image named after it (e.g. `photo.jpg.yaml`) and from the image's entry
+/// in the folder's manifest, `rith.yaml`. Values in the image's own file take precedence.
+/// ```yaml
+/// alt: A heron standing in a river
+/// caption: Early morning on the Test
+/// title: Heron
+/// credit: Arran France
+/// license: CC BY 4.0
+/// tags: [birds, rivers]
+/// focus: 0.5,0.3
+/// layout: sidebar
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sidecar {
+    /// A description of the image for people who can't see it
+    pub alt: Option<String>,
+    pub caption: Option<String>,
+    pub title: Option<String>,
+    /// Who made the image
+    pub credit: Option<String>,
+    pub license: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The point crops of the image are centred on
+    pub focus: Option<FocalPoint>,
+    /// The part of the image crops are taken from
+    pub crop: Option<CropRectangle>,
+    /// The name of the layout the image is rendered with, instead of the configured one
+    pub layout: Option<String>,
+    /// The placeholder shown whilst the image loads, instead of `--placeholder`
+    pub placeholder: Option<PlaceholderKind>,
+}
+
+impl Sidecar {
+    /// Where the sidecar for the image at `image_path` is
+    pub fn path_for(image_path: &Path) -> PathBuf {
+        let mut file_name = image_path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".yaml");
+        image_path.with_file_name(file_name)
+    }
+
+    /// Reads the sidecar for the image at `image_path` and its entry in the folder's `manifest`, if it has either
+    pub fn load(image_path: &Path, manifest: &Manifest) -> Result<Option<Self>> {
+        let own = read_yaml::<Self>(&Self::path_for(image_path))?;
+        let listed = image_path
+            .file_name()
+            .and_then(|name| manifest.0.get(name.to_str()?).cloned());
+        Ok(match (own, listed) {
+            (Some(own), Some(listed)) => Some(own.or(listed)),
+            (own, listed) => own.or(listed),
+        })
+    }
+
+    /// This sidecar, falling back to `other` for any value that isn't set
+    pub fn or(self, other: Sidecar) -> Self {
+        Self {
+            alt: self.alt.or(other.alt),
+            caption: self.caption.or(other.caption),
+            title: self.title.or(other.title),
+            credit: self.credit.or(other.credit),
+            license: self.license.or(other.license),
+            tags: if self.tags.is_empty() {
+                other.tags
+            } else {
+                self.tags
+            },
+            focus: self.focus.or(other.focus),
+            crop: self.crop.or(other.crop),
+            layout: self.layout.or(other.layout),
+            placeholder: self.placeholder.or(other.placeholder),
+        }
+    }
+
+    pub fn crop_hints(&self) -> CropHints {
+        CropHints {
+            focus: self.focus,
+            rectangle: self.crop,
+        }
+    }
+}
+
+/// The sidecars of every image in a folder, keyed by file name, read from its `rith.yaml`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Manifest(BTreeMap<String, Sidecar>);
+
+impl Manifest {
+    /// Reads the manifest of `directory`. A folder without one has an empty manifest.
+    pub fn load(directory: &Path) -> Result<Self> {
+        Ok(read_yaml(&directory.join(MANIFEST_FILE))?.unwrap_or_default())
+    }
+}
+
+/// Parses the YAML file at `path`, if it exists
+fn read_yaml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.to_string_lossy()))?;
+    let value = serde_norway::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}", path.to_string_lossy()))?;
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sidecar_takes_precedence_over_manifest() {
+        let directory = tempdir().unwrap();
+        let image = directory.path().join("heron.jpg");
+        write(
+            directory.path().join(MANIFEST_FILE),
+            "heron.jpg:\n  alt: A heron\n  credit: Arran France\n  tags: [birds]\nother.jpg:\n  alt: Something else\n",
+        )
+        .unwrap();
+        write(
+            Sidecar::path_for(&image),
+            "alt: A heron in a river\nfocus: 0.5,0.3\n",
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(directory.path()).unwrap();
+        let sidecar = Sidecar::load(&image, &manifest).unwrap().unwrap();
+        assert_eq!(sidecar.alt.as_deref(), Some("A heron in a river"));
+        assert_eq!(sidecar.credit.as_deref(), Some("Arran France"));
+        assert_eq!(sidecar.tags, vec!["birds"]);
+        assert_eq!(sidecar.focus, Some(FocalPoint { x: 0.5, y: 0.3 }));
+
+        assert_eq!(
+            Sidecar::load(&directory.path().join("missing.jpg"), &manifest).unwrap(),
+            None
+        );
+        assert_eq!(
+            Manifest::load(&directory.path().join("empty")).unwrap(),
+            Manifest::default()
+        );
+    }
+}
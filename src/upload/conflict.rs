@@ -0,0 +1,315 @@
+use super::keys::split_extension;
+use super::retry::with_retries;
+use super::storage::{content_etag, PutOptions, Storage};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// What to do when a different object already exists at a key being uploaded to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Stop uploading with an error
+    Fail,
+    /// Upload to a new key with a numbered suffix. e.g. `photo-320w-1.jpeg`
+    Rename,
+    /// Replace the existing object
+    Overwrite,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "rename" => Ok(ConflictPolicy::Rename),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            _ => Err(format!(
+                "{s} is not a conflict policy. Expected fail, rename or overwrite"
+            )),
+        }
+    }
+}
+
+/// What happened when a file was uploaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum UploadOutcome {
+    /// Nothing existed at the key
+    Written,
+    /// An identical object already existed at the key so nothing was written
+    Skipped,
+    /// A different object existed at the key and was replaced
+    Overwritten,
+    /// A different object existed at the intended key so the file was written to another key
+    Renamed { from: String },
+}
+
+/// Which keys were written and which were skipped during an upload
+#[derive(Debug, Default)]
+pub struct UploadReport {
+    outcomes: Mutex<Vec<(String, UploadOutcome)>>,
+}
+
+impl UploadReport {
+    pub fn record(&self, key: String, outcome: UploadOutcome) {
+        self.outcomes.lock().unwrap().push((key, outcome));
+    }
+
+    /// Every key uploaded to and what happened, sorted by key
+    pub fn outcomes(&self) -> Vec<(String, UploadOutcome)> {
+        let mut outcomes = self.outcomes.lock().unwrap().clone();
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+        outcomes
+    }
+
+    fn count(&self, predicate: impl Fn(&UploadOutcome) -> bool) -> usize {
+        self.outcomes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, outcome)| predicate(outcome))
+            .count()
+    }
+}
+
+impl fmt::Display for UploadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} written, {} skipped as identical, {} overwritten, {} renamed",
+            self.count(|o| *o == UploadOutcome::Written),
+            self.count(|o| *o == UploadOutcome::Skipped),
+            self.count(|o| *o == UploadOutcome::Overwritten),
+            self.count(|o| matches!(o, UploadOutcome::Renamed { .. })),
+        )
+    }
+}
+
+/// Finds the key `bytes` should be stored at and whether it needs writing.
+///
+/// Objects are identical when both their size and ETag (the MD5 of their contents) match.
+/// Objects whose storage doesn't know their ETag are compared by contents when their sizes match.
+pub fn resolve_key(
+    storage: &dyn Storage,
+    key: &str,
+    bytes: &[u8],
+    policy: ConflictPolicy,
+    retries: u32,
+) -> Result<(String, UploadOutcome)> {
+    let etag = content_etag(bytes);
+    let mut candidate = key.to_owned();
+    for attempt in 1.. {
+        let existing = with_retries(
+            retries,
+            |e| storage.is_transient(e),
+            || storage.head(&candidate),
+        )?;
+        let Some(existing) = existing else {
+            let outcome = if candidate == key {
+                UploadOutcome::Written
+            } else {
+                UploadOutcome::Renamed {
+                    from: key.to_owned(),
+                }
+            };
+            return Ok((candidate, outcome));
+        };
+
+        let identical = existing.size == bytes.len() as u64
+            && match &existing.etag {
+                Some(existing) => *existing == etag,
+                None => {
+                    with_retries(
+                        retries,
+                        |e| storage.is_transient(e),
+                        || storage.get(&candidate),
+                    )?
+                    .as_deref()
+                        == Some(bytes)
+                }
+            };
+        if identical {
+            return Ok((candidate, UploadOutcome::Skipped));
+        }
+
+        match policy {
+            ConflictPolicy::Fail => bail!(
+                "A different object already exists at {candidate}. Use --on-conflict rename or --on-conflict overwrite to upload anyway"
+            ),
+            ConflictPolicy::Overwrite => return Ok((candidate, UploadOutcome::Overwritten)),
+            ConflictPolicy::Rename => candidate = numbered_key(key, attempt),
+        }
+    }
+    unreachable!()
+}
+
+/// Uploads `bytes` to `key`, or to the key a conflict is resolved to by `policy`, unless an identical object is already there.
+///
+/// Keys nothing was found at are written with [`Storage::put_if_absent`]. If another upload writes to the key after it
+/// was checked, the key is resolved again rather than the other object being replaced.
+pub fn upload_object(
+    storage: &dyn Storage,
+    key: &str,
+    bytes: &[u8],
+    options: PutOptions,
+    policy: ConflictPolicy,
+    retries: u32,
+) -> Result<(String, UploadOutcome)> {
+    loop {
+        let (resolved, outcome) = resolve_key(storage, key, bytes, policy, retries)
+            .with_context(|| format!("Failed to check for an existing object at {key}"))?;
+        let written = match outcome {
+            UploadOutcome::Skipped => Ok(true),
+            UploadOutcome::Overwritten => with_retries(
+                retries,
+                |e| storage.is_transient(e),
+                || storage.put(&resolved, bytes, options),
+            )
+            .map(|()| true),
+            UploadOutcome::Written | UploadOutcome::Renamed { .. } => with_retries(
+                retries,
+                |e| storage.is_transient(e),
+                || storage.put_if_absent(&resolved, bytes, options),
+            ),
+        }
+        .with_context(|| format!("Failed to upload {resolved}"))?;
+        if written {
+            return Ok((resolved, outcome));
+        }
+        debug!(
+            "{resolved} was written by something else whilst uploading so is being checked again"
+        );
+    }
+}
+
+/// Inserts `-<number>` before the key's extension. e.g. `a/photo.jpeg` becomes `a/photo-1.jpeg`
+fn numbered_key(key: &str, number: usize) -> String {
+    let (stem, extension) = split_extension(key);
+    format!("{stem}-{number}{extension}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::local::LocalStorage;
+    use crate::upload::memory::MemoryStorage;
+    use crate::upload::storage::ObjectMetadata;
+
+    const KEY: &str = "images/2024/Jan/photo-320w.jpeg";
+
+    /// Writes another object to a key just before the first conditional write to it, as a concurrent upload might
+    #[derive(Default)]
+    struct RacedStorage {
+        storage: MemoryStorage,
+        raced: Mutex<bool>,
+    }
+
+    impl Storage for RacedStorage {
+        fn put(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<()> {
+            self.storage.put(key, bytes, options)
+        }
+
+        fn put_if_absent(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<bool> {
+            if !std::mem::replace(&mut *self.raced.lock().unwrap(), true) {
+                self.storage.put(key, b"another photo", options)?;
+            }
+            self.storage.put_if_absent(key, bytes, options)
+        }
+
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.storage.get(key)
+        }
+
+        fn head(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+            self.storage.head(key)
+        }
+
+        fn delete(&self, key: &str) -> Result<()> {
+            self.storage.delete(key)
+        }
+
+        fn list(&self, prefix: &str) -> Result<Vec<String>> {
+            self.storage.list(prefix)
+        }
+
+        fn public_url(&self, key: &str) -> String {
+            self.storage.public_url(key)
+        }
+    }
+
+    #[test]
+    fn test_objects_written_whilst_uploading_are_not_replaced() {
+        let storage = RacedStorage::default();
+        let options = PutOptions::new("image/jpeg");
+        assert!(upload_object(&storage, KEY, b"photo", options, ConflictPolicy::Fail, 0).is_err());
+
+        let storage = RacedStorage::default();
+        let uploaded =
+            upload_object(&storage, KEY, b"photo", options, ConflictPolicy::Rename, 0).unwrap();
+        assert_eq!(uploaded.0, "images/2024/Jan/photo-320w-1.jpeg");
+        assert_eq!(
+            storage.get(KEY).unwrap().as_deref(),
+            Some(&b"another photo"[..])
+        );
+        assert_eq!(
+            storage.get(&uploaded.0).unwrap().as_deref(),
+            Some(&b"photo"[..])
+        );
+    }
+
+    #[test]
+    fn test_identical_objects_are_skipped() {
+        let storage = MemoryStorage::default();
+        storage
+            .put(KEY, b"photo", PutOptions::new("image/jpeg"))
+            .unwrap();
+        let resolved = resolve_key(&storage, KEY, b"photo", ConflictPolicy::Fail, 0).unwrap();
+        assert_eq!(resolved, (KEY.to_owned(), UploadOutcome::Skipped));
+    }
+
+    #[test]
+    fn test_objects_without_an_etag_are_compared_by_contents() {
+        let directory = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(directory.path().to_path_buf(), "/".to_owned());
+        storage
+            .put(KEY, b"photo", PutOptions::new("image/jpeg"))
+            .unwrap();
+        let resolved = resolve_key(&storage, KEY, b"photo", ConflictPolicy::Fail, 0).unwrap();
+        assert_eq!(resolved, (KEY.to_owned(), UploadOutcome::Skipped));
+        // The same size but different contents
+        assert!(resolve_key(&storage, KEY, b"other", ConflictPolicy::Fail, 0).is_err());
+    }
+
+    #[test]
+    fn test_conflicts_fail() {
+        let storage = MemoryStorage::default();
+        storage
+            .put(KEY, b"another photo", PutOptions::new("image/jpeg"))
+            .unwrap();
+        assert!(resolve_key(&storage, KEY, b"photo", ConflictPolicy::Fail, 0).is_err());
+    }
+
+    #[test]
+    fn test_conflicts_are_renamed() {
+        let storage = MemoryStorage::default();
+        storage
+            .put(KEY, b"another photo", PutOptions::new("image/jpeg"))
+            .unwrap();
+        let resolved = resolve_key(&storage, KEY, b"photo", ConflictPolicy::Rename, 0).unwrap();
+        assert_eq!(
+            resolved,
+            (
+                "images/2024/Jan/photo-320w-1.jpeg".to_owned(),
+                UploadOutcome::Renamed {
+                    from: KEY.to_owned()
+                }
+            )
+        );
+    }
+}
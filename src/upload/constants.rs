@@ -1,4 +1,7 @@
-// TODO: Make this configuration
-pub const BUCKET_NAME: &str = "arranfrance.com";
-pub const REGION: &str = "eu-west-2";
-pub const WEB_PREFIX: &str = "https://files.arranfrance.com/";
+// Defaults used when no configuration overrides them. See [`crate::config::Config`]
+pub const DEFAULT_BUCKET_NAME: &str = "arranfrance.com";
+pub const DEFAULT_REGION: &str = "eu-west-2";
+pub const DEFAULT_WEB_PREFIX: &str = "https://files.arranfrance.com/";
+
+/// The `Cache-Control` header for objects whose key changes whenever their contents do
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
@@ -0,0 +1,76 @@
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The number of hex characters of the content hash included in a content hashed key
+const CONTENT_HASH_LENGTH: usize = 10;
+
+/// Splits a key into everything before the file's extension and the extension (including the `.`).
+/// e.g. `a/photo.jpeg` becomes (`a/photo`, `.jpeg`)
+pub fn split_extension(key: &str) -> (&str, &str) {
+    let file_start = key.rfind('/').map_or(0, |i| i + 1);
+    match key[file_start..].rfind('.') {
+        Some(dot) => key.split_at(file_start + dot),
+        None => (key, ""),
+    }
+}
+
+/// A short hex encoded hash of `bytes`. e.g. `1a2b3c4d5e`
+pub fn short_content_hash(bytes: &[u8]) -> String {
+    let mut hash = format!("{:x}", Sha256::digest(bytes));
+    hash.truncate(CONTENT_HASH_LENGTH);
+    hash
+}
+
+/// Inserts a short hash of `bytes` before the key's extension, so the key changes whenever the contents do.
+/// e.g. `a/photo-320w.jpeg` becomes `a/photo-320w.1a2b3c4d5e.jpeg`
+pub fn content_hashed_key(key: &str, bytes: &[u8]) -> String {
+    let (stem, extension) = split_extension(key);
+    format!("{stem}.{}{extension}", short_content_hash(bytes))
+}
+
+/// The keys handed out during a run, so two different files are never given the same key
+#[derive(Debug, Default)]
+pub struct KeyClaims(Mutex<HashMap<String, PathBuf>>);
+
+impl KeyClaims {
+    /// Records `key` as belonging to `path`, failing if it already belongs to another file
+    pub fn claim(&self, key: &str, path: &Path) -> Result<()> {
+        let mut claims = self.0.lock().unwrap();
+        match claims.get(key) {
+            Some(claimed_by) if claimed_by != path => bail!(
+                "Both {} and {} would be uploaded to {key}. Change the key template so that it gives every file a unique key",
+                claimed_by.to_string_lossy(),
+                path.to_string_lossy()
+            ),
+            Some(_) => Ok(()),
+            None => {
+                claims.insert(key.to_owned(), path.to_path_buf());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_extension() {
+        assert_eq!(split_extension("a.b/photo.jpeg"), ("a.b/photo", ".jpeg"));
+        assert_eq!(split_extension("a.b/photo"), ("a.b/photo", ""));
+    }
+
+    #[test]
+    fn test_content_hashed_key_changes_with_contents() {
+        let first = content_hashed_key("images/photo-320w.jpeg", b"first");
+        let second = content_hashed_key("images/photo-320w.jpeg", b"second");
+        assert!(first.starts_with("images/photo-320w."));
+        assert!(first.ends_with(".jpeg"));
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,183 @@
+use super::storage::{ObjectMetadata, PutOptions, Storage};
+use crate::image::image::MIME_TABLE;
+
+use anyhow::{bail, Context, Result};
+
+use std::fs::{create_dir_all, metadata, read_dir, remove_file, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Stores objects as files in a directory. e.g. a Hugo site's `static` directory
+pub struct LocalStorage {
+    root: PathBuf,
+    web_prefix: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf, web_prefix: String) -> Self {
+        Self { root, web_prefix }
+    }
+
+    /// The file `key` is stored in. Keys which are absolute or contain `..` would escape the root so are rejected.
+    fn path_for(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        let mut components = relative.components().peekable();
+        if components.peek().is_none() || !components.all(|c| matches!(c, Component::Normal(_))) {
+            bail!("{key} is not a valid key. Keys must be relative paths without `.` or `..`");
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl Storage for LocalStorage {
+    /// Files served from a directory get their headers from the web server, so `options` are ignored
+    fn put(&self, key: &str, bytes: &[u8], _options: PutOptions) -> Result<()> {
+        let path = self.path_for(key)?;
+        create_dir_all(path.with_file_name("")).with_context(|| {
+            format!(
+                "Failed to create directory for {}",
+                path.with_file_name("").to_string_lossy()
+            )
+        })?;
+        std::fs::write(&path, bytes)
+            .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+        Ok(())
+    }
+
+    fn put_if_absent(&self, key: &str, bytes: &[u8], _options: PutOptions) -> Result<bool> {
+        let path = self.path_for(key)?;
+        create_dir_all(path.with_file_name("")).with_context(|| {
+            format!(
+                "Failed to create directory for {}",
+                path.with_file_name("").to_string_lossy()
+            )
+        })?;
+        let mut file = match File::create_new(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to write {}", path.to_string_lossy()))
+            }
+        };
+        file.write_all(bytes)
+            .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+        Ok(true)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path_for(key)?;
+        match std::fs::read(&path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_range(&self, key: &str, length: u64) -> Result<Option<Vec<u8>>> {
+        let path = self.path_for(key)?;
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut bytes = Vec::new();
+        file.take(length).read_to_end(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+
+    /// The ETag isn't known without reading the whole file, so it's left out
+    fn head(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        let path = self.path_for(key)?;
+        match metadata(&path) {
+            Ok(metadata) => Ok(Some(ObjectMetadata {
+                size: metadata.len(),
+                content_type: path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .and_then(|e| MIME_TABLE.get(e.to_lowercase().as_str()))
+                    .map(|mime| (*mime).to_owned()),
+                cache_control: None,
+                etag: None,
+            })),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match remove_file(self.path_for(key)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        if self.root.exists() {
+            collect_keys(&self.root, &self.root, &mut keys)?;
+        }
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        [self.web_prefix.as_str(), key].join("")
+    }
+}
+
+/// Recursively gathers every file below `directory` as a key relative to `root`
+fn collect_keys(root: &Path, directory: &Path, keys: &mut Vec<String>) -> Result<()> {
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_keys(root, &path, keys)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let key = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            keys.push(key);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::storage::tests::check_storage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_local_storage() {
+        let directory = tempdir().unwrap();
+        let storage = LocalStorage::new(directory.path().join("static"), "/".to_owned());
+        check_storage(&storage);
+        assert!(directory.path().join("static/images/a.jpeg").exists());
+    }
+
+    #[test]
+    fn test_keys_outside_the_root_are_rejected() {
+        let directory = tempdir().unwrap();
+        let root = directory.path().join("static");
+        let storage = LocalStorage::new(root, "/".to_owned());
+        let outside = directory.path().join("outside.jpeg");
+        std::fs::write(&outside, b"keep").unwrap();
+        for key in [
+            "../outside.jpeg",
+            "images/../../outside.jpeg",
+            outside.to_str().unwrap(),
+            "./a.jpeg",
+            "",
+        ] {
+            assert!(storage
+                .put(key, b"replaced", PutOptions::new("image/jpeg"))
+                .is_err());
+            assert!(storage.delete(key).is_err());
+        }
+        assert_eq!(std::fs::read(&outside).unwrap(), b"keep");
+    }
+}
@@ -0,0 +1,116 @@
+use super::storage::{content_etag, ObjectMetadata, PutOptions, Storage};
+
+use anyhow::Result;
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Keeps objects in memory. Useful for tests and dry runs.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: Mutex<BTreeMap<String, StoredObject>>,
+    web_prefix: String,
+}
+
+#[derive(Debug, Clone)]
+struct StoredObject {
+    bytes: Vec<u8>,
+    content_type: String,
+    cache_control: Option<String>,
+}
+
+impl MemoryStorage {
+    pub fn new(web_prefix: String) -> Self {
+        Self {
+            objects: Mutex::default(),
+            web_prefix,
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn put(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<()> {
+        self.objects.lock().unwrap().insert(
+            key.to_owned(),
+            StoredObject {
+                bytes: bytes.to_vec(),
+                content_type: options.content_type.to_owned(),
+                cache_control: options.cache_control.map(str::to_owned),
+            },
+        );
+        Ok(())
+    }
+
+    fn put_if_absent(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<bool> {
+        match self.objects.lock().unwrap().entry(key.to_owned()) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(StoredObject {
+                    bytes: bytes.to_vec(),
+                    content_type: options.content_type.to_owned(),
+                    cache_control: options.cache_control.map(str::to_owned),
+                });
+                Ok(true)
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|object| object.bytes.clone()))
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|object| ObjectMetadata {
+                size: object.bytes.len() as u64,
+                content_type: Some(object.content_type.clone()),
+                cache_control: object.cache_control.clone(),
+                etag: Some(content_etag(&object.bytes)),
+            }))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        [self.web_prefix.as_str(), key].join("")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::storage::tests::check_storage;
+
+    #[test]
+    fn test_memory_storage() {
+        let storage = MemoryStorage::new("/".to_owned());
+        check_storage(&storage);
+        let stored = storage.head("images/a.jpeg").unwrap().unwrap();
+        assert_eq!(stored.cache_control.as_deref(), Some("public, max-age=60"));
+        assert_eq!(stored.etag, Some(content_etag(b"second")));
+    }
+}
@@ -1,2 +1,29 @@
+pub mod conflict;
 pub mod constants;
+pub mod keys;
+pub mod local;
+pub mod memory;
+pub mod object;
+pub mod retry;
+pub mod s3;
+pub mod storage;
+pub mod template;
 pub mod uploadable;
+
+use crate::config::{Backend, Config};
+use crate::error::AppError;
+
+use local::LocalStorage;
+use s3::S3Storage;
+use storage::Storage;
+
+/// Creates the storage backend selected by the configuration
+pub fn storage_from_config(config: &Config) -> Result<Box<dyn Storage>, AppError> {
+    Ok(match config.backend {
+        Backend::S3 => Box::new(S3Storage::new(config)?),
+        Backend::Local => Box::new(LocalStorage::new(
+            config.local_directory.clone(),
+            config.web_prefix.clone(),
+        )),
+    })
+}
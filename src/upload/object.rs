@@ -0,0 +1,129 @@
+use anyhow::{Context, Result};
+use chrono::prelude::*;
+
+use super::keys::{content_hashed_key, short_content_hash};
+use super::template::{KeyFields, Token};
+use crate::config::Config;
+use crate::image::generated_image::GeneratedImage;
+use crate::image::image::MIME_TABLE;
+use crate::image::image_info::ImageInfo;
+use crate::image::variant::Variant;
+use crate::options::Options;
+
+/// Everything about a run that the keys files are uploaded to can depend on
+pub struct KeyContext<'a> {
+    pub config: &'a Config,
+    /// The name of the set of images
+    pub name: &'a str,
+    /// The S3 directory passed on the command line
+    pub directory: Option<&'a str>,
+    /// When the upload started
+    pub now: DateTime<Local>,
+}
+
+impl<'a> KeyContext<'a> {
+    pub fn new(config: &'a Config, options: &'a Options, now: DateTime<Local>) -> Self {
+        Self {
+            config,
+            name: &options.name,
+            directory: options.s3_directory.as_deref(),
+            now,
+        }
+    }
+
+    /// Whether keys depend on the contents of the file being uploaded
+    pub fn needs_contents(&self) -> bool {
+        self.config.hashed_keys || self.config.key_template.uses(Token::Hash)
+    }
+}
+
+/// A file belonging to an image which is published.
+/// Both uploads and dry runs name files, choose keys and pick content types from this.
+pub struct ObjectFile<'a> {
+    pub image: &'a ImageInfo,
+    pub variant: Variant,
+    pub width: usize,
+    /// The lowercase extension of the file. e.g. `avif`
+    pub extension: String,
+}
+
+impl<'a> ObjectFile<'a> {
+    /// A resized or full size re-encoded image
+    pub fn generated(image: &'a ImageInfo, generated: &GeneratedImage) -> Self {
+        Self {
+            image,
+            variant: generated.variant,
+            width: generated.width,
+            extension: generated.format.extension().to_owned(),
+        }
+    }
+
+    /// The untouched input file, keeping its own extension
+    pub fn original(image: &'a ImageInfo) -> Self {
+        Self {
+            image,
+            variant: Variant::Original,
+            width: image.full_size_reencoded_image.width,
+            extension: image
+                .original_image
+                .path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The input file name without its extension
+    fn stem(&self) -> String {
+        self.image
+            .input_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// The name the file is published under. e.g. `photo-320w.avif` or `photo-copy-of-original.png`
+    pub fn file_name(&self) -> String {
+        self.variant.file_name(&self.stem(), &self.extension)
+    }
+
+    /// The `Content-Type` the file is served with
+    pub fn content_type(&self) -> Result<&'static str> {
+        MIME_TABLE
+            .get(self.extension.as_str())
+            .copied()
+            .with_context(|| {
+                format!(
+                    "Failed to get a matching mimetype when processing {}. {} does not map to a mime-type.",
+                    self.image.input_path.to_string_lossy(),
+                    self.extension
+                )
+            })
+    }
+
+    /// The path from a bucket's root to the file, laid out according to the configured key template.
+    /// `bytes` are the contents of the file and are only used when [`KeyContext::needs_contents`].
+    pub fn key(&self, bytes: Option<&[u8]>, context: &KeyContext) -> String {
+        let config = context.config;
+        let hash = bytes.map(short_content_hash);
+        let key = config.key_template.render(&KeyFields {
+            prefix: &config.key_prefix,
+            uploaded_at: context.now,
+            captured_on: self.image.exif.captured_on(),
+            directory: context.directory,
+            name: context.name,
+            stem: &self.stem(),
+            file: &self.file_name(),
+            variant: &self.variant.suffix(),
+            width: self.width,
+            format: &self.extension,
+            hash: hash.as_deref(),
+        });
+        match bytes {
+            Some(bytes) if config.hashed_keys && !config.key_template.uses(Token::Hash) => {
+                content_hashed_key(&key, bytes)
+            }
+            _ => key,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::Result;
+use log::warn;
+
+use std::thread::sleep;
+use std::time::Duration;
+
+/// The delay before the first retry. Each subsequent retry waits twice as long as the last, up to [`MAX_BACKOFF`].
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// The longest delay between retries, however many there are
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The delay before retry `attempt`, counting from 0
+fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF)
+}
+
+/// Runs `operation`, retrying up to `retries` times with exponential backoff whilst it fails with an error
+/// `is_transient` accepts
+pub fn with_retries<T>(
+    retries: u32,
+    is_transient: impl Fn(&anyhow::Error) -> bool,
+    mut operation: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries && is_transient(&e) => {
+                let backoff = backoff(attempt);
+                attempt += 1;
+                warn!(
+                    "Retrying in {:?} ({}/{}) after error: {:#}",
+                    backoff, attempt, retries, e
+                );
+                sleep(backoff);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_retries_transient_errors() {
+        let mut calls = 0;
+        let result = with_retries(
+            2,
+            |_| true,
+            || {
+                calls += 1;
+                if calls < 3 {
+                    Err(anyhow!("transient"))
+                } else {
+                    Ok(calls)
+                }
+            },
+        );
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        assert_eq!(backoff(0), INITIAL_BACKOFF);
+        assert_eq!(backoff(1), INITIAL_BACKOFF * 2);
+        for attempt in [7, 31, 32, 40, u32::MAX] {
+            assert_eq!(backoff(attempt), MAX_BACKOFF);
+        }
+
+        let mut calls = 0;
+        let result = with_retries(
+            40,
+            |_| true,
+            || {
+                calls += 1;
+                if calls < 2 {
+                    Err(anyhow!("transient"))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_does_not_retry_permanent_errors() {
+        let mut calls = 0;
+        let result: Result<()> = with_retries(
+            2,
+            |_| false,
+            || {
+                calls += 1;
+                Err(anyhow!("permanent"))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
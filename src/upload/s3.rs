@@ -0,0 +1,140 @@
+use super::storage::{ObjectMetadata, PutOptions, Storage};
+use crate::config::Config;
+use crate::error::AppError;
+
+use anyhow::Result;
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::Region;
+
+/// Stores objects in an S3 bucket
+pub struct S3Storage {
+    bucket: Box<Bucket>,
+    web_prefix: String,
+}
+
+impl S3Storage {
+    /// Connects to the bucket named in the configuration. Credentials are loaded from the environment.
+    pub fn new(config: &Config) -> Result<Self, AppError> {
+        let region = config
+            .region
+            .parse::<Region>()
+            .map_err(AppError::RegionParse)?;
+
+        // Loads from environment variables
+        let credentials = Credentials::default()?;
+        let bucket = Bucket::new(&config.bucket, region, credentials)?.with_path_style();
+        Ok(Self {
+            bucket,
+            web_prefix: config.web_prefix.to_owned(),
+        })
+    }
+}
+
+impl Storage for S3Storage {
+    fn put(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<()> {
+        match options.cache_control {
+            Some(cache_control) => {
+                let mut bucket = self.bucket.clone();
+                bucket.add_header("Cache-Control", cache_control);
+                bucket.put_object_with_content_type_blocking(key, bytes, options.content_type)?;
+            }
+            None => {
+                self.bucket.put_object_with_content_type_blocking(
+                    key,
+                    bytes,
+                    options.content_type,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sent with `If-None-Match: *`, which S3 answers with 412 Precondition Failed if the object exists,
+    /// or 409 Conflict if another conditional write to the key is in progress
+    fn put_if_absent(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<bool> {
+        let mut bucket = self.bucket.clone();
+        bucket.add_header("If-None-Match", "*");
+        if let Some(cache_control) = options.cache_control {
+            bucket.add_header("Cache-Control", cache_control);
+        }
+        match bucket.put_object_with_content_type_blocking(key, bytes, options.content_type) {
+            Err(S3Error::HttpFailWithBody(409 | 412, _)) => Ok(false),
+            Ok(response) if matches!(response.status_code(), 409 | 412) => Ok(false),
+            Ok(_) => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.bucket.get_object_blocking(key) {
+            Err(S3Error::HttpFailWithBody(404, _)) => Ok(None),
+            Ok(response) if response.status_code() == 404 => Ok(None),
+            Ok(response) => Ok(Some(response.bytes().to_vec())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get_range(&self, key: &str, length: u64) -> Result<Option<Vec<u8>>> {
+        if length == 0 {
+            return Ok(self.head(key)?.map(|_| Vec::new()));
+        }
+        match self
+            .bucket
+            .get_object_range_blocking(key, 0, Some(length - 1))
+        {
+            Err(S3Error::HttpFailWithBody(404, _)) => Ok(None),
+            Ok(response) if response.status_code() == 404 => Ok(None),
+            // The object is empty so no range of it can be satisfied
+            Err(S3Error::HttpFailWithBody(416, _)) => Ok(Some(Vec::new())),
+            Ok(response) if response.status_code() == 416 => Ok(Some(Vec::new())),
+            Ok(response) => Ok(Some(response.bytes().to_vec())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn head(&self, key: &str) -> Result<Option<ObjectMetadata>> {
+        match self.bucket.head_object_blocking(key) {
+            Ok((_, 404)) | Err(S3Error::HttpFailWithBody(404, _)) => Ok(None),
+            Ok((head, _)) => Ok(Some(ObjectMetadata {
+                size: head.content_length.unwrap_or_default().try_into()?,
+                content_type: head.content_type,
+                cache_control: head.cache_control,
+                etag: head.e_tag.map(|etag| etag.trim_matches('"').to_owned()),
+            })),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.bucket.delete_object_blocking(key)?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .bucket
+            .list_blocking(prefix.to_owned(), None)?
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| object.key)
+            .collect())
+    }
+
+    fn public_url(&self, key: &str) -> String {
+        [self.web_prefix.as_str(), key].join("")
+    }
+
+    fn is_transient(&self, error: &anyhow::Error) -> bool {
+        match error.downcast_ref::<S3Error>() {
+            // Throttling, timeouts and server errors
+            Some(S3Error::HttpFailWithBody(status, _)) => {
+                *status == 408 || *status == 429 || *status >= 500
+            }
+            // Connection failures
+            Some(S3Error::Io(_)) | Some(S3Error::Reqwest(_)) => true,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::Result;
+use md5::{Digest, Md5};
+
+/// What is known about an object that has already been stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMetadata {
+    /// The size of the object in bytes
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>,
+    /// The MD5 of the object's contents, hex encoded without quotes. See [`content_etag`]
+    pub etag: Option<String>,
+}
+
+/// The ETag S3 gives an object uploaded in a single part: the hex encoded MD5 of its contents
+pub fn content_etag(bytes: &[u8]) -> String {
+    format!("{:x}", Md5::digest(bytes))
+}
+
+/// The headers an object is stored with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PutOptions<'a> {
+    pub content_type: &'a str,
+    /// e.g. `public, max-age=31536000, immutable`
+    pub cache_control: Option<&'a str>,
+}
+
+impl<'a> PutOptions<'a> {
+    pub fn new(content_type: &'a str) -> Self {
+        Self {
+            content_type,
+            cache_control: None,
+        }
+    }
+}
+
+/// Somewhere images can be published to.
+///
+/// Keys are `/` separated paths relative to the root of the storage. e.g. `images/2024/Jan/photo-320w.jpeg`
+pub trait Storage: Send + Sync {
+    /// Writes `bytes` to `key`, replacing any existing object
+    fn put(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<()>;
+
+    /// Writes `bytes` to `key` only if there is no object at `key`, returning whether it was written.
+    /// The check and the write are a single operation, so an object written by someone else in between is never replaced.
+    fn put_if_absent(&self, key: &str, bytes: &[u8], options: PutOptions) -> Result<bool>;
+
+    /// Downloads the contents of the object at `key`, or `None` if there is no object
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Downloads at most the first `length` bytes of the object at `key`, or `None` if there is no object
+    fn get_range(&self, key: &str, length: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(key)?.map(|mut bytes| {
+            bytes.truncate(length.try_into().unwrap_or(usize::MAX));
+            bytes
+        }))
+    }
+
+    /// Gets the metadata for the object at `key`, or `None` if there is no object
+    fn head(&self, key: &str) -> Result<Option<ObjectMetadata>>;
+
+    /// Removes the object at `key`. Removing an object that doesn't exist is not an error.
+    fn delete(&self, key: &str) -> Result<()>;
+
+    /// Lists every key that starts with `prefix`
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+
+    /// The URL the object at `key` is publicly served from
+    fn public_url(&self, key: &str) -> String;
+
+    /// Whether an error returned by this storage is likely to succeed if the operation is retried
+    fn is_transient(&self, _error: &anyhow::Error) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Checks the behaviour every [`Storage`] must have, leaving `images/a.jpeg` stored with the contents `second`
+    pub(crate) fn check_storage(storage: &dyn Storage) {
+        assert_eq!(storage.head("images/a.jpeg").unwrap(), None);
+        assert_eq!(storage.get("images/a.jpeg").unwrap(), None);
+        assert!(storage.list("").unwrap().is_empty());
+
+        let options = PutOptions {
+            content_type: "image/jpeg",
+            cache_control: Some("public, max-age=60"),
+        };
+        assert!(storage
+            .put_if_absent("images/a.jpeg", b"first", options)
+            .unwrap());
+        assert!(!storage
+            .put_if_absent("images/a.jpeg", b"other", options)
+            .unwrap());
+        assert_eq!(
+            storage.get("images/a.jpeg").unwrap().as_deref(),
+            Some(&b"first"[..])
+        );
+        storage.put("images/a.jpeg", b"second", options).unwrap();
+        storage
+            .put("images/b.webp", b"b", PutOptions::new("image/webp"))
+            .unwrap();
+        storage.put("other/c.jpeg", b"c", options).unwrap();
+
+        assert_eq!(
+            storage.get("images/a.jpeg").unwrap().as_deref(),
+            Some(&b"second"[..])
+        );
+        assert_eq!(
+            storage.get_range("images/a.jpeg", 3).unwrap().as_deref(),
+            Some(&b"sec"[..])
+        );
+        assert_eq!(
+            storage.get_range("images/a.jpeg", 100).unwrap().as_deref(),
+            Some(&b"second"[..])
+        );
+        assert_eq!(storage.get_range("images/none.jpeg", 3).unwrap(), None);
+        let head = storage.head("images/a.jpeg").unwrap().unwrap();
+        assert_eq!(head.size, 6);
+        assert_eq!(head.content_type.as_deref(), Some("image/jpeg"));
+        if let Some(etag) = head.etag {
+            assert_eq!(etag, content_etag(b"second"));
+        }
+
+        assert_eq!(
+            storage.list("images/").unwrap(),
+            ["images/a.jpeg", "images/b.webp"]
+        );
+        storage.delete("images/b.webp").unwrap();
+        // Deleting something which doesn't exist isn't an error
+        storage.delete("images/b.webp").unwrap();
+        assert_eq!(storage.list("images/").unwrap(), ["images/a.jpeg"]);
+        assert_eq!(storage.head("images/b.webp").unwrap(), None);
+
+        assert_eq!(storage.public_url("images/a.jpeg"), "/images/a.jpeg");
+        storage.delete("other/c.jpeg").unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::constants::MONTH_NAMES;
+
+/// The layout keys were uploaded with before templates were configurable
+pub const DEFAULT_KEY_TEMPLATE: &str = "{prefix}/{year}/{month_name}/{directory}/{file}";
+
+/// A value which can be substituted into a [`KeyTemplate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    /// The configured `key_prefix`. e.g. `images`
+    Prefix,
+    /// The year of the upload. e.g. `2024`
+    Year,
+    /// The zero-padded month of the upload. e.g. `03`
+    Month,
+    /// The abbreviated month name of the upload. e.g. `Mar`
+    MonthName,
+    /// The S3 directory passed on the command line, if any
+    Directory,
+    /// The name of the set of images
+    Name,
+    /// The input file name without its extension
+    Stem,
+    /// The generated file's name. e.g. `photo-320w.jpeg`
+    File,
+    /// Which version of the image the file is. e.g. `320w`, `original` or `copy-of-original`
+    Variant,
+    /// The width of the file in pixels
+    Width,
+    /// The extension of the file's format. e.g. `avif`
+    Format,
+    /// A short hash of the file's contents
+    Hash,
+    /// The date the photo was taken from its EXIF data, falling back to the upload date. e.g. `2024-03-09`
+    ExifDate,
+}
+
+impl Token {
+    const ALL: &'static [(&'static str, Token)] = &[
+        ("prefix", Token::Prefix),
+        ("year", Token::Year),
+        ("month", Token::Month),
+        ("month_name", Token::MonthName),
+        ("directory", Token::Directory),
+        ("name", Token::Name),
+        ("stem", Token::Stem),
+        ("file", Token::File),
+        ("variant", Token::Variant),
+        ("width", Token::Width),
+        ("format", Token::Format),
+        ("hash", Token::Hash),
+        ("exif_date", Token::ExifDate),
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(token_name, _)| *token_name == name)
+            .map(|(_, token)| *token)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Token(Token),
+}
+
+/// The values a [`KeyTemplate`] is rendered with for a single file
+#[derive(Debug, Clone)]
+pub struct KeyFields<'a> {
+    pub prefix: &'a str,
+    pub uploaded_at: DateTime<Local>,
+    pub captured_on: Option<NaiveDate>,
+    pub directory: Option<&'a str>,
+    pub name: &'a str,
+    pub stem: &'a str,
+    pub file: &'a str,
+    pub variant: &'a str,
+    pub width: usize,
+    pub format: &'a str,
+    /// Only required when the template uses `{hash}`
+    pub hash: Option<&'a str>,
+}
+
+/// A layout for object keys. e.g. `{prefix}/{year}/{month_name}/{directory}/{file}`
+///
+/// Empty path segments are removed when rendering, so an unset `{directory}` doesn't leave a `//` in the key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl KeyTemplate {
+    /// Whether `token` appears in the template
+    pub fn uses(&self, token: Token) -> bool {
+        self.segments.contains(&Segment::Token(token))
+    }
+
+    pub fn render(&self, fields: &KeyFields) -> String {
+        let rendered = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Token(token) => Self::value(*token, fields),
+            })
+            .collect::<String>();
+        rendered
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn value(token: Token, fields: &KeyFields) -> String {
+        match token {
+            Token::Prefix => fields.prefix.trim_matches('/').to_owned(),
+            Token::Year => fields.uploaded_at.year().to_string(),
+            Token::Month => format!("{:02}", fields.uploaded_at.month()),
+            Token::MonthName => MONTH_NAMES[fields.uploaded_at.month0() as usize].to_owned(),
+            Token::Directory => fields.directory.unwrap_or_default().to_owned(),
+            Token::Name => fields.name.replace(' ', "-"),
+            Token::Stem => fields.stem.replace(' ', "-"),
+            Token::File => fields.file.replace(' ', "-"),
+            Token::Variant => fields.variant.to_owned(),
+            Token::Width => fields.width.to_string(),
+            Token::Format => fields.format.to_owned(),
+            Token::Hash => fields.hash.unwrap_or_default().to_owned(),
+            Token::ExifDate => fields
+                .captured_on
+                .unwrap_or_else(|| fields.uploaded_at.date_naive())
+                .format("%Y-%m-%d")
+                .to_string(),
+        }
+    }
+
+    /// Checks every file of every image gets its own key.
+    /// Files of different images are told apart by `{file}`, `{stem}` or `{hash}`,
+    /// and the files of a single image by `{file}`, `{hash}` or `{variant}` together with `{format}`.
+    fn validate(&self) -> Result<(), String> {
+        let identifies_image =
+            self.uses(Token::File) || self.uses(Token::Stem) || self.uses(Token::Hash);
+        let identifies_variant = self.uses(Token::File)
+            || self.uses(Token::Hash)
+            || (self.uses(Token::Variant) && self.uses(Token::Format));
+        if !identifies_image {
+            return Err(format!(
+                "Key template {} would give different images the same key. It must include {{file}}, {{stem}} or {{hash}}",
+                self.template
+            ));
+        }
+        if !identifies_variant {
+            return Err(format!(
+                "Key template {} would give different sizes or formats of an image the same key. It must include {{file}}, {{hash}} or both {{variant}} and {{format}}",
+                self.template
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for KeyTemplate {
+    fn default() -> Self {
+        DEFAULT_KEY_TEMPLATE
+            .parse()
+            .expect("The default key template is valid")
+    }
+}
+
+impl FromStr for KeyTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(start) if rest[start..].starts_with('{') => {
+                    if start > 0 {
+                        segments.push(Segment::Literal(rest[..start].to_owned()));
+                    }
+                    let end = rest[start..]
+                        .find('}')
+                        .map(|end| start + end)
+                        .ok_or_else(|| format!("Key template {s} has an unclosed {{"))?;
+                    let name = &rest[start + 1..end];
+                    let token = Token::from_name(name).ok_or_else(|| {
+                        let names = Token::ALL
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!("Key template {s} uses unknown token {{{name}}}. Expected one of {names}")
+                    })?;
+                    segments.push(Segment::Token(token));
+                    rest = &rest[end + 1..];
+                }
+                Some(_) => return Err(format!("Key template {s} has an unopened }}")),
+                None => {
+                    segments.push(Segment::Literal(rest.to_owned()));
+                    rest = "";
+                }
+            }
+        }
+        let template = Self {
+            template: s.to_owned(),
+            segments,
+        };
+        template.validate()?;
+        Ok(template)
+    }
+}
+
+impl TryFrom<String> for KeyTemplate {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<KeyTemplate> for String {
+    fn from(template: KeyTemplate) -> Self {
+        template.template
+    }
+}
+
+impl fmt::Display for KeyTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(directory: Option<&'static str>) -> KeyFields<'static> {
+        KeyFields {
+            prefix: "images",
+            uploaded_at: Local.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap(),
+            captured_on: NaiveDate::from_ymd_opt(2023, 12, 25),
+            directory,
+            name: "Holiday",
+            stem: "IMG 0001",
+            file: "IMG 0001-320w.avif",
+            variant: "320w",
+            width: 320,
+            format: "avif",
+            hash: Some("1a2b3c4d5e"),
+        }
+    }
+
+    #[test]
+    fn test_default_template_matches_previous_layout() {
+        let template = KeyTemplate::default();
+        assert_eq!(
+            template.render(&fields(Some("trips"))),
+            "images/2024/Mar/trips/IMG-0001-320w.avif"
+        );
+        assert_eq!(
+            template.render(&fields(None)),
+            "images/2024/Mar/IMG-0001-320w.avif"
+        );
+    }
+
+    #[test]
+    fn test_render_every_token() {
+        let template: KeyTemplate =
+            "{name}/{exif_date}/{month}/{stem}.{hash}-{variant}-{width}.{format}"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            template.render(&fields(None)),
+            "Holiday/2023-12-25/03/IMG-0001.1a2b3c4d5e-320w-320.avif"
+        );
+    }
+
+    #[test]
+    fn test_invalid_templates_are_rejected() {
+        assert!("{year}/{stem}.{unknown}".parse::<KeyTemplate>().is_err());
+        assert!("{year}/{stem".parse::<KeyTemplate>().is_err());
+        // Every size of an image would share a key
+        assert!("{year}/{stem}.{format}".parse::<KeyTemplate>().is_err());
+        // Every image would share a key
+        assert!("{year}/{variant}.{format}".parse::<KeyTemplate>().is_err());
+    }
+}